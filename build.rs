@@ -63,5 +63,22 @@ fn build_scss(paths: Paths) -> anyhow::Result<()> {
         .write_all(&output_content)
         .context("Failed to write compiled CSS to output")?;
 
+    write_precompressed(&out_dir.join("style.css"), &output_content)
+        .context("Failed to write precompressed CSS")?;
+
+    Ok(())
+}
+
+/// Writes gzip & brotli variants of a static asset alongside it (as `.gz` & `.br`), so they
+/// can be served without compressing the same bytes on every request.
+fn write_precompressed(path: &Path, content: &[u8]) -> anyhow::Result<()> {
+    let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+    gzip.write_all(content)?;
+    std::fs::write(path.with_extension("css.gz"), gzip.finish()?)?;
+
+    let mut brotli = brotli::CompressorWriter::new(Vec::new(), 4096, 11, 22);
+    brotli.write_all(content)?;
+    std::fs::write(path.with_extension("css.br"), brotli.into_inner())?;
+
     Ok(())
 }
@@ -82,6 +82,10 @@
             partitionType = "count";
           });
           formatting = treefmt.config.build.check self;
+          highlighters = pkgs.runCommand "rgit-check-highlighters" { } ''
+            ${rgit}/bin/rgit check-highlighters
+            touch $out
+          '';
         };
 
         formatter = treefmt.config.build.wrapper;
@@ -121,6 +125,16 @@
                 description = "Timeout for incoming HTTP requests";
                 type = types.str;
               };
+              streamingRequestTimeout = mkOption {
+                default = null;
+                description = "Timeout for clones and snapshot downloads, unlimited if null";
+                type = types.nullOr types.str;
+              };
+              basePath = mkOption {
+                default = null;
+                description = "Path rgit is served under behind a reverse proxy, served from the root if null";
+                type = types.nullOr types.str;
+              };
               package = mkOption {
                 default = rgit;
                 description = "rgit package to use";
@@ -144,7 +158,7 @@
                 path = [ pkgs.git ];
                 serviceConfig = {
                   Type = "exec";
-                  ExecStart = "${cfg.package}/bin/rgit --request-timeout ${cfg.requestTimeout} --db-store ${cfg.dbStorePath} ${cfg.bindAddress} ${cfg.repositoryStorePath}";
+                  ExecStart = "${cfg.package}/bin/rgit --request-timeout ${cfg.requestTimeout} ${optionalString (cfg.streamingRequestTimeout != null) "--streaming-request-timeout ${cfg.streamingRequestTimeout}"} ${optionalString (cfg.basePath != null) "--base-path ${cfg.basePath}"} --db-store ${cfg.dbStorePath} ${cfg.bindAddress} ${cfg.repositoryStorePath}";
                   Restart = "on-failure";
 
                   User = "rgit";
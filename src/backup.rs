@@ -0,0 +1,204 @@
+//! The `export` and `import` subcommands, which snapshot the index into a tarball and restore
+//! it elsewhere so large instances can be migrated between hosts without a full reindex.
+//!
+//! Exports are taken from a `RocksDB` checkpoint, so they're consistent, but the database can't
+//! be open elsewhere whilst exporting or importing, so rgit has to be stopped first.
+//!
+//! The tarball contains a `schema_version` file, followed by the checkpoint itself under
+//! `db/`. Imports are refused if the schema version doesn't match this build of rgit, since it'd
+//! be thrown away and rebuilt on startup anyway.
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use rocksdb::{checkpoint::Checkpoint, Options};
+
+use crate::database::schema::SCHEMA_VERSION;
+
+const SCHEMA_VERSION_ENTRY: &str = "schema_version";
+const DB_PREFIX: &str = "db";
+
+#[derive(clap::Args, Debug)]
+pub struct ExportArgs {
+    /// Path to the database to export, as given to rgit via `--db-store`
+    #[clap(short, long)]
+    db_store: PathBuf,
+    /// Path to write the snapshot tarball to
+    #[clap(short, long)]
+    out: PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ImportArgs {
+    /// Path to the snapshot tarball written by `rgit export`
+    input: PathBuf,
+    /// Path to restore the database to, as will be given to rgit via `--db-store`
+    #[clap(short, long)]
+    db_store: PathBuf,
+    /// Replace the database at `--db-store` if there's already one there
+    #[clap(long)]
+    force: bool,
+}
+
+pub fn export(args: &ExportArgs) -> anyhow::Result<()> {
+    let column_families = rocksdb::DB::list_cf(&Options::default(), &args.db_store)
+        .context("Failed to read database, is the path correct?")?;
+    let db = rocksdb::DB::open_cf(&Options::default(), &args.db_store, column_families)
+        .context("Failed to open database, rgit must be stopped before exporting")?;
+
+    let schema_version = db
+        .get(SCHEMA_VERSION_ENTRY)?
+        .context("Database has no schema version, has it been indexed?")?;
+
+    let checkpoint_path = sibling_path(&args.out, "checkpoint");
+    if checkpoint_path.exists() {
+        bail!(
+            "{} already exists, remove it and try again",
+            checkpoint_path.display()
+        );
+    }
+
+    Checkpoint::new(&db)?
+        .create_checkpoint(&checkpoint_path)
+        .context("Failed to create checkpoint")?;
+    drop(db);
+
+    let res = write_tarball(&args.out, &schema_version, &checkpoint_path);
+
+    if let Err(error) = std::fs::remove_dir_all(&checkpoint_path) {
+        eprintln!(
+            "Failed to remove checkpoint at {}: {error}",
+            checkpoint_path.display()
+        );
+    }
+
+    res?;
+
+    println!(
+        "Exported schema version {} to {}",
+        String::from_utf8_lossy(&schema_version),
+        args.out.display()
+    );
+
+    Ok(())
+}
+
+fn write_tarball(out: &Path, schema_version: &[u8], checkpoint: &Path) -> anyhow::Result<()> {
+    let file = File::create(out).with_context(|| format!("Failed to create {}", out.display()))?;
+    let mut builder = tar::Builder::new(BufWriter::new(file));
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(schema_version.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, SCHEMA_VERSION_ENTRY, schema_version)?;
+
+    builder.append_dir_all(DB_PREFIX, checkpoint)?;
+    builder.into_inner()?.into_inner()?.sync_all()?;
+
+    Ok(())
+}
+
+pub fn import(args: &ImportArgs) -> anyhow::Result<()> {
+    if args.db_store.exists() && !args.force {
+        bail!(
+            "{} already exists, pass --force to replace it",
+            args.db_store.display()
+        );
+    }
+
+    let staging_path = sibling_path(&args.db_store, "import");
+    if staging_path.exists() {
+        std::fs::remove_dir_all(&staging_path)?;
+    }
+
+    let file = File::open(&args.input)
+        .with_context(|| format!("Failed to open {}", args.input.display()))?;
+    let mut archive = tar::Archive::new(BufReader::new(file));
+    let mut entries = archive.entries()?;
+
+    let mut first = entries.next().context("Snapshot is empty")??;
+    if first.path()?.as_os_str() != SCHEMA_VERSION_ENTRY {
+        bail!("Snapshot doesn't start with a schema version, was it written by `rgit export`?");
+    }
+
+    let mut schema_version = String::new();
+    first.read_to_string(&mut schema_version)?;
+    if schema_version != SCHEMA_VERSION {
+        bail!(
+            "Snapshot has schema version {schema_version} but this build of rgit expects \
+             {SCHEMA_VERSION}, it would be rebuilt from scratch on startup anyway"
+        );
+    }
+
+    std::fs::create_dir_all(&staging_path)?;
+
+    for entry in entries {
+        let mut entry = entry?;
+        if !entry.path()?.starts_with(DB_PREFIX) {
+            bail!("Unexpected entry {} in snapshot", entry.path()?.display());
+        }
+
+        // `unpack_in` refuses paths that would escape the staging directory
+        entry.unpack_in(&staging_path)?;
+    }
+
+    // the existing database is moved aside rather than deleted, so it can be put back if the
+    // imported one can't be moved into place
+    let old_path = sibling_path(&args.db_store, "old");
+    let replacing = args.db_store.exists();
+    if replacing {
+        if old_path.exists() {
+            std::fs::remove_dir_all(&old_path)?;
+        }
+
+        std::fs::rename(&args.db_store, &old_path).with_context(|| {
+            format!(
+                "Failed to move existing database aside to {}",
+                old_path.display()
+            )
+        })?;
+    }
+
+    if let Err(error) = std::fs::rename(staging_path.join(DB_PREFIX), &args.db_store) {
+        if replacing {
+            std::fs::rename(&old_path, &args.db_store).with_context(|| {
+                format!(
+                    "Failed to restore existing database from {}",
+                    old_path.display()
+                )
+            })?;
+        }
+
+        return Err(error).with_context(|| {
+            format!(
+                "Failed to move imported database to {}",
+                args.db_store.display()
+            )
+        });
+    }
+
+    if replacing {
+        std::fs::remove_dir_all(&old_path)?;
+    }
+    std::fs::remove_dir_all(&staging_path)?;
+
+    println!(
+        "Imported schema version {schema_version} to {}",
+        args.db_store.display()
+    );
+
+    Ok(())
+}
+
+/// A path next to `path` to stage files in, ie. `snapshot.tar.checkpoint`.
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    path.with_file_name(name)
+}
@@ -2,32 +2,210 @@ use std::{
     collections::HashSet,
     ffi::OsStr,
     fmt::Debug,
-    path::{Path, PathBuf},
-    sync::Arc,
+    panic::AssertUnwindSafe,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
 };
 
 use anyhow::Context;
-use gix::{bstr::ByteSlice, refs::Category, Reference};
+use gix::{bstr::ByteSlice, objs::CommitRef, refs::Category, Reference};
+use globset::{Glob, GlobSet, GlobSetBuilder};
 use itertools::Itertools;
 use rocksdb::WriteBatch;
+use serde::Serialize;
 use time::{OffsetDateTime, UtcOffset};
 use tracing::{error, info, info_span, instrument, warn};
 
-use crate::database::schema::{
-    commit::Commit,
-    repository::{ArchivedRepository, Repository, RepositoryId},
-    tag::{Tag, TagTree},
+use crate::{
+    database::{
+        languages,
+        schema::{
+            commit::Commit,
+            repository::{ArchivedRepository, Repository, RepositoryId},
+            stats::PendingStats,
+            tag::{Tag, TagTree},
+        },
+    },
+    git::repair_commit_signatures,
 };
 
-pub fn run(scan_path: &Path, db: &Arc<rocksdb::DB>) {
+/// Serialises index runs, a full scan and a single repository reindex writing to the same
+/// branch at the same time would result in duplicated commits.
+static INDEX_LOCK: Mutex<()> = Mutex::new(());
+
+/// Settings for the indexer, built once from the command line arguments.
+#[derive(Debug, Clone)]
+pub struct IndexerConfig {
+    /// Whether newly indexed repositories get an ID derived from their path rather than a random
+    /// one, set via `--deterministic-repository-ids`
+    pub deterministic_repository_ids: bool,
+    /// Number of commits written to the database in each batch, set via `--index-batch-size`
+    pub commit_batch_size: usize,
+    /// Whether commit batches are written through the write-ahead log, set via `--index-wal`
+    pub wal: bool,
+    /// Maximum number of repositories discovered under the scan path, set via `--max-repos`
+    pub max_repositories: usize,
+    /// Maximum number of directories deep repositories are looked for under the scan path, set
+    /// via `--max-scan-depth`
+    pub max_scan_depth: usize,
+    /// Patterns matching paths, relative to the scan path, of repositories that shouldn't be
+    /// indexed or served, set via `--exclude`
+    pub excluded: GlobSet,
+    /// Bytes of decompressed objects kept around per repository whilst indexing, set via
+    /// `--object-cache-size`
+    pub object_cache_size: usize,
+}
+
+impl Default for IndexerConfig {
+    fn default() -> Self {
+        Self {
+            deterministic_repository_ids: false,
+            commit_batch_size: 250,
+            wal: false,
+            max_repositories: usize::MAX,
+            max_scan_depth: usize::MAX,
+            excluded: GlobSet::empty(),
+            object_cache_size: 10 * 1024 * 1024,
+        }
+    }
+}
+
+impl IndexerConfig {
+    /// Whether the repository at the given path, relative to the scan path, has been excluded
+    /// with `--exclude`.
+    pub fn is_excluded(&self, relative_path: &Path) -> bool {
+        self.excluded.is_match(relative_path)
+    }
+
+    /// Whether the repository at `relative` to the scan path is one a full scan would find, so
+    /// reindexing a single repository can't index anything [`run`] wouldn't.
+    pub fn is_discoverable(&self, scan_path: &Path, relative: &Path) -> bool {
+        let depth = relative.components().count();
+        if depth == 0
+            || depth > self.max_scan_depth
+            || !relative
+                .components()
+                .all(|c| matches!(c, Component::Normal(_)))
+            || self.is_excluded(relative)
+        {
+            return false;
+        }
+
+        // the scan doesn't descend into excluded directories, nor into other repositories
+        let ancestors_searched = relative
+            .ancestors()
+            .skip(1)
+            .filter(|v| !v.as_os_str().is_empty())
+            .all(|ancestor| {
+                !self.is_excluded(ancestor)
+                    && matches!(
+                        gix::open_opts(
+                            scan_path.join(ancestor),
+                            gix::open::Options::default().open_path_as_is(true),
+                        ),
+                        Err(gix::open::Error::NotARepository { .. })
+                    )
+            });
+
+        ancestors_searched && scan_path.join(relative).is_dir()
+    }
+
+    fn configure_object_cache(&self, repo: &mut gix::Repository) {
+        let size = self.object_cache_size;
+        repo.object_cache_size((size > 0).then_some(size));
+    }
+}
+
+/// Progress of the running index update, or the last one to finish, shared with
+/// `/admin/index-status` so operators can tell when the first index of a large scan path is
+/// done.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct IndexStatus {
+    /// Whether an index update is in progress
+    pub running: bool,
+    /// The pass the update is currently on, one of `metadata`, `commits` or `tags`
+    pub phase: Option<&'static str>,
+    pub current_repository: Option<String>,
+    /// Repositories the current pass has finished with
+    pub repositories_done: usize,
+    /// Repositories the current pass has to go through
+    pub repositories_total: usize,
+    /// Commits written to the index since the update started
+    pub commits_ingested: u64,
+    /// Unix timestamp the last update started at
+    pub started_at: Option<i64>,
+    /// Unix timestamp the last update finished at
+    pub finished_at: Option<i64>,
+    /// Number of full index updates that have finished since startup
+    pub completed_runs: u64,
+}
+
+impl IndexStatus {
+    fn update(status: &Mutex<Self>, f: impl FnOnce(&mut Self)) {
+        f(&mut status.lock().unwrap_or_else(PoisonError::into_inner));
+    }
+
+    fn start(status: &Mutex<Self>) {
+        Self::update(status, |status| {
+            *status = Self {
+                running: true,
+                started_at: Some(OffsetDateTime::now_utc().unix_timestamp()),
+                completed_runs: status.completed_runs,
+                ..Self::default()
+            };
+        });
+    }
+
+    fn start_phase(status: &Mutex<Self>, phase: &'static str, repositories_total: usize) {
+        Self::update(status, |status| {
+            status.phase = Some(phase);
+            status.current_repository = None;
+            status.repositories_done = 0;
+            status.repositories_total = repositories_total;
+        });
+    }
+
+    fn start_repository(status: &Mutex<Self>, repository: &str) {
+        Self::update(status, |status| {
+            status.current_repository = Some(repository.to_string());
+        });
+    }
+
+    fn finish_repository(status: &Mutex<Self>) {
+        Self::update(status, |status| {
+            status.current_repository = None;
+            status.repositories_done += 1;
+        });
+    }
+
+    fn finish(status: &Mutex<Self>, full_run: bool) {
+        Self::update(status, |status| {
+            status.running = false;
+            status.phase = None;
+            status.current_repository = None;
+            status.finished_at = Some(OffsetDateTime::now_utc().unix_timestamp());
+            status.completed_runs += u64::from(full_run);
+        });
+    }
+}
+
+pub fn run(
+    scan_path: &Path,
+    db: &Arc<rocksdb::DB>,
+    status: &Mutex<IndexStatus>,
+    config: &IndexerConfig,
+) {
     let span = info_span!("index_update");
     let _entered = span.enter();
 
+    let _guard = INDEX_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+
     info!("Starting index update");
+    IndexStatus::start(status);
 
-    update_repository_metadata(scan_path, db);
-    update_repository_reflog(scan_path, db.clone());
-    update_repository_tags(scan_path, db.clone());
+    update_repository_metadata(scan_path, db, status, config);
+    update_repository_reflog(scan_path, db.clone(), status, config);
+    update_repository_tags(scan_path, db.clone(), status, config);
 
     info!("Flushing to disk");
 
@@ -35,66 +213,353 @@ pub fn run(scan_path: &Path, db: &Arc<rocksdb::DB>) {
         error!(%error, "Failed to flush database to disk");
     }
 
+    IndexStatus::finish(status, true);
     info!("Finished index update");
 }
 
-#[instrument(skip(db))]
-fn update_repository_metadata(scan_path: &Path, db: &rocksdb::DB) {
+/// Single repository reindexes requested via `POST /<repo>/reindex`, worked through one at a
+/// time on a dedicated thread so a burst of pushes can't spawn a thread each.
+#[derive(Clone)]
+pub struct ReindexQueue {
+    send: std::sync::mpsc::SyncSender<PathBuf>,
+    /// Repositories already waiting in the queue, which don't need queueing again
+    pending: Arc<Mutex<HashSet<PathBuf>>>,
+}
+
+impl ReindexQueue {
+    /// Number of distinct repositories that can be waiting on a reindex at once.
+    const CAPACITY: usize = 64;
+
+    pub fn spawn(
+        scan_path: PathBuf,
+        db: Arc<rocksdb::DB>,
+        status: Arc<Mutex<IndexStatus>>,
+        config: Arc<IndexerConfig>,
+    ) -> Self {
+        let (send, recv) = std::sync::mpsc::sync_channel::<PathBuf>(Self::CAPACITY);
+        let pending = Arc::new(Mutex::new(HashSet::new()));
+
+        std::thread::spawn({
+            let pending = pending.clone();
+
+            move || {
+                for relative in recv {
+                    // taken off before reindexing so a push that lands mid-reindex is picked up
+                    // by another one
+                    pending
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .remove(&relative);
+
+                    let span = info_span!("reindex", repository = %relative.display());
+                    let _entered = span.enter();
+
+                    if let Err(error) = reindex_one(&scan_path, &relative, &db, &status, &config) {
+                        error!(?error, "Failed to reindex repository");
+                    }
+                }
+            }
+        });
+
+        Self { send, pending }
+    }
+
+    /// Queues a reindex of the repository at `relative` to the scan path, returning `false` if
+    /// the queue is full.
+    pub fn push(&self, relative: PathBuf) -> bool {
+        let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+        if pending.contains(&relative) {
+            return true;
+        }
+
+        if self.send.try_send(relative.clone()).is_err() {
+            return false;
+        }
+
+        pending.insert(relative);
+        true
+    }
+}
+
+/// Reindexes a single repository at `relative` to the `scan_path`, rather than scanning
+/// every repository like [`run`] does.
+#[instrument(skip(db, status, config))]
+pub fn reindex_one(
+    scan_path: &Path,
+    relative: &Path,
+    db: &Arc<rocksdb::DB>,
+    status: &Mutex<IndexStatus>,
+    config: &IndexerConfig,
+) -> anyhow::Result<()> {
+    let _guard = INDEX_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+
+    info!("Starting single repository index update");
+    IndexStatus::start(status);
+    IndexStatus::start_phase(status, "commits", 1);
+    IndexStatus::start_repository(status, &relative.to_string_lossy());
+
+    let res = reindex_one_inner(scan_path, relative, db, status, config);
+
+    IndexStatus::finish_repository(status);
+    IndexStatus::finish(status, false);
+
+    res
+}
+
+fn reindex_one_inner(
+    scan_path: &Path,
+    relative: &Path,
+    db: &Arc<rocksdb::DB>,
+    status: &Mutex<IndexStatus>,
+    config: &IndexerConfig,
+) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        config.is_discoverable(scan_path, relative),
+        "Repository wouldn't be found by scanning, refusing to index it"
+    );
+
+    if Repository::open(db, None, relative)?.is_none() {
+        let max_repositories = config.max_repositories;
+        anyhow::ensure!(
+            Repository::fetch_all(db)?.len() < max_repositories,
+            "Already indexing {max_repositories} repositories, raise --max-repos to index more"
+        );
+    }
+
+    let repository_path = scan_path.join(relative);
+    let mut git_repository = gix::open_opts(
+        &repository_path,
+        gix::open::Options::default().open_path_as_is(true),
+    )
+    .context("Failed to open repository")?;
+    config.configure_object_cache(&mut git_repository);
+
+    let mut ids_in_use = RepositoryId::in_use(db)?;
+    update_single_repository_metadata(
+        relative,
+        &repository_path,
+        &git_repository,
+        db,
+        &mut ids_in_use,
+        config,
+    )?;
+
+    let relative_path = relative.to_str().context("invalid path")?;
+    let db_repository = Repository::open(db, None, relative)?
+        .context("Repository missing from index after updating metadata")?;
+
+    update_single_repository_reflog(
+        relative_path,
+        db_repository.get(),
+        db,
+        &git_repository,
+        status,
+        config,
+    );
+    tag_index_scan(
+        relative_path,
+        db_repository.get(),
+        db.clone(),
+        &git_repository,
+    )?;
+
+    db.flush().context("Failed to flush database to disk")?;
+
+    info!("Finished single repository index update");
+
+    Ok(())
+}
+
+#[instrument(skip(db, status, config))]
+fn update_repository_metadata(
+    scan_path: &Path,
+    db: &rocksdb::DB,
+    status: &Mutex<IndexStatus>,
+    config: &IndexerConfig,
+) {
+    let mut ids_in_use = match RepositoryId::in_use(db) {
+        Ok(v) => v,
+        Err(error) => {
+            error!(%error, "Failed to read repository index to update metadata");
+            return;
+        }
+    };
+
     let mut discovered = Vec::new();
-    discover_repositories(scan_path, &mut discovered);
+    let truncated = discover_repositories(scan_path, scan_path, 1, config, &mut discovered);
+    // new repositories are given IDs in path order, so which of two repositories with colliding
+    // path hashes gets rehashed doesn't depend on the order the filesystem lists them in
+    discovered.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+    if truncated {
+        warn!(
+            "Stopped looking for repositories after finding {}, raise --max-repos to index more",
+            discovered.len()
+        );
+    }
+    IndexStatus::start_phase(status, "metadata", discovered.len());
+
+    let mut found = HashSet::new();
 
     for (repository_path, git_repository) in discovered {
         let Some(relative) = get_relative_path(scan_path, &repository_path) else {
+            IndexStatus::finish_repository(status);
             continue;
         };
+        found.insert(relative.to_path_buf());
+        IndexStatus::start_repository(status, &relative.to_string_lossy());
 
-        let id = match Repository::open(db, relative) {
-            Ok(v) => v.map_or_else(RepositoryId::new, |v| {
-                RepositoryId(v.get().id.0.to_native())
-            }),
-            Err(error) => {
-                // maybe we could nuke it ourselves, but we need to instantly trigger
-                // a reindex and we could enter into an infinite loop if there's a bug
-                // or something
-                error!(%error, "Failed to open repository index {}, please consider nuking database", relative.display());
-                continue;
-            }
-        };
+        if let Err(error) = isolate(|| {
+            update_single_repository_metadata(
+                relative,
+                &repository_path,
+                &git_repository,
+                db,
+                &mut ids_in_use,
+                config,
+            )
+        }) {
+            error!(%error, "Failed to update metadata for {}", relative.display());
+        }
+
+        IndexStatus::finish_repository(status);
+    }
+
+    prune_missing_repositories(scan_path, db, &found, truncated, config);
+}
+
+/// Removes the index of every repository that's no longer on disk or that the scan would no
+/// longer find, so a repository that's been renamed or moved isn't listed at both its old and new
+/// paths until the reflog pass gets to it, and lowering `--max-repos` or `--max-scan-depth`
+/// actually shrinks the index.
+///
+/// `found` are the repositories found by this scan, which was cut short by `--max-repos` if
+/// `truncated` is set. Repositories the scan could have found but didn't are kept, since that's
+/// more likely a transient error reading a directory than them being gone.
+fn prune_missing_repositories(
+    scan_path: &Path,
+    db: &rocksdb::DB,
+    found: &HashSet<PathBuf>,
+    truncated: bool,
+    config: &IndexerConfig,
+) {
+    let repos = match Repository::fetch_all(db) {
+        Ok(v) => v,
+        Err(error) => {
+            error!(%error, "Failed to read repository index to prune missing repositories");
+            return;
+        }
+    };
+
+    for (relative_path, db_repository) in repos {
+        let relative = Path::new(&relative_path);
 
-        let Some(name) = relative.file_name().and_then(OsStr::to_str) else {
+        if found.contains(relative) {
             continue;
-        };
-        let description = std::fs::read(repository_path.join("description")).unwrap_or_default();
-        let description = String::from_utf8(description)
-            .ok()
-            .filter(|v| !v.is_empty());
-
-        let owner = git_repository
-            .config_snapshot()
-            .string("gitweb.owner")
-            .map(|v| v.to_string());
-
-        let res = Repository {
-            id,
-            name: name.to_string(),
-            description,
-            owner,
-            last_modified: {
-                let r =
-                    find_last_committed_time(&git_repository).unwrap_or(OffsetDateTime::UNIX_EPOCH);
-                (r.unix_timestamp(), r.offset().whole_seconds())
-            },
-            default_branch: find_default_branch(&git_repository).ok().flatten(),
-            exported: repository_path.join("git-daemon-export-ok").exists(),
-        }
-        .insert(db, relative);
-
-        if let Err(error) = res {
-            warn!(%error, "Failed to insert repository");
+        } else if config.is_excluded(relative) {
+            info!("Repository {relative_path} excluded, removing from db");
+        } else if !scan_path.join(relative).exists() {
+            info!("Repository {relative_path} gone from disk, removing from db");
+        } else if truncated {
+            info!("Repository {relative_path} is beyond --max-repos, removing from db");
+        } else if !config.is_discoverable(scan_path, relative) {
+            info!("Repository {relative_path} is no longer discoverable, removing from db");
+        } else {
+            continue;
+        }
+
+        if let Err(error) = db_repository.get().delete(db, &relative_path) {
+            warn!(%error, "Failed to delete dangling index for {relative_path}");
         }
     }
 }
 
+fn update_single_repository_metadata(
+    relative: &Path,
+    repository_path: &Path,
+    git_repository: &gix::Repository,
+    db: &rocksdb::DB,
+    ids_in_use: &mut HashSet<u64>,
+    config: &IndexerConfig,
+) -> Result<(), anyhow::Error> {
+    let existing = match Repository::open(db, None, relative) {
+        Ok(v) => v,
+        Err(error) => {
+            // maybe we could nuke it ourselves, but we need to instantly trigger
+            // a reindex and we could enter into an infinite loop if there's a bug
+            // or something
+            return Err(error.context(format!(
+                "Failed to open repository index {}, please consider nuking database",
+                relative.display()
+            )));
+        }
+    };
+
+    let id = match &existing {
+        Some(v) => RepositoryId(v.get().id.0.to_native()),
+        None if config.deterministic_repository_ids => {
+            RepositoryId::from_path(relative, ids_in_use)?
+        }
+        None => RepositoryId::new(),
+    };
+
+    let languages = languages::breakdown(
+        git_repository,
+        existing.as_ref().and_then(|v| v.get().languages.as_ref()),
+    )
+    .unwrap_or_else(|error| {
+        warn!(%error, "Failed to compute language breakdown for {}", relative.display());
+        None
+    });
+
+    let name = relative
+        .file_name()
+        .and_then(OsStr::to_str)
+        .context("Repository has an invalid name")?;
+    let description = std::fs::read(repository_path.join("description")).unwrap_or_default();
+    let description = String::from_utf8(description)
+        .ok()
+        .filter(|v| !v.is_empty());
+
+    let config = git_repository.config_snapshot();
+    let owner = config.string("gitweb.owner").map(|v| v.to_string());
+    let issues_url = config.string("gitweb.issuesurl").map(|v| v.to_string());
+
+    Repository {
+        id,
+        name: name.to_string(),
+        description,
+        owner,
+        last_modified: {
+            let r = find_last_committed_time(git_repository).unwrap_or(OffsetDateTime::UNIX_EPOCH);
+            (r.unix_timestamp(), r.offset().whole_seconds())
+        },
+        default_branch: find_default_branch(git_repository).ok().flatten(),
+        exported: repository_path.join("git-daemon-export-ok").exists(),
+        issues_url,
+        languages,
+        empty: is_empty(git_repository).unwrap_or_default(),
+    }
+    .insert(db, relative)
+    .context("Failed to insert repository")
+}
+
+/// Whether the repository has no commits at all, ie. `HEAD` is unborn and there are no other
+/// references we could fall back to.
+fn is_empty(repo: &gix::Repository) -> Result<bool, anyhow::Error> {
+    if !repo
+        .head()
+        .context("Couldn't find HEAD of repository")?
+        .is_unborn()
+    {
+        return Ok(false);
+    }
+
+    let references = repo
+        .references()
+        .context("Couldn't read repository references")?;
+    Ok(references.all()?.next().is_none())
+}
+
 fn find_default_branch(repo: &gix::Repository) -> Result<Option<String>, anyhow::Error> {
     Ok(Some(repo.head()?.name().as_bstr().to_string()))
 }
@@ -103,23 +568,36 @@ fn find_last_committed_time(repo: &gix::Repository) -> Result<OffsetDateTime, an
     let mut timestamp = OffsetDateTime::UNIX_EPOCH;
 
     for reference in repo.references()?.all()? {
-        let Ok(commit) = reference.unwrap().peel_to_commit() else {
+        // a broken reference shouldn't stop the rest from being considered
+        let Ok(mut reference) = reference else {
+            continue;
+        };
+        let Ok(commit) = reference.peel_to_commit() else {
             continue;
         };
 
-        let committer = commit.committer()?;
+        let Ok(committer) = commit.committer() else {
+            continue;
+        };
         let offset = UtcOffset::from_whole_seconds(committer.time.offset).unwrap_or(UtcOffset::UTC);
         let committed_time = OffsetDateTime::from_unix_timestamp(committer.time.seconds)
-            .unwrap_or(OffsetDateTime::UNIX_EPOCH)
-            .to_offset(offset);
+            .unwrap_or(OffsetDateTime::UNIX_EPOCH);
+        let committed_time = committed_time
+            .checked_to_offset(offset)
+            .unwrap_or(committed_time);
         timestamp = timestamp.max(committed_time);
     }
 
     Ok(timestamp)
 }
 
-#[instrument(skip(db))]
-fn update_repository_reflog(scan_path: &Path, db: Arc<rocksdb::DB>) {
+#[instrument(skip(db, status, config))]
+fn update_repository_reflog(
+    scan_path: &Path,
+    db: Arc<rocksdb::DB>,
+    status: &Mutex<IndexStatus>,
+    config: &IndexerConfig,
+) {
     let repos = match Repository::fetch_all(&db) {
         Ok(v) => v,
         Err(error) => {
@@ -128,164 +606,317 @@ fn update_repository_reflog(scan_path: &Path, db: Arc<rocksdb::DB>) {
         }
     };
 
+    IndexStatus::start_phase(status, "commits", repos.len());
+
     for (relative_path, db_repository) in repos {
-        let Some(git_repository) = open_repo(scan_path, &relative_path, db_repository.get(), &db)
+        IndexStatus::start_repository(status, &relative_path);
+
+        let Some(git_repository) =
+            open_repo(scan_path, &relative_path, db_repository.get(), &db, config)
         else {
+            IndexStatus::finish_repository(status);
             continue;
         };
 
-        let references = match git_repository.references() {
-            Ok(v) => v,
-            Err(error) => {
-                error!(%error, "Failed to read references for {relative_path}");
-                continue;
-            }
-        };
+        if let Err(error) = isolate(|| {
+            update_single_repository_reflog(
+                &relative_path,
+                db_repository.get(),
+                &db,
+                &git_repository,
+                status,
+                config,
+            );
+            Ok(())
+        }) {
+            error!(%error, "Failed to update reflog for {relative_path}");
+        }
+
+        IndexStatus::finish_repository(status);
+    }
+}
+
+fn update_single_repository_reflog(
+    relative_path: &str,
+    db_repository: &ArchivedRepository,
+    db: &Arc<rocksdb::DB>,
+    git_repository: &gix::Repository,
+    status: &Mutex<IndexStatus>,
+    config: &IndexerConfig,
+) {
+    let references = match git_repository.references() {
+        Ok(v) => v,
+        Err(error) => {
+            error!(%error, "Failed to read references for {relative_path}");
+            return;
+        }
+    };
+
+    let references = match references.all() {
+        Ok(v) => v,
+        Err(error) => {
+            error!(%error, "Failed to read references for {relative_path}");
+            return;
+        }
+    };
+
+    // identities are normalised as commits are indexed, so changes to the mailmap only apply to
+    // commits indexed after them
+    let mailmap = git_repository.open_mailmap();
+
+    let mut valid_references = Vec::new();
+    // commit bodies of dropped trees are cleaned up once at the end, rather than walking every
+    // tree in the repository each time one is dropped
+    let mut dropped_commits = false;
+    // references that couldn't be read might still exist, so nothing is cleaned up unless every
+    // one of them was
+    let mut all_references_read = true;
 
-        let references = match references.all() {
+    for reference in references {
+        let mut reference = match reference {
             Ok(v) => v,
             Err(error) => {
-                error!(%error, "Failed to read references for {relative_path}");
+                error!(%error, "Failed to read reference for {relative_path}");
+                all_references_read = false;
                 continue;
             }
         };
 
-        let mut valid_references = Vec::new();
+        let reference_name = reference.name();
+        if !matches!(
+            reference_name.category(),
+            Some(Category::Tag | Category::LocalBranch)
+        ) {
+            continue;
+        }
 
-        for reference in references {
-            let mut reference = match reference {
-                Ok(v) => v,
-                Err(error) => {
-                    error!(%error, "Failed to read reference for {relative_path}");
-                    continue;
-                }
-            };
+        valid_references.push(reference_name.as_bstr().to_string());
 
-            let reference_name = reference.name();
-            if !matches!(
-                reference_name.category(),
-                Some(Category::Tag | Category::LocalBranch)
-            ) {
-                continue;
+        match branch_index_update(
+            &mut reference,
+            relative_path,
+            db_repository,
+            db.clone(),
+            git_repository,
+            &mailmap,
+            status,
+            config,
+            false,
+        ) {
+            Ok(dropped) => dropped_commits |= dropped,
+            Err(error) => {
+                // the tree may have been dropped before failing
+                dropped_commits = true;
+                error!(%error, "Failed to update reflog for {relative_path}@{:?}", valid_references.last());
             }
+        }
+    }
 
-            valid_references.push(reference_name.as_bstr().to_string());
-
-            if let Err(error) = branch_index_update(
-                &mut reference,
-                &relative_path,
-                db_repository.get(),
-                db.clone(),
-                &git_repository,
-                false,
-            ) {
-                error!(%error, "Failed to update reflog for {relative_path}@{:?}", valid_references.last());
+    if all_references_read {
+        match drop_deleted_heads(db_repository, db, &valid_references) {
+            Ok(dropped) => dropped_commits |= dropped,
+            Err(error) => {
+                dropped_commits = true;
+                error!(%error, "Failed to drop commits of deleted references for {relative_path}");
             }
         }
+    }
 
-        if let Err(error) = db_repository.get().replace_heads(&db, &valid_references) {
-            error!(%error, "Failed to update heads");
+    if dropped_commits {
+        if let Err(error) = db_repository.drop_unreferenced_commits(db) {
+            error!(%error, "Failed to drop unreferenced commits for {relative_path}");
         }
     }
+
+    if let Err(error) = db_repository.replace_heads(db, &valid_references) {
+        error!(%error, "Failed to update heads");
+    }
 }
 
-#[instrument(skip(reference, db_repository, db, git_repository))]
+/// Drops the indexed commits of every reference that was indexed previously but no longer
+/// exists, such as deleted branches, so their commits don't linger in the database forever.
+/// Returns whether any were dropped.
+fn drop_deleted_heads(
+    db_repository: &ArchivedRepository,
+    db: &Arc<rocksdb::DB>,
+    valid_references: &[String],
+) -> Result<bool, anyhow::Error> {
+    let Some(previous_heads) = db_repository.heads(db, None)? else {
+        return Ok(false);
+    };
+
+    let mut dropped = false;
+
+    for head in previous_heads.get().0.iter() {
+        if valid_references.iter().any(|v| v == head.as_str()) {
+            continue;
+        }
+
+        info!("Dropping commits of deleted reference {head}");
+        db_repository
+            .commit_tree(db.clone(), None, head.as_str())?
+            .drop_commits()?;
+        dropped = true;
+    }
+
+    Ok(dropped)
+}
+
+/// Indexes any new commits on `reference`, returning whether a previously indexed tree was
+/// dropped along the way.
+#[instrument(skip(reference, db_repository, db, git_repository, mailmap, status, config))]
+#[allow(clippy::too_many_arguments)]
 fn branch_index_update(
     reference: &mut Reference<'_>,
     relative_path: &str,
     db_repository: &ArchivedRepository,
     db: Arc<rocksdb::DB>,
     git_repository: &gix::Repository,
+    mailmap: &gix::mailmap::Snapshot,
+    status: &Mutex<IndexStatus>,
+    config: &IndexerConfig,
     force_reindex: bool,
-) -> Result<(), anyhow::Error> {
+) -> Result<bool, anyhow::Error> {
     info!("Refreshing indexes");
 
-    let commit_tree = db_repository.commit_tree(db.clone(), reference.name().as_bstr().to_str()?);
-
-    if force_reindex {
-        commit_tree.drop_commits()?;
+    let active_tree =
+        db_repository.commit_tree(db.clone(), None, reference.name().as_bstr().to_str()?)?;
+
+    // forced reindexes and first indexes are built into the inactive generation so readers keep
+    // seeing the old history, or none at all, until the new one is complete. the generation that
+    // was swapped out is only dropped on the next pass, by which point any requests still reading
+    // from it have long finished
+    let rebuild = force_reindex || active_tree.len()? == 0;
+    let inactive_tree = active_tree.inactive();
+    let dropped = rebuild || inactive_tree.len()? > 0;
+    if dropped {
+        inactive_tree.drop_generation()?;
     }
 
+    let commit_tree = if rebuild {
+        &inactive_tree
+    } else {
+        &active_tree
+    };
+
     let commit = reference.peel_to_commit()?;
+    let tip = commit.id().detach();
+
+    let latest_indexed = commit_tree.fetch_latest_one()?;
+    let latest_indexed = latest_indexed.as_ref().map(|v| v.get().hash.as_slice());
+
+    if latest_indexed == Some(tip.as_bytes()) {
+        info!("No commits since last index");
+        return Ok(dropped);
+    }
 
-    let latest_indexed = if let Some(latest_indexed) = commit_tree.fetch_latest_one()? {
-        if commit.id().as_bytes() == latest_indexed.get().hash.as_slice() {
-            info!("No commits since last index");
-            return Ok(());
+    // commits are numbered oldest first but walked newest first, so rather than holding onto the
+    // id of every new commit to reverse them, which is the entire history on a first index, the
+    // walk is done twice. the first only counts the commits up to the tip we indexed last time
+    let mut new_commits = 0_u64;
+    let mut found_latest_indexed = latest_indexed.is_none();
+
+    for rev in git_repository.rev_walk([tip]).all()? {
+        if Some(rev?.id.as_bytes()) == latest_indexed {
+            found_latest_indexed = true;
+            break;
         }
 
-        Some(latest_indexed)
-    } else {
-        None
-    };
+        new_commits += 1;
+    }
 
-    // TODO: stop collecting into a vec
-    let revwalk = git_repository
-        .rev_walk([commit.id().detach()])
-        .all()?
-        .collect::<Vec<_>>()
-        .into_iter()
-        .rev();
+    // if the branch was force-pushed the tip we indexed up to may no longer be part of its
+    // history, so the commits indexed before it can't be trusted anymore either
+    if !found_latest_indexed {
+        warn!("Previously indexed tip is no longer an ancestor of {tip}, history was likely rewritten, forcing reindex");
+
+        return branch_index_update(
+            reference,
+            relative_path,
+            db_repository,
+            db,
+            git_repository,
+            mailmap,
+            status,
+            config,
+            true,
+        );
+    }
 
     let tree_len = commit_tree.len()?;
-    let mut seen = false;
+    let stats = commit_tree.stats();
     let mut i = 0;
-    for revs in &revwalk.chunks(250) {
-        let mut batch = WriteBatch::default();
-
-        for rev in revs {
-            let rev = rev?;
+    let batch_size = config.commit_batch_size.max(1);
+    let use_wal = config.wal;
 
-            if let (false, Some(latest_indexed)) = (seen, &latest_indexed) {
-                if rev.id.as_bytes() == latest_indexed.get().hash.as_slice() {
-                    seen = true;
-                }
-
-                continue;
-            }
+    // the second walk numbers the new commits from the top down as it goes
+    let revs = git_repository
+        .rev_walk([tip])
+        .all()?
+        .take(usize::try_from(new_commits)?);
 
-            seen = true;
+    for revs in &revs.chunks(batch_size) {
+        let mut batch = WriteBatch::default();
+        let mut pending_stats = PendingStats::default();
+        let batch_start = i;
 
+        for rev in revs {
             if ((i + 1) % 25_000) == 0 {
                 info!("{} commits ingested", i + 1);
             }
 
-            let commit = rev.object()?;
-            let oid = commit.id;
-            let commit = commit.decode()?;
-            let author = commit.author();
-            let committer = commit.committer();
-
-            Commit::new(oid, &commit, author, committer)?.insert(
-                &commit_tree,
-                tree_len + i,
-                &mut batch,
-            )?;
+            let oid = rev?.id;
+            let commit = git_repository.find_commit(oid)?.detach().data;
+            let commit = repair_commit_signatures(&commit).unwrap_or(commit);
+            let commit = CommitRef::from_bytes(&commit)?;
+            let author = mailmap.resolve_cow(commit.author());
+            let committer = mailmap.resolve_cow(commit.committer());
+
+            let commit = Commit::new(oid, &commit, author, committer);
+            pending_stats.add(&commit);
             i += 1;
+            commit.insert(commit_tree, tree_len + new_commits - i, &mut batch)?;
         }
 
-        commit_tree.update_counter(tree_len + i, &mut batch)?;
-        db.write_without_wal(batch)?;
+        // the counter bounds what readers see, so none of the new commits become visible until
+        // the oldest of them has been written in the last batch
+        if i == new_commits {
+            commit_tree.update_counter(tree_len + new_commits, &mut batch)?;
+        }
+        stats.merge(pending_stats, &mut batch)?;
+        write_batch(&db, batch, use_wal)?;
+
+        IndexStatus::update(status, |status| {
+            status.commits_ingested += i - batch_start;
+        });
     }
 
-    if !seen && !force_reindex {
-        warn!("Detected converged history, forcing reindex");
+    if rebuild {
+        let mut batch = WriteBatch::default();
+        commit_tree.activate(&mut batch)?;
+        write_batch(&db, batch, use_wal)?;
+    }
 
-        return branch_index_update(
-            reference,
-            relative_path,
-            db_repository,
-            db,
-            git_repository,
-            true,
-        );
+    Ok(dropped)
+}
+
+fn write_batch(db: &rocksdb::DB, batch: WriteBatch, use_wal: bool) -> Result<(), anyhow::Error> {
+    if use_wal {
+        db.write(batch)?;
+    } else {
+        db.write_without_wal(batch)?;
     }
 
     Ok(())
 }
 
-#[instrument(skip(db))]
-fn update_repository_tags(scan_path: &Path, db: Arc<rocksdb::DB>) {
+#[instrument(skip(db, status, config))]
+fn update_repository_tags(
+    scan_path: &Path,
+    db: Arc<rocksdb::DB>,
+    status: &Mutex<IndexStatus>,
+    config: &IndexerConfig,
+) {
     let repos = match Repository::fetch_all(&db) {
         Ok(v) => v,
         Err(error) => {
@@ -294,20 +925,30 @@ fn update_repository_tags(scan_path: &Path, db: Arc<rocksdb::DB>) {
         }
     };
 
+    IndexStatus::start_phase(status, "tags", repos.len());
+
     for (relative_path, db_repository) in repos {
-        let Some(git_repository) = open_repo(scan_path, &relative_path, db_repository.get(), &db)
+        IndexStatus::start_repository(status, &relative_path);
+
+        let Some(git_repository) =
+            open_repo(scan_path, &relative_path, db_repository.get(), &db, config)
         else {
+            IndexStatus::finish_repository(status);
             continue;
         };
 
-        if let Err(error) = tag_index_scan(
-            &relative_path,
-            db_repository.get(),
-            db.clone(),
-            &git_repository,
-        ) {
+        if let Err(error) = isolate(|| {
+            tag_index_scan(
+                &relative_path,
+                db_repository.get(),
+                db.clone(),
+                &git_repository,
+            )
+        }) {
             error!(%error, "Failed to update tags for {relative_path}");
         }
+
+        IndexStatus::finish_repository(status);
     }
 }
 
@@ -318,7 +959,7 @@ fn tag_index_scan(
     db: Arc<rocksdb::DB>,
     git_repository: &gix::Repository,
 ) -> Result<(), anyhow::Error> {
-    let tag_tree = db_repository.tag_tree(db);
+    let tag_tree = db_repository.tag_tree(db, None);
 
     let git_tags: HashSet<_> = git_repository
         .references()
@@ -331,8 +972,9 @@ fn tag_index_scan(
     let indexed_tags: HashSet<String> = tag_tree.list()?.into_iter().collect();
 
     // insert any git tags that are missing from the index
+    let mailmap = git_repository.open_mailmap();
     for tag_name in git_tags.difference(&indexed_tags) {
-        tag_index_update(tag_name, git_repository, &tag_tree)?;
+        tag_index_update(tag_name, git_repository, &mailmap, &tag_tree)?;
     }
 
     // remove any extra tags that the index has
@@ -344,11 +986,12 @@ fn tag_index_scan(
     Ok(())
 }
 
-#[instrument(skip(git_repository, tag_tree))]
+#[instrument(skip(git_repository, mailmap, tag_tree))]
 fn tag_index_update(
     tag_name: &str,
     git_repository: &gix::Repository,
-    tag_tree: &TagTree,
+    mailmap: &gix::mailmap::Snapshot,
+    tag_tree: &TagTree<'_>,
 ) -> Result<(), anyhow::Error> {
     let mut reference = git_repository
         .find_reference(tag_name)
@@ -357,30 +1000,50 @@ fn tag_index_update(
     if let Ok(tag) = reference.peel_to_tag() {
         info!("Inserting newly discovered tag to index");
 
-        Tag::new(tag.tagger()?)?.insert(tag_tree, tag_name)?;
+        Tag::annotated(tag.tagger()?.map(|v| mailmap.resolve_cow(v))).insert(tag_tree, tag_name)?;
+    } else if reference.peel_to_commit().is_ok() {
+        info!("Inserting newly discovered lightweight tag to index");
+
+        Tag::lightweight().insert(tag_tree, tag_name)?;
     }
 
     Ok(())
 }
 
 #[instrument(skip(tag_tree))]
-fn tag_index_delete(tag_name: &str, tag_tree: &TagTree) -> Result<(), anyhow::Error> {
+fn tag_index_delete(tag_name: &str, tag_tree: &TagTree<'_>) -> Result<(), anyhow::Error> {
     info!("Removing stale tag from index");
     tag_tree.remove(tag_name)?;
 
     Ok(())
 }
 
-#[instrument(skip(scan_path, db_repository, db))]
+/// Runs the indexing of a single repository, turning any panic into an error so a single
+/// malformed repository is skipped rather than taking down the indexer thread and stopping
+/// indexing for every other repository.
+fn isolate<T>(f: impl FnOnce() -> anyhow::Result<T>) -> anyhow::Result<T> {
+    std::panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown panic");
+
+        Err(anyhow::anyhow!("Panicked whilst indexing: {message}"))
+    })
+}
+
+#[instrument(skip(scan_path, db_repository, db, config))]
 fn open_repo<P: AsRef<Path> + Debug>(
     scan_path: &Path,
     relative_path: P,
     db_repository: &ArchivedRepository,
     db: &rocksdb::DB,
+    config: &IndexerConfig,
 ) -> Option<gix::Repository> {
     match gix::open(scan_path.join(relative_path.as_ref())) {
         Ok(mut v) => {
-            v.object_cache_size(10 * 1024 * 1024);
+            config.configure_object_cache(&mut v);
             Some(v)
         }
         Err(gix::open::Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {
@@ -403,12 +1066,34 @@ fn get_relative_path<'a>(relative_to: &Path, full_path: &'a Path) -> Option<&'a
     full_path.strip_prefix(relative_to).ok()
 }
 
-fn discover_repositories(current: &Path, discovered_repos: &mut Vec<(PathBuf, gix::Repository)>) {
+/// Builds the set of `--exclude` patterns. `*` matches across directories, so `*.backup.git`
+/// excludes backups at any depth.
+pub fn build_exclusions(patterns: &[String]) -> anyhow::Result<GlobSet> {
+    let mut excluded = GlobSetBuilder::new();
+    for pattern in patterns {
+        excluded.add(
+            Glob::new(pattern).with_context(|| format!("Invalid --exclude pattern {pattern}"))?,
+        );
+    }
+
+    Ok(excluded.build()?)
+}
+
+/// Recursively finds the repositories within `current`, which is `depth` directories deep into
+/// the scan path, without entering any excluded directory. Returns whether the search was cut
+/// short by hitting `--max-repos`.
+fn discover_repositories(
+    scan_path: &Path,
+    current: &Path,
+    depth: usize,
+    config: &IndexerConfig,
+    discovered_repos: &mut Vec<(PathBuf, gix::Repository)>,
+) -> bool {
     let current = match std::fs::read_dir(current) {
         Ok(v) => v,
         Err(error) => {
             error!(%error, "Failed to enter repository directory {}", current.display());
-            return;
+            return false;
         }
     };
 
@@ -418,17 +1103,235 @@ fn discover_repositories(current: &Path, discovered_repos: &mut Vec<(PathBuf, gi
         .filter(|path| path.is_dir());
 
     for dir in dirs {
+        if get_relative_path(scan_path, &dir).is_some_and(|v| config.is_excluded(v)) {
+            continue;
+        }
+
         match gix::open_opts(&dir, gix::open::Options::default().open_path_as_is(true)) {
             Ok(mut repo) => {
-                repo.object_cache_size(10 * 1024 * 1024);
+                if discovered_repos.len() >= config.max_repositories {
+                    return true;
+                }
+
+                config.configure_object_cache(&mut repo);
                 discovered_repos.push((dir, repo));
             }
-            Err(gix::open::Error::NotARepository { .. }) => {
-                discover_repositories(&dir, discovered_repos);
+            Err(gix::open::Error::NotARepository { .. }) if depth < config.max_scan_depth => {
+                if discover_repositories(scan_path, &dir, depth + 1, config, discovered_repos) {
+                    return true;
+                }
             }
+            Err(gix::open::Error::NotARepository { .. }) => {}
             Err(error) => {
                 warn!(%error, "Failed to open repository {} for indexing", dir.display());
             }
         }
     }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, path::PathBuf, sync::Arc};
+
+    use rkyv::string::ArchivedString;
+
+    use super::{
+        build_exclusions, discover_repositories, prune_missing_repositories, IndexerConfig,
+    };
+    use crate::{
+        database::schema::repository::Repository,
+        test_util::{self, Fixture},
+    };
+
+    /// The number of commits indexed for `reference`, and their summaries from newest to oldest.
+    fn indexed_commits(
+        db: &Arc<rocksdb::DB>,
+        fixture: &Fixture,
+        reference: &str,
+    ) -> (u64, Vec<String>) {
+        let repository = Repository::open(db, None, fixture.relative_path())
+            .unwrap()
+            .unwrap();
+        let tree = repository
+            .get()
+            .commit_tree(db.clone(), None, reference)
+            .unwrap();
+
+        let summaries = tree
+            .fetch_latest(100, 0)
+            .unwrap()
+            .iter()
+            .map(|commit| commit.get().summary.to_string())
+            .collect();
+
+        (tree.len().unwrap(), summaries)
+    }
+
+    #[test]
+    fn repositories_beyond_max_repos_are_pruned() {
+        let mut kept = Fixture::named("a.git");
+        let mut dropped = kept.sibling("b.git");
+        kept.commit("initial");
+        dropped.commit("initial");
+
+        let (_dir, db) = test_util::open_db(kept.scan_path());
+        test_util::index(kept.scan_path(), &db);
+        assert!(Repository::exists(&db, dropped.relative_path()).unwrap());
+
+        let found = HashSet::from([PathBuf::from(kept.relative_path())]);
+
+        // a repository that wasn't seen is left alone when discovery finished, since it may have
+        // just been unreadable for a moment
+        let config = IndexerConfig::default();
+        prune_missing_repositories(kept.scan_path(), &db, &found, false, &config);
+        assert!(Repository::exists(&db, dropped.relative_path()).unwrap());
+
+        prune_missing_repositories(kept.scan_path(), &db, &found, true, &config);
+        assert!(Repository::exists(&db, kept.relative_path()).unwrap());
+        assert!(!Repository::exists(&db, dropped.relative_path()).unwrap());
+    }
+
+    #[test]
+    fn repositories_without_commits_are_marked_empty() {
+        let mut populated = Fixture::named("populated.git");
+        let empty = populated.sibling("empty.git");
+        populated.commit("initial");
+
+        let (_dir, db) = test_util::open_db(populated.scan_path());
+        test_util::index(populated.scan_path(), &db);
+
+        for (fixture, expected) in [(&populated, false), (&empty, true)] {
+            let repository = Repository::open(&db, None, fixture.relative_path())
+                .unwrap()
+                .unwrap();
+            assert_eq!(
+                repository.get().empty,
+                expected,
+                "{}",
+                fixture.relative_path().display()
+            );
+        }
+    }
+
+    #[test]
+    fn exclusion_patterns_match_at_any_depth() {
+        let excluded =
+            build_exclusions(&["*/archived/*".to_string(), "*.backup.git".to_string()]).unwrap();
+
+        for path in [
+            "group/archived/old.git",
+            "org/team/archived/old.git",
+            "group/archived/nested/old.git",
+            "nightly.backup.git",
+            "group/nightly.backup.git",
+        ] {
+            assert!(excluded.is_match(path), "{path}");
+        }
+
+        for path in [
+            "archived/old.git",
+            "group/archived",
+            "group/live.git",
+            "backup.git",
+        ] {
+            assert!(!excluded.is_match(path), "{path}");
+        }
+
+        assert!(build_exclusions(&["[unclosed".to_string()]).is_err());
+    }
+
+    #[test]
+    fn excluded_directories_are_not_searched() {
+        let kept = Fixture::named("kept.git");
+        let live = kept.sibling("group/live.git");
+        let _backup = kept.sibling("group/nightly.backup.git");
+        let _archived = kept.sibling("group/archived/old.git");
+        let _nested = kept.sibling("group/archived/nested/older.git");
+
+        // only the archive directory itself matches, not the repositories within it
+        let config = IndexerConfig {
+            excluded: build_exclusions(&["*/archived".to_string(), "*.backup.git".to_string()])
+                .unwrap(),
+            ..IndexerConfig::default()
+        };
+
+        let mut discovered = Vec::new();
+        assert!(!discover_repositories(
+            kept.scan_path(),
+            kept.scan_path(),
+            1,
+            &config,
+            &mut discovered,
+        ));
+
+        let mut found: Vec<_> = discovered
+            .into_iter()
+            .map(|(path, _)| path.strip_prefix(kept.scan_path()).unwrap().to_path_buf())
+            .collect();
+        found.sort();
+        assert_eq!(found, [live.relative_path(), kept.relative_path()]);
+    }
+
+    #[test]
+    fn force_pushed_branches_are_reindexed() {
+        let mut fixture = Fixture::new();
+        let base = fixture.commit("base");
+        fixture.commit("old 1");
+        fixture.commit("old 2");
+
+        let (_dir, db) = test_util::open_db(fixture.scan_path());
+        test_util::index(fixture.scan_path(), &db);
+
+        // rewrite history out from under the indexed tip, with the same number of commits
+        fixture.git(&["reset", "-q", "--hard", &base]);
+        fixture.commit("new 1");
+        fixture.commit("new 2");
+        test_util::index(fixture.scan_path(), &db);
+
+        assert_eq!(
+            indexed_commits(&db, &fixture, "refs/heads/main"),
+            (
+                3,
+                vec!["new 2".to_string(), "new 1".to_string(), "base".to_string()]
+            )
+        );
+    }
+
+    #[test]
+    fn deleted_branches_are_dropped_from_the_index() {
+        let mut fixture = Fixture::new();
+        fixture.commit("initial");
+        fixture.git(&["checkout", "-q", "-b", "feature"]);
+        fixture.commit("feature work");
+        fixture.git(&["checkout", "-q", "main"]);
+
+        let (_dir, db) = test_util::open_db(fixture.scan_path());
+        test_util::index(fixture.scan_path(), &db);
+        assert_eq!(indexed_commits(&db, &fixture, "refs/heads/feature").0, 2);
+
+        fixture.git(&["branch", "-q", "-D", "feature"]);
+        test_util::index(fixture.scan_path(), &db);
+
+        assert_eq!(
+            indexed_commits(&db, &fixture, "refs/heads/feature"),
+            (0, vec![])
+        );
+        assert_eq!(indexed_commits(&db, &fixture, "refs/heads/main").0, 1);
+
+        let repository = Repository::open(&db, None, fixture.relative_path())
+            .unwrap()
+            .unwrap();
+        let heads = repository.get().heads(&db, None).unwrap().unwrap();
+        assert_eq!(
+            heads
+                .get()
+                .0
+                .iter()
+                .map(ArchivedString::as_str)
+                .collect::<Vec<_>>(),
+            ["refs/heads/main"]
+        );
+    }
 }
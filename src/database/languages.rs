@@ -0,0 +1,113 @@
+//! Breaks the default branch of a repository down by the bytes written in each language, like
+//! GitHub's language bar.
+//!
+//! Files can be left out of the breakdown by marking them as `linguist-vendored`,
+//! `linguist-generated` or `linguist-documentation` in the `.gitattributes` at the root of the
+//! tree, ie.
+//!
+//! ```text
+//! vendor/** linguist-vendored
+//! *.pb.go linguist-generated
+//! ```
+
+use std::collections::HashMap;
+
+use anyhow::Context;
+use gix::{bstr::ByteSlice, traverse::tree::Recorder, ObjectId};
+use tree_sitter_grammar_repository::Language;
+
+use crate::{
+    database::schema::repository::{ArchivedLanguageBreakdown, LanguageBreakdown, LanguageBytes},
+    gitattributes::GitAttributes,
+};
+
+/// Languages beyond this are summed into [`LanguageBreakdown::other_bytes`].
+const MAX_LANGUAGES: usize = 8;
+
+/// Attributes that remove a file from the breakdown when set.
+const EXCLUDING_ATTRIBUTES: [&str; 3] = [
+    "linguist-vendored",
+    "linguist-generated",
+    "linguist-documentation",
+];
+
+/// Computes the language breakdown of the repository's `HEAD` tree, reusing `existing` if the
+/// tree hasn't changed since it was computed. Returns `None` for repositories without any
+/// commits.
+pub fn breakdown(
+    repo: &gix::Repository,
+    existing: Option<&ArchivedLanguageBreakdown>,
+) -> anyhow::Result<Option<LanguageBreakdown>> {
+    let Ok(commit) = repo.head_commit() else {
+        return Ok(None);
+    };
+
+    let tree_id = commit
+        .tree_id()
+        .context("Failed to read tree of HEAD")?
+        .detach();
+    let tree_bytes = match tree_id {
+        ObjectId::Sha1(d) => d,
+    };
+
+    if let Some(existing) = existing.filter(|v| v.tree == tree_bytes) {
+        return Ok(Some(LanguageBreakdown {
+            tree: tree_bytes,
+            languages: existing
+                .languages
+                .iter()
+                .map(|v| LanguageBytes {
+                    name: v.name.to_string(),
+                    bytes: v.bytes.to_native(),
+                })
+                .collect(),
+            other_bytes: existing.other_bytes.to_native(),
+        }));
+    }
+
+    let tree = repo.find_tree(tree_id)?;
+    let attributes = GitAttributes::from_tree(&tree, &EXCLUDING_ATTRIBUTES)?;
+
+    let mut recorder = Recorder::default();
+    tree.traverse()
+        .breadthfirst(&mut recorder)
+        .context("Failed to traverse tree")?;
+
+    let mut totals = HashMap::<Language, u64>::new();
+
+    for entry in &recorder.records {
+        let excluded = EXCLUDING_ATTRIBUTES
+            .iter()
+            .any(|v| attributes.is_set(entry.filepath.as_bstr(), false, v));
+        if !entry.mode.is_blob() || excluded {
+            continue;
+        }
+
+        let Some(language) = Language::from_file_name(entry.filepath.to_path_lossy()) else {
+            continue;
+        };
+
+        *totals.entry(language).or_default() += repo.find_header(entry.oid)?.size();
+    }
+
+    let mut languages = totals
+        .into_iter()
+        .filter(|(_, bytes)| *bytes > 0)
+        .map(|(language, bytes)| LanguageBytes {
+            name: language.name().to_string(),
+            bytes,
+        })
+        .collect::<Vec<_>>();
+    languages.sort_unstable_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.name.cmp(&b.name)));
+
+    let other_bytes = languages
+        .drain(languages.len().min(MAX_LANGUAGES)..)
+        .map(|v| v.bytes)
+        .sum();
+
+    Ok(Some(LanguageBreakdown {
+        tree: tree_bytes,
+        languages,
+        other_bytes,
+    }))
+}
@@ -1,2 +1,3 @@
 pub mod indexer;
+mod languages;
 pub mod schema;
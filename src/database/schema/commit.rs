@@ -1,17 +1,19 @@
-use std::sync::Arc;
+use std::{collections::HashSet, sync::Arc};
 
 use anyhow::Context;
-use gix::{actor::SignatureRef, objs::CommitRef, ObjectId};
+use gix::{mailmap::Signature, objs::CommitRef, ObjectId};
 use rkyv::{Archive, Serialize};
-use rocksdb::{IteratorMode, ReadOptions, WriteBatch};
+use rocksdb::{IteratorMode, PrefixRange, WriteBatch};
 use time::{OffsetDateTime, UtcOffset};
 use tracing::debug;
 use yoke::{Yoke, Yokeable};
 
 use crate::database::schema::{
-    prefixes::{COMMIT_COUNT_FAMILY, COMMIT_FAMILY},
+    prefixes::{COMMIT_COUNT_FAMILY, COMMIT_FAMILY, COMMIT_OBJECT_FAMILY, COMMIT_TIME_FAMILY},
+    read_options,
     repository::RepositoryId,
-    Yoked,
+    stats::StatsTree,
+    Snapshot, Yoked,
 };
 
 #[derive(Serialize, Archive, Debug, Yokeable)]
@@ -24,26 +26,33 @@ pub struct Commit {
 }
 
 impl Commit {
+    /// Builds a commit for the index, `author` and `committer` are expected to have already been
+    /// normalised through the repository's mailmap.
     pub fn new(
         oid: ObjectId,
         commit: &CommitRef<'_>,
-        author: SignatureRef<'_>,
-        committer: SignatureRef<'_>,
-    ) -> Result<Self, anyhow::Error> {
+        author: Signature<'_>,
+        committer: Signature<'_>,
+    ) -> Self {
         let message = commit.message();
 
-        Ok(Self {
+        Self {
             summary: message.summary().to_string(),
             message: message.body.map(ToString::to_string).unwrap_or_default(),
-            committer: committer.try_into()?,
-            author: author.try_into()?,
+            committer: committer.into(),
+            author: author.into(),
             hash: match oid {
                 ObjectId::Sha1(d) => d,
             },
-        })
+        }
     }
 
-    pub fn insert(&self, tree: &CommitTree, id: u64, tx: &mut WriteBatch) -> anyhow::Result<()> {
+    pub fn insert(
+        &self,
+        tree: &CommitTree<'_>,
+        id: u64,
+        tx: &mut WriteBatch,
+    ) -> anyhow::Result<()> {
         tree.insert(id, self, tx)
     }
 }
@@ -57,63 +66,171 @@ pub struct Author {
 
 impl ArchivedAuthor {
     pub fn time(&self) -> OffsetDateTime {
-        OffsetDateTime::from_unix_timestamp(self.time.0.to_native())
-            .unwrap()
-            .to_offset(UtcOffset::from_whole_seconds(self.time.1.to_native()).unwrap())
+        let offset =
+            UtcOffset::from_whole_seconds(self.time.1.to_native()).unwrap_or(UtcOffset::UTC);
+        let utc = OffsetDateTime::from_unix_timestamp(self.time.0.to_native())
+            .unwrap_or(OffsetDateTime::UNIX_EPOCH);
+        utc.checked_to_offset(offset).unwrap_or(utc)
     }
 }
 
-impl TryFrom<SignatureRef<'_>> for Author {
-    type Error = anyhow::Error;
-
-    fn try_from(author: SignatureRef<'_>) -> Result<Self, anyhow::Error> {
-        Ok(Self {
+impl From<Signature<'_>> for Author {
+    fn from(author: Signature<'_>) -> Self {
+        Self {
             name: author.name.to_string(),
             email: author.email.to_string(),
             time: (author.time.seconds, author.time.offset),
-        })
+        }
     }
 }
 
-pub struct CommitTree {
+/// The ordered list of commits reachable from a single reference.
+///
+/// Commit bodies are shared between every reference in a repository and keyed by oid in
+/// [`COMMIT_OBJECT_FAMILY`], the tree itself only stores the oid of the commit at each index so
+/// history shared between many branches is only stored once.
+///
+/// Each reference has two generations of its tree, only one of which is active at a time. The
+/// active generation is resolved once when the tree is opened, so a reader sees the same
+/// generation for as long as it holds onto the tree. A forced reindex builds the inactive
+/// generation from scratch and [activates](Self::activate) it once it's complete, rather than
+/// dropping the commits readers are currently looking at.
+pub struct CommitTree<'a> {
     db: Arc<rocksdb::DB>,
+    snapshot: Option<&'a Snapshot<'a>>,
+    repository: RepositoryId,
+    /// Key of the active generation, shared by both generations of the reference.
+    reference_prefix: Box<[u8]>,
+    generation: u8,
     pub prefix: Box<[u8]>,
 }
 
 pub type YokedCommit = Yoked<&'static <Commit as Archive>::Archived>;
 
-impl CommitTree {
-    pub(super) fn new(db: Arc<rocksdb::DB>, repository: RepositoryId, reference: &str) -> Self {
-        let mut prefix = Vec::with_capacity(std::mem::size_of::<u64>() + reference.len() + 1);
-        prefix.extend_from_slice(&repository.to_be_bytes());
-        prefix.extend_from_slice(reference.as_bytes());
-        prefix.push(b'\0');
+impl<'a> CommitTree<'a> {
+    pub(super) fn new(
+        db: Arc<rocksdb::DB>,
+        snapshot: Option<&'a Snapshot<'a>>,
+        repository: RepositoryId,
+        reference: &str,
+    ) -> anyhow::Result<Self> {
+        let mut reference_prefix =
+            Vec::with_capacity(std::mem::size_of::<u64>() + reference.len() + 1);
+        reference_prefix.extend_from_slice(&repository.to_be_bytes());
+        reference_prefix.extend_from_slice(reference.as_bytes());
+        reference_prefix.push(b'\0');
+
+        let cf = db
+            .cf_handle(COMMIT_COUNT_FAMILY)
+            .context("missing column family")?;
+        let generation =
+            match db.get_pinned_cf_opt(cf, &reference_prefix, &read_options(snapshot))? {
+                Some(v) => *v.first().context("invalid commit tree generation")?,
+                None => 0,
+            };
+
+        Ok(Self::with_generation(
+            db,
+            snapshot,
+            repository,
+            reference_prefix.into_boxed_slice(),
+            generation,
+        ))
+    }
+
+    fn with_generation(
+        db: Arc<rocksdb::DB>,
+        snapshot: Option<&'a Snapshot<'a>>,
+        repository: RepositoryId,
+        reference_prefix: Box<[u8]>,
+        generation: u8,
+    ) -> Self {
+        let mut prefix = Vec::with_capacity(reference_prefix.len() + 1);
+        prefix.extend_from_slice(&reference_prefix);
+        prefix.push(generation);
 
         Self {
             db,
+            snapshot,
+            repository,
+            reference_prefix,
+            generation,
             prefix: prefix.into_boxed_slice(),
         }
     }
 
+    /// The generation of this reference's tree that isn't currently active, which is where
+    /// forced reindexes are built.
+    pub fn inactive(&self) -> Self {
+        Self::with_generation(
+            self.db.clone(),
+            self.snapshot,
+            self.repository,
+            self.reference_prefix.clone(),
+            self.generation ^ 1,
+        )
+    }
+
+    /// Makes this generation the one readers see from now on. Readers that already opened the
+    /// tree carry on reading from the generation they resolved, so the previously active one has
+    /// to be left in place until they're done with it.
+    pub fn activate(&self, tx: &mut WriteBatch) -> anyhow::Result<()> {
+        let cf = self
+            .db
+            .cf_handle(COMMIT_COUNT_FAMILY)
+            .context("missing column family")?;
+
+        tx.put_cf(cf, &self.reference_prefix, [self.generation]);
+
+        Ok(())
+    }
+
+    /// Drops this reference's commits, across both generations. Commit bodies are shared with
+    /// other references so are left in place, see [`drop_unreferenced_objects`].
     pub fn drop_commits(&self) -> anyhow::Result<()> {
-        let mut to = self.prefix.clone();
+        self.drop_range(&self.reference_prefix)
+    }
+
+    /// Drops only this generation of the reference's tree, which shouldn't be the active one.
+    /// Commit bodies are left in place, like with [`Self::drop_commits`].
+    pub fn drop_generation(&self) -> anyhow::Result<()> {
+        self.drop_range(&self.prefix)
+    }
+
+    fn drop_range(&self, from: &[u8]) -> anyhow::Result<()> {
+        let mut to = from.to_vec();
         *to.last_mut().unwrap() += 1;
 
         let commit_cf = self
             .db
             .cf_handle(COMMIT_FAMILY)
             .context("commit column family missing")?;
-        self.db.delete_range_cf(commit_cf, &self.prefix, &to)?;
+        self.db.delete_range_cf(commit_cf, from, &to)?;
 
+        let commit_time_cf = self
+            .db
+            .cf_handle(COMMIT_TIME_FAMILY)
+            .context("commit time column family missing")?;
+        self.db.delete_range_cf(commit_time_cf, from, &to)?;
+
+        // the counters are keyed by generation and sort after the key of the active generation,
+        // so dropping the whole reference takes all three with it
         let commit_count_cf = self
             .db
             .cf_handle(COMMIT_COUNT_FAMILY)
             .context("missing column family")?;
-        self.db.delete_cf(commit_count_cf, &self.prefix)?;
+        self.db.delete_range_cf(commit_count_cf, from, &to)?;
+
+        StatsTree::new(self.db.clone(), None, from.into()).drop_stats()?;
 
         Ok(())
     }
 
+    /// Contributor and activity totals for this tree's commits.
+    pub fn stats(&self) -> StatsTree<'a> {
+        StatsTree::new(self.db.clone(), self.snapshot, self.prefix.clone())
+    }
+
     pub fn update_counter(&self, count: u64, tx: &mut WriteBatch) -> anyhow::Result<()> {
         let cf = self
             .db
@@ -131,7 +248,10 @@ impl CommitTree {
             .cf_handle(COMMIT_COUNT_FAMILY)
             .context("missing column family")?;
 
-        let Some(res) = self.db.get_pinned_cf(cf, &self.prefix)? else {
+        let Some(res) =
+            self.db
+                .get_pinned_cf_opt(cf, &self.prefix, &read_options(self.snapshot))?
+        else {
             return Ok(0);
         };
 
@@ -140,6 +260,19 @@ impl CommitTree {
     }
 
     fn insert(&self, id: u64, commit: &Commit, tx: &mut WriteBatch) -> anyhow::Result<()> {
+        let object_cf = self
+            .db
+            .cf_handle(COMMIT_OBJECT_FAMILY)
+            .context("missing column family")?;
+
+        // the same commit may already have been written by another reference, in which case
+        // this overwrites it with an identical value which is cheaper than checking first
+        tx.put_cf(
+            object_cf,
+            self.object_key(&commit.hash),
+            rkyv::to_bytes::<rkyv::rancor::Error>(commit)?,
+        );
+
         let cf = self
             .db
             .cf_handle(COMMIT_FAMILY)
@@ -148,11 +281,63 @@ impl CommitTree {
         let mut key = self.prefix.to_vec();
         key.extend_from_slice(&id.to_be_bytes());
 
-        tx.put_cf(cf, key, rkyv::to_bytes::<rkyv::rancor::Error>(commit)?);
+        tx.put_cf(cf, key, commit.hash);
+
+        // secondary index allowing commits to be looked up by committer time without having to
+        // deserialise every commit on the branch, this costs an extra ~50 byte write per commit
+        let time_cf = self
+            .db
+            .cf_handle(COMMIT_TIME_FAMILY)
+            .context("missing column family")?;
+
+        let mut time_key = self.time_key(commit.committer.time.0);
+        time_key.extend_from_slice(&commit.hash);
+
+        tx.put_cf(time_cf, time_key, id.to_be_bytes());
 
         Ok(())
     }
 
+    /// Builds a key into the commit time index for this tree, the timestamp has its sign bit
+    /// flipped so commits before the epoch still sort correctly as big-endian bytes.
+    fn time_key(&self, timestamp: i64) -> Vec<u8> {
+        #[allow(clippy::cast_sign_loss)]
+        let timestamp = (timestamp as u64) ^ (1 << 63);
+
+        let mut key = self.prefix.to_vec();
+        key.extend_from_slice(&timestamp.to_be_bytes());
+        key
+    }
+
+    fn object_key(&self, oid: &[u8]) -> Vec<u8> {
+        let mut key = Vec::with_capacity(std::mem::size_of::<u64>() + oid.len());
+        key.extend_from_slice(&self.repository.to_be_bytes());
+        key.extend_from_slice(oid);
+        key
+    }
+
+    /// Looks up the shared commit bodies for the given oids, preserving order.
+    fn fetch_objects<O: AsRef<[u8]>>(&self, oids: &[O]) -> Result<Vec<YokedCommit>, anyhow::Error> {
+        let cf = self
+            .db
+            .cf_handle(COMMIT_OBJECT_FAMILY)
+            .context("missing column family")?;
+
+        self.db
+            .multi_get_cf_opt(
+                oids.iter().map(|oid| (cf, self.object_key(oid.as_ref()))),
+                &read_options(self.snapshot),
+            )
+            .into_iter()
+            .filter_map(Result::transpose)
+            .map(|v| {
+                Yoke::try_attach_to_cart(Box::from(v.context("failed to read commit")?), |data| {
+                    rkyv::access::<_, rkyv::rancor::Error>(data).context("failed to deserialize")
+                })
+            })
+            .collect()
+    }
+
     pub fn fetch_latest_one(&self) -> Result<Option<YokedCommit>, anyhow::Error> {
         let mut key = self.prefix.to_vec();
         key.extend_from_slice(&(self.len()?.saturating_sub(1)).to_be_bytes());
@@ -162,15 +347,11 @@ impl CommitTree {
             .cf_handle(COMMIT_FAMILY)
             .context("missing column family")?;
 
-        let Some(value) = self.db.get_cf(cf, key)? else {
+        let Some(oid) = self.db.get_cf_opt(cf, key, &read_options(self.snapshot))? else {
             return Ok(None);
         };
 
-        Yoke::try_attach_to_cart(Box::from(value), |value| {
-            rkyv::access::<_, rkyv::rancor::Error>(value)
-        })
-        .context("Failed to deserialize commit")
-        .map(Some)
+        Ok(self.fetch_objects(&[oid])?.pop())
     }
 
     pub fn fetch_latest(
@@ -197,18 +378,221 @@ impl CommitTree {
         let mut end_key = self.prefix.to_vec();
         end_key.extend_from_slice(&(latest_commit_id.saturating_sub(offset)).to_be_bytes());
 
-        let mut opts = ReadOptions::default();
+        let mut opts = read_options(self.snapshot);
         opts.set_iterate_range(start_key.as_slice()..end_key.as_slice());
 
         opts.set_prefix_same_as_start(true);
 
-        self.db
+        let oids = self
+            .db
             .iterator_cf_opt(cf, opts, IteratorMode::End)
+            .map(|v| Ok(v.context("failed to read commit")?.1))
+            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+
+        self.fetch_objects(&oids)
+    }
+
+    /// Fetches the latest `amount` commits committed between `since` and `until` (both
+    /// inclusive unix timestamps), newest first.
+    pub fn fetch_between(
+        &self,
+        since: Option<i64>,
+        until: Option<i64>,
+        amount: u64,
+        offset: u64,
+    ) -> Result<Vec<YokedCommit>, anyhow::Error> {
+        let time_cf = self
+            .db
+            .cf_handle(COMMIT_TIME_FAMILY)
+            .context("missing column family")?;
+
+        let start_key = self.time_key(since.unwrap_or(i64::MIN));
+        let end_key = if let Some(until) = until.and_then(|v| v.checked_add(1)) {
+            self.time_key(until)
+        } else {
+            let mut to = self.prefix.to_vec();
+            *to.last_mut().unwrap() += 1;
+            to
+        };
+
+        let mut opts = read_options(self.snapshot);
+        opts.set_iterate_range(start_key.as_slice()..end_key.as_slice());
+
+        // the oid is the tail of the time index key, so we can go straight to the commit body
+        let oids = self
+            .db
+            .iterator_cf_opt(time_cf, opts, IteratorMode::End)
+            .skip(usize::try_from(offset)?)
+            .take(usize::try_from(amount)?)
             .map(|v| {
-                Yoke::try_attach_to_cart(v.context("failed to read commit")?.1, |data| {
-                    rkyv::access::<_, rkyv::rancor::Error>(data).context("failed to deserialize")
-                })
+                let key = v.context("failed to read commit time index")?.0;
+                let oid_start = key
+                    .len()
+                    .checked_sub(20)
+                    .context("commit time index key too short")?;
+                Ok(key[oid_start..].to_vec())
             })
-            .collect::<Result<Vec<_>, anyhow::Error>>()
+            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+
+        self.fetch_objects(&oids)
+    }
+
+    /// Finds the commits either side of `oid` in this tree, returning `None` if the commit
+    /// isn't reachable from this reference.
+    ///
+    /// The commit's own index is found through the time index, using the committer time from
+    /// the shared commit body, so this is a handful of point lookups rather than a scan.
+    pub fn neighbors(&self, oid: &[u8; 20]) -> Result<Option<CommitNeighbors>, anyhow::Error> {
+        let Some(commit) = self.fetch_objects(&[oid])?.pop() else {
+            return Ok(None);
+        };
+
+        let time_cf = self
+            .db
+            .cf_handle(COMMIT_TIME_FAMILY)
+            .context("missing column family")?;
+
+        let mut time_key = self.time_key(commit.get().committer.time.0.to_native());
+        time_key.extend_from_slice(oid);
+
+        let Some(id) =
+            self.db
+                .get_pinned_cf_opt(time_cf, time_key, &read_options(self.snapshot))?
+        else {
+            return Ok(None);
+        };
+        let id = u64::from_be_bytes(id.as_ref().try_into()?);
+
+        let cf = self
+            .db
+            .cf_handle(COMMIT_FAMILY)
+            .context("missing column family")?;
+
+        let key = |id: u64| {
+            let mut key = self.prefix.to_vec();
+            key.extend_from_slice(&id.to_be_bytes());
+            key
+        };
+
+        let mut neighbors = self
+            .db
+            .multi_get_cf_opt(
+                [id.checked_sub(1), id.checked_add(1)]
+                    .into_iter()
+                    .flatten()
+                    .map(|id| (cf, key(id))),
+                &read_options(self.snapshot),
+            )
+            .into_iter()
+            .map(|v| {
+                v?.map(|oid| <[u8; 20]>::try_from(oid.as_slice()))
+                    .transpose()
+                    .context("invalid oid in commit tree")
+            });
+
+        let previous = if id == 0 {
+            None
+        } else {
+            neighbors.next().transpose()?.flatten()
+        };
+        let next = neighbors.next().transpose()?.flatten();
+
+        Ok(Some(CommitNeighbors { previous, next }))
+    }
+}
+
+/// Drops the bodies of every commit in the repository that's no longer in any reference's tree.
+/// This walks every tree in the repository, so is done once after an indexing pass has dropped
+/// trees rather than every time one is dropped.
+pub(super) fn drop_unreferenced_objects(
+    db: &rocksdb::DB,
+    repository: RepositoryId,
+) -> anyhow::Result<()> {
+    let repository = repository.to_be_bytes();
+
+    let commit_cf = db
+        .cf_handle(COMMIT_FAMILY)
+        .context("commit column family missing")?;
+    // the trees span many prefixes as far as the prefix extractor is concerned
+    let mut opts = rocksdb::ReadOptions::default();
+    opts.set_total_order_seek(true);
+    opts.set_iterate_range(PrefixRange(repository.as_slice()));
+
+    let mut referenced = HashSet::new();
+    for entry in db.iterator_cf_opt(commit_cf, opts, IteratorMode::Start) {
+        let (_, oid) = entry.context("failed to read commit tree")?;
+        referenced.insert(oid);
+    }
+
+    let object_cf = db
+        .cf_handle(COMMIT_OBJECT_FAMILY)
+        .context("commit object column family missing")?;
+    let mut opts = rocksdb::ReadOptions::default();
+    opts.set_iterate_range(PrefixRange(repository.as_slice()));
+
+    let mut tx = WriteBatch::default();
+    for entry in db.iterator_cf_opt(object_cf, opts, IteratorMode::Start) {
+        let (key, _) = entry.context("failed to read commit object")?;
+        if !referenced.contains(&key[repository.len()..]) {
+            tx.delete_cf(object_cf, key);
+        }
+    }
+
+    if !tx.is_empty() {
+        debug!(count = tx.len(), "Dropping unreferenced commit bodies");
+        db.write(tx)?;
+    }
+
+    Ok(())
+}
+
+/// The commits either side of a commit within a [`CommitTree`].
+#[derive(Debug, Default)]
+pub struct CommitNeighbors {
+    /// The commit indexed immediately before, ie. the older one
+    pub previous: Option<[u8; 20]>,
+    /// The commit indexed immediately after, ie. the newer one
+    pub next: Option<[u8; 20]>,
+}
+
+#[cfg(test)]
+mod tests {
+    use rocksdb::IteratorMode;
+
+    use crate::{
+        database::schema::prefixes::COMMIT_OBJECT_FAMILY,
+        test_util::{index, open_db, Fixture},
+    };
+
+    fn commit_bodies(db: &rocksdb::DB) -> usize {
+        let cf = db.cf_handle(COMMIT_OBJECT_FAMILY).unwrap();
+        db.iterator_cf(cf, IteratorMode::Start).count()
+    }
+
+    #[test]
+    fn commit_bodies_are_shared_and_dropped_with_their_last_reference() {
+        let mut fixture = Fixture::new();
+        for i in 0..3 {
+            fixture.write("file", i.to_string());
+            fixture.commit(&format!("commit {i}"));
+        }
+        fixture.git(&["branch", "shared"]);
+        fixture.git(&["checkout", "-q", "-b", "feature"]);
+        fixture.write("file", "feature");
+        fixture.commit("feature commit");
+        fixture.git(&["checkout", "-q", "main"]);
+
+        let (_dir, db) = open_db(fixture.scan_path());
+        index(fixture.scan_path(), &db);
+        assert_eq!(commit_bodies(&db), 4);
+
+        // the shared history is still referenced by main
+        fixture.git(&["branch", "-q", "-D", "shared"]);
+        index(fixture.scan_path(), &db);
+        assert_eq!(commit_bodies(&db), 4);
+
+        fixture.git(&["branch", "-q", "-D", "feature"]);
+        index(fixture.scan_path(), &db);
+        assert_eq!(commit_bodies(&db), 3);
     }
 }
@@ -1,12 +1,29 @@
 #![allow(clippy::module_name_repetitions)]
 
+use rocksdb::ReadOptions;
 use yoke::Yoke;
 
 pub mod commit;
 pub mod prefixes;
 pub mod repository;
+pub mod stats;
 pub mod tag;
 
 pub type Yoked<T> = Yoke<T, Box<[u8]>>;
 
-pub const SCHEMA_VERSION: &str = "3";
+/// A point-in-time view of the database. Reads made through a snapshot all see the database as
+/// it was when the snapshot was taken, so a page built from many reads is consistent even if the
+/// indexer writes in between them.
+pub type Snapshot<'a> = rocksdb::SnapshotWithThreadMode<'a, rocksdb::DB>;
+
+/// Options for reads that should go through `snapshot`, or see the latest state of the database
+/// if there isn't one.
+fn read_options(snapshot: Option<&Snapshot<'_>>) -> ReadOptions {
+    let mut opts = ReadOptions::default();
+    if let Some(snapshot) = snapshot {
+        opts.set_snapshot(snapshot);
+    }
+    opts
+}
+
+pub const SCHEMA_VERSION: &str = "12";
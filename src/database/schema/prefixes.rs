@@ -1,5 +1,8 @@
 pub const COMMIT_FAMILY: &str = "commit";
 pub const COMMIT_COUNT_FAMILY: &str = "commit_count";
+pub const COMMIT_TIME_FAMILY: &str = "commit_time";
+pub const COMMIT_OBJECT_FAMILY: &str = "commit_object";
+pub const COMMIT_STATS_FAMILY: &str = "commit_stats";
 pub const REPOSITORY_FAMILY: &str = "repository";
 pub const TAG_FAMILY: &str = "tag";
 pub const REFERENCE_FAMILY: &str = "repository_refs";
@@ -1,16 +1,26 @@
-use std::{collections::BTreeMap, ops::Deref, path::Path, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashSet},
+    ops::Deref,
+    path::Path,
+    sync::Arc,
+};
 
 use anyhow::{Context, Result};
 use rand::random;
 use rkyv::{Archive, Serialize};
 use rocksdb::IteratorMode;
+use xxhash_rust::const_xxh3::xxh3_64_with_seed;
 use yoke::{Yoke, Yokeable};
 
 use crate::database::schema::{
-    commit::CommitTree,
-    prefixes::{COMMIT_FAMILY, REFERENCE_FAMILY, REPOSITORY_FAMILY, TAG_FAMILY},
+    commit::{drop_unreferenced_objects, CommitTree},
+    prefixes::{
+        COMMIT_FAMILY, COMMIT_OBJECT_FAMILY, COMMIT_STATS_FAMILY, COMMIT_TIME_FAMILY,
+        REFERENCE_FAMILY, REPOSITORY_FAMILY, TAG_FAMILY,
+    },
+    read_options,
     tag::TagTree,
-    Yoked,
+    Snapshot, Yoked,
 };
 
 #[derive(Serialize, Archive, Debug, PartialEq, Eq, Hash, Yokeable)]
@@ -32,6 +42,33 @@ pub struct Repository {
     ///
     /// This is set to `true` based on the presence of `git-daemon-export-ok` in the repository
     pub exported: bool,
+    /// Base URL issue references (ie. `#123`) in commit messages link to, read from
+    /// `gitweb.issuesurl` in the repository configuration
+    pub issues_url: Option<String>,
+    /// The languages making up the default branch's tree, computed by the indexer
+    pub languages: Option<LanguageBreakdown>,
+    /// Whether the repository has no commits at all, ie. `HEAD` is unborn and there are no
+    /// other references to fall back to
+    pub empty: bool,
+}
+
+#[derive(Serialize, Archive, Debug, PartialEq, Eq, Hash)]
+pub struct LanguageBreakdown {
+    /// The tree the breakdown was computed from, so it's only recomputed once the default
+    /// branch moves on to a different tree
+    pub tree: [u8; 20],
+    /// The largest languages by bytes, largest first
+    pub languages: Vec<LanguageBytes>,
+    /// The total bytes of every language that didn't make it into `languages`
+    pub other_bytes: u64,
+}
+
+#[derive(Serialize, Archive, Debug, PartialEq, Eq, Hash)]
+pub struct LanguageBytes {
+    /// The name of the language (ie. `rust`)
+    pub name: String,
+    /// The total size of the language's files in bytes
+    pub bytes: u64,
 }
 
 pub type YokedRepository = Yoked<&'static <Repository as Archive>::Archived>;
@@ -78,6 +115,7 @@ impl Repository {
 
     pub fn open<P: AsRef<Path>>(
         database: &rocksdb::DB,
+        snapshot: Option<&Snapshot<'_>>,
         path: P,
     ) -> Result<Option<YokedRepository>> {
         let cf = database
@@ -85,7 +123,7 @@ impl Repository {
             .context("repository column family missing")?;
 
         let path = path.as_ref().to_str().context("invalid path")?;
-        let Some(value) = database.get_cf(cf, path)? else {
+        let Some(value) = database.get_cf_opt(cf, path, &read_options(snapshot))? else {
             return Ok(None);
         };
 
@@ -109,6 +147,21 @@ impl ArchivedRepository {
             .context("commit column family missing")?;
         database.delete_range_cf(commit_cf, start_id, end_id)?;
 
+        let commit_time_cf = database
+            .cf_handle(COMMIT_TIME_FAMILY)
+            .context("commit time column family missing")?;
+        database.delete_range_cf(commit_time_cf, start_id, end_id)?;
+
+        let commit_object_cf = database
+            .cf_handle(COMMIT_OBJECT_FAMILY)
+            .context("commit object column family missing")?;
+        database.delete_range_cf(commit_object_cf, start_id, end_id)?;
+
+        let commit_stats_cf = database
+            .cf_handle(COMMIT_STATS_FAMILY)
+            .context("commit stats column family missing")?;
+        database.delete_range_cf(commit_stats_cf, start_id, end_id)?;
+
         // delete tags
         let tag_cf = database
             .cf_handle(TAG_FAMILY)
@@ -125,12 +178,32 @@ impl ArchivedRepository {
         Ok(())
     }
 
-    pub fn commit_tree(&self, database: Arc<rocksdb::DB>, reference: &str) -> CommitTree {
-        CommitTree::new(database, RepositoryId(self.id.0.to_native()), reference)
+    pub fn commit_tree<'a>(
+        &self,
+        database: Arc<rocksdb::DB>,
+        snapshot: Option<&'a Snapshot<'a>>,
+        reference: &str,
+    ) -> Result<CommitTree<'a>> {
+        CommitTree::new(
+            database,
+            snapshot,
+            RepositoryId(self.id.0.to_native()),
+            reference,
+        )
+    }
+
+    /// Drops the bodies of commits that are no longer in any of this repository's commit trees,
+    /// which [`CommitTree::drop_commits`] and [`CommitTree::drop_generation`] leave behind.
+    pub fn drop_unreferenced_commits(&self, database: &rocksdb::DB) -> Result<()> {
+        drop_unreferenced_objects(database, RepositoryId(self.id.0.to_native()))
     }
 
-    pub fn tag_tree(&self, database: Arc<rocksdb::DB>) -> TagTree {
-        TagTree::new(database, RepositoryId(self.id.0.to_native()))
+    pub fn tag_tree<'a>(
+        &self,
+        database: Arc<rocksdb::DB>,
+        snapshot: Option<&'a Snapshot<'a>>,
+    ) -> TagTree<'a> {
+        TagTree::new(database, snapshot, RepositoryId(self.id.0.to_native()))
     }
 
     pub fn replace_heads(&self, database: &rocksdb::DB, new_heads: &Vec<String>) -> Result<()> {
@@ -151,12 +224,18 @@ impl ArchivedRepository {
     pub fn heads(
         &self,
         database: &rocksdb::DB,
+        snapshot: Option<&Snapshot<'_>>,
     ) -> Result<Option<Yoke<&'static ArchivedHeads, Box<[u8]>>>> {
         let cf = database
             .cf_handle(REFERENCE_FAMILY)
             .context("missing reference column family")?;
 
-        let Some(bytes) = database.get_cf(cf, self.id.0.to_native().to_be_bytes())? else {
+        let Some(bytes) = database.get_cf_opt(
+            cf,
+            self.id.0.to_native().to_be_bytes(),
+            &read_options(snapshot),
+        )?
+        else {
             return Ok(None);
         };
 
@@ -178,6 +257,35 @@ impl RepositoryId {
     pub fn new() -> Self {
         Self(random())
     }
+
+    /// Every ID that's currently assigned to an indexed repository, loaded once per indexing pass
+    /// to hand to [`Self::from_path`].
+    pub fn in_use(database: &rocksdb::DB) -> Result<HashSet<u64>> {
+        Ok(Repository::fetch_all(database)?
+            .into_values()
+            .map(|repository| repository.get().id.0.to_native())
+            .collect())
+    }
+
+    /// Derives the ID from the repository's path relative to the scan path, so the repository
+    /// keeps the same ID when the database is rebuilt. A hash that's already `in_use` is rehashed
+    /// with the next seed until a free one is found, which is then marked as in use.
+    ///
+    /// Which of two colliding repositories gets which ID depends on the order they're assigned
+    /// in, so callers assigning several at once should do so in path order.
+    pub fn from_path<P: AsRef<Path>>(path: P, in_use: &mut HashSet<u64>) -> Result<Self> {
+        let path = path.as_ref().to_str().context("invalid path")?;
+
+        let mut seed = 0;
+        loop {
+            let id = xxh3_64_with_seed(path.as_bytes(), seed);
+            if in_use.insert(id) {
+                return Ok(Self(id));
+            }
+
+            seed += 1;
+        }
+    }
 }
 
 impl Deref for RepositoryId {
@@ -187,3 +295,25 @@ impl Deref for RepositoryId {
         &self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use xxhash_rust::const_xxh3::xxh3_64_with_seed;
+
+    use super::RepositoryId;
+
+    #[test]
+    fn path_ids_are_rehashed_until_free() {
+        let mut in_use = HashSet::from([xxh3_64_with_seed(b"a.git", 0)]);
+
+        let first = RepositoryId::from_path("a.git", &mut in_use).unwrap();
+        assert_eq!(*first, xxh3_64_with_seed(b"a.git", 1));
+        assert!(in_use.contains(&*first));
+
+        // ids handed out earlier in the same pass count as taken without touching the database
+        let second = RepositoryId::from_path("a.git", &mut in_use).unwrap();
+        assert_eq!(*second, xxh3_64_with_seed(b"a.git", 2));
+    }
+}
@@ -0,0 +1,280 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use anyhow::Context;
+use rocksdb::{IteratorMode, WriteBatch};
+use time::OffsetDateTime;
+
+use crate::database::schema::{
+    commit::Commit, prefixes::COMMIT_STATS_FAMILY, read_options, Snapshot,
+};
+
+const SECONDS_PER_WEEK: i64 = 7 * 24 * 60 * 60;
+
+/// The number of weeks of activity returned, so a commit with a wildly wrong date can't stretch
+/// the activity out over centuries.
+const ACTIVITY_WEEKS: i64 = 52;
+
+/// Marks a key as a per-author commit count, followed by the author's email.
+const AUTHOR_KIND: u8 = b'a';
+/// Marks a key as a per-week commit count, followed by the week number.
+const WEEK_KIND: u8 = b'w';
+
+/// Contributor and activity totals for the commits of a single [`CommitTree`], kept up to date
+/// as commits are indexed so the stats page never has to scan the whole history.
+///
+/// [`CommitTree`]: crate::database::schema::commit::CommitTree
+pub struct StatsTree<'a> {
+    db: Arc<rocksdb::DB>,
+    snapshot: Option<&'a Snapshot<'a>>,
+    prefix: Box<[u8]>,
+}
+
+/// A contributor to a [`StatsTree`], identified by their (mailmapped) email.
+#[derive(Debug)]
+pub struct Contributor {
+    pub name: String,
+    pub email: String,
+    pub commits: u64,
+}
+
+/// Totals for a batch of newly indexed commits, yet to be merged into a [`StatsTree`].
+#[derive(Default)]
+pub struct PendingStats {
+    authors: HashMap<String, (String, u64)>,
+    weeks: BTreeMap<i64, u64>,
+}
+
+impl PendingStats {
+    /// Counts the commit towards its author and the week it was committed in. Commits are
+    /// indexed oldest first, so the most recently seen name for an email wins.
+    pub fn add(&mut self, commit: &Commit) {
+        let author = self.authors.entry(commit.author.email.clone()).or_default();
+        author.0.clone_from(&commit.author.name);
+        author.1 += 1;
+
+        *self
+            .weeks
+            .entry(commit.committer.time.0.div_euclid(SECONDS_PER_WEEK))
+            .or_default() += 1;
+    }
+}
+
+impl<'a> StatsTree<'a> {
+    pub(super) fn new(
+        db: Arc<rocksdb::DB>,
+        snapshot: Option<&'a Snapshot<'a>>,
+        prefix: Box<[u8]>,
+    ) -> Self {
+        Self {
+            db,
+            snapshot,
+            prefix,
+        }
+    }
+
+    fn key(&self, kind: u8, suffix: &[u8]) -> Vec<u8> {
+        let mut key = Vec::with_capacity(self.prefix.len() + 1 + suffix.len());
+        key.extend_from_slice(&self.prefix);
+        key.push(kind);
+        key.extend_from_slice(suffix);
+        key
+    }
+
+    /// Week numbers have their sign bit flipped so weeks before the epoch still sort correctly
+    /// as big-endian bytes.
+    #[allow(clippy::cast_sign_loss)]
+    fn week_key(&self, week: i64) -> Vec<u8> {
+        self.key(WEEK_KIND, &((week as u64) ^ (1 << 63)).to_be_bytes())
+    }
+
+    /// Adds the pending totals to the stored ones. This reads the current totals rather than
+    /// going through the tree's snapshot, so batches have to be written before the next one is
+    /// merged.
+    pub fn merge(&self, pending: PendingStats, tx: &mut WriteBatch) -> anyhow::Result<()> {
+        let cf = self
+            .db
+            .cf_handle(COMMIT_STATS_FAMILY)
+            .context("missing column family")?;
+
+        for (email, (name, commits)) in pending.authors {
+            let key = self.key(AUTHOR_KIND, email.as_bytes());
+            let existing = match self.db.get_pinned_cf(cf, &key)? {
+                Some(v) => decode_count(&v)?,
+                None => 0,
+            };
+
+            let mut value = (existing + commits).to_be_bytes().to_vec();
+            value.extend_from_slice(name.as_bytes());
+            tx.put_cf(cf, key, value);
+        }
+
+        for (week, commits) in pending.weeks {
+            let key = self.week_key(week);
+            let existing = match self.db.get_pinned_cf(cf, &key)? {
+                Some(v) => decode_count(&v)?,
+                None => 0,
+            };
+
+            tx.put_cf(cf, key, (existing + commits).to_be_bytes());
+        }
+
+        Ok(())
+    }
+
+    pub fn drop_stats(&self) -> anyhow::Result<()> {
+        let cf = self
+            .db
+            .cf_handle(COMMIT_STATS_FAMILY)
+            .context("missing column family")?;
+
+        let mut to = self.prefix.to_vec();
+        *to.last_mut().unwrap() += 1;
+        self.db.delete_range_cf(cf, &self.prefix, &to)?;
+
+        Ok(())
+    }
+
+    /// Every contributor to the tree, most commits first.
+    pub fn contributors(&self) -> anyhow::Result<Vec<Contributor>> {
+        let mut contributors = self
+            .scan(AUTHOR_KIND)?
+            .into_iter()
+            .map(|(email, value)| {
+                Ok(Contributor {
+                    name: String::from_utf8_lossy(value.get(8..).unwrap_or_default()).into_owned(),
+                    email: String::from_utf8_lossy(&email).into_owned(),
+                    commits: decode_count(&value)?,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        contributors
+            .sort_unstable_by(|a, b| b.commits.cmp(&a.commits).then_with(|| a.name.cmp(&b.name)));
+
+        Ok(contributors)
+    }
+
+    /// The number of commits made in each of the last [`ACTIVITY_WEEKS`] weeks up to the most
+    /// recent commit, including weeks without any, alongside the unix timestamp the first week
+    /// starts at. Commits dated in the future are left out.
+    pub fn weekly_activity(&self) -> anyhow::Result<(i64, Vec<u64>)> {
+        let weeks = self
+            .scan(WEEK_KIND)?
+            .into_iter()
+            .map(|(week, value)| {
+                let week: [u8; 8] = week.as_slice().try_into()?;
+                #[allow(clippy::cast_possible_wrap)]
+                let week = (u64::from_be_bytes(week) ^ (1 << 63)) as i64;
+                Ok((week, decode_count(&value)?))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let current_week = OffsetDateTime::now_utc()
+            .unix_timestamp()
+            .div_euclid(SECONDS_PER_WEEK);
+
+        bucket_weeks(&weeks, current_week)
+    }
+
+    /// Returns the key suffixes & values of every entry of the given kind.
+    fn scan(&self, kind: u8) -> anyhow::Result<Vec<(Vec<u8>, Box<[u8]>)>> {
+        let cf = self
+            .db
+            .cf_handle(COMMIT_STATS_FAMILY)
+            .context("missing column family")?;
+
+        let start = self.key(kind, &[]);
+        let end = self.key(kind + 1, &[]);
+
+        let mut opts = read_options(self.snapshot);
+        opts.set_iterate_range(start.as_slice()..end.as_slice());
+
+        self.db
+            .iterator_cf_opt(cf, opts, IteratorMode::Start)
+            .map(|v| {
+                let (key, value) = v.context("failed to read commit stats")?;
+                Ok((key[start.len()..].to_vec(), value))
+            })
+            .collect()
+    }
+}
+
+/// Lays out the sparse per-week counts, sorted by week, into a dense window of at most
+/// [`ACTIVITY_WEEKS`] weeks ending at the most recent week that isn't after `current_week`.
+fn bucket_weeks(weeks: &[(i64, u64)], current_week: i64) -> anyhow::Result<(i64, Vec<u64>)> {
+    let Some(last) = weeks
+        .iter()
+        .rev()
+        .map(|(week, _)| *week)
+        .find(|week| *week <= current_week)
+    else {
+        return Ok((0, Vec::new()));
+    };
+
+    let first = weeks
+        .iter()
+        .map(|(week, _)| *week)
+        .find(|week| *week > last - ACTIVITY_WEEKS)
+        .unwrap_or(last);
+
+    let mut activity = vec![0; usize::try_from(last - first)? + 1];
+    for (week, commits) in weeks {
+        if (first..=last).contains(week) {
+            activity[usize::try_from(week - first)?] = *commits;
+        }
+    }
+
+    Ok((first * SECONDS_PER_WEEK, activity))
+}
+
+fn decode_count(value: &[u8]) -> anyhow::Result<u64> {
+    let count: [u8; 8] = value
+        .get(..8)
+        .context("commit stats value too short")?
+        .try_into()?;
+    Ok(u64::from_be_bytes(count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bucket_weeks, ACTIVITY_WEEKS, SECONDS_PER_WEEK};
+
+    #[test]
+    fn activity_fills_in_empty_weeks() {
+        let (start, activity) = bucket_weeks(&[(100, 2), (103, 5)], 110).unwrap();
+
+        assert_eq!(start, 100 * SECONDS_PER_WEEK);
+        assert_eq!(activity, [2, 0, 0, 5]);
+    }
+
+    #[test]
+    fn activity_is_clamped_to_a_window() {
+        // a commit dated 1970 and another dated in the year 9999
+        let epoch = 0;
+        let far_future = 253_402_300_799 / SECONDS_PER_WEEK;
+        let weeks = [(epoch, 1), (2800, 3), (2810, 4), (far_future, 1)];
+
+        let (start, activity) = bucket_weeks(&weeks, 2820).unwrap();
+
+        assert_eq!(activity.len(), 11);
+        assert_eq!(start, 2800 * SECONDS_PER_WEEK);
+        assert_eq!(activity.first(), Some(&3));
+        assert_eq!(activity.last(), Some(&4));
+
+        let (_, activity) = bucket_weeks(&[(0, 1), (2800, 1)], 2820).unwrap();
+        assert_eq!(activity.len(), 1);
+
+        let weeks: Vec<_> = (0..200).map(|week| (2000 + week, 1)).collect();
+        let (_, activity) = bucket_weeks(&weeks, 2820).unwrap();
+        assert_eq!(activity.len(), usize::try_from(ACTIVITY_WEEKS).unwrap());
+    }
+
+    #[test]
+    fn activity_only_in_the_future_is_empty() {
+        assert_eq!(bucket_weeks(&[(5000, 1)], 2820).unwrap(), (0, Vec::new()));
+        assert_eq!(bucket_weeks(&[], 2820).unwrap(), (0, Vec::new()));
+    }
+}
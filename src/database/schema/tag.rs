@@ -1,45 +1,67 @@
 use std::{collections::HashSet, sync::Arc};
 
 use anyhow::Context;
-use gix::actor::SignatureRef;
+use gix::mailmap::Signature;
 use rkyv::{Archive, Serialize};
+use rocksdb::{Direction, IteratorMode};
 use yoke::{Yoke, Yokeable};
 
 use crate::database::schema::{
     commit::{ArchivedAuthor, Author},
     prefixes::TAG_FAMILY,
+    read_options,
     repository::RepositoryId,
-    Yoked,
+    Snapshot, Yoked,
 };
 
 #[derive(Serialize, Archive, Debug, Yokeable)]
 pub struct Tag {
+    /// Whether the tag is a tag object rather than a reference straight to a commit, annotated
+    /// tags don't necessarily have a tagger
+    pub annotated: bool,
     pub tagger: Option<Author>,
 }
 
 impl Tag {
-    pub fn new(tagger: Option<SignatureRef<'_>>) -> Result<Self, anyhow::Error> {
-        Ok(Self {
-            tagger: tagger.map(TryFrom::try_from).transpose()?,
-        })
+    pub fn annotated(tagger: Option<Signature<'_>>) -> Self {
+        Self {
+            annotated: true,
+            tagger: tagger.map(Author::from),
+        }
     }
 
-    pub fn insert(&self, batch: &TagTree, name: &str) -> Result<(), anyhow::Error> {
+    pub fn lightweight() -> Self {
+        Self {
+            annotated: false,
+            tagger: None,
+        }
+    }
+
+    pub fn insert(&self, batch: &TagTree<'_>, name: &str) -> Result<(), anyhow::Error> {
         batch.insert(name, self)
     }
 }
 
-pub struct TagTree {
+pub struct TagTree<'a> {
     db: Arc<rocksdb::DB>,
+    snapshot: Option<&'a Snapshot<'a>>,
     prefix: RepositoryId,
 }
 
 pub type YokedString = Yoked<&'static str>;
 pub type YokedTag = Yoked<&'static <Tag as Archive>::Archived>;
 
-impl TagTree {
-    pub(super) fn new(db: Arc<rocksdb::DB>, prefix: RepositoryId) -> Self {
-        Self { db, prefix }
+impl<'a> TagTree<'a> {
+    pub(super) fn new(
+        db: Arc<rocksdb::DB>,
+        snapshot: Option<&'a Snapshot<'a>>,
+        prefix: RepositoryId,
+    ) -> Self {
+        Self {
+            db,
+            snapshot,
+            prefix,
+        }
     }
 
     pub fn insert(&self, name: &str, value: &Tag) -> anyhow::Result<()> {
@@ -70,6 +92,18 @@ impl TagTree {
         Ok(())
     }
 
+    /// Iterates over every tag of the repository, in name order.
+    fn iter(&self, cf: &rocksdb::ColumnFamily) -> rocksdb::DBIterator<'_> {
+        let mut opts = read_options(self.snapshot);
+        opts.set_prefix_same_as_start(true);
+
+        self.db.iterator_cf_opt(
+            cf,
+            opts,
+            IteratorMode::From(&self.prefix.to_be_bytes(), Direction::Forward),
+        )
+    }
+
     pub fn list(&self) -> anyhow::Result<HashSet<String>> {
         let cf = self
             .db
@@ -77,8 +111,7 @@ impl TagTree {
             .context("missing tag column family")?;
 
         Ok(self
-            .db
-            .prefix_iterator_cf(cf, self.prefix.to_be_bytes())
+            .iter(cf)
             .filter_map(Result::ok)
             .filter_map(|(k, _)| {
                 Some(
@@ -96,8 +129,7 @@ impl TagTree {
             .context("missing tag column family")?;
 
         let mut res = self
-            .db
-            .prefix_iterator_cf(cf, self.prefix.to_be_bytes())
+            .iter(cf)
             .filter_map(Result::ok)
             .filter_map(|(name, value)| {
                 let name = Yoke::try_attach_to_cart(name, |data| {
@@ -129,3 +161,56 @@ impl TagTree {
         Ok(res)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        database::schema::repository::Repository,
+        test_util::{index, open_db, Fixture},
+    };
+
+    #[test]
+    fn tag_kind_is_stored_rather_than_inferred_from_the_tagger() {
+        let mut fixture = Fixture::new();
+        let commit = fixture.commit("initial");
+        fixture.git(&["tag", "lightweight"]);
+        fixture.git(&["tag", "-a", "-m", "annotated", "annotated"]);
+        // git itself always writes a tagger, but older tools didn't
+        let tag = fixture.write_raw_object(
+            "tag",
+            format!("object {commit}\ntype commit\ntag no-tagger\n\nno tagger\n").as_bytes(),
+        );
+        fixture.git(&["update-ref", "refs/tags/no-tagger", &tag]);
+
+        let (_dir, db) = open_db(fixture.scan_path());
+        index(fixture.scan_path(), &db);
+
+        let repository = Repository::open(&db, None, fixture.relative_path())
+            .unwrap()
+            .unwrap();
+        let mut tags: Vec<_> = repository
+            .get()
+            .tag_tree(db.clone(), None)
+            .fetch_all()
+            .unwrap()
+            .iter()
+            .map(|(name, tag)| {
+                (
+                    name.get().to_string(),
+                    tag.get().annotated,
+                    tag.get().tagger.is_some(),
+                )
+            })
+            .collect();
+        tags.sort();
+
+        assert_eq!(
+            tags,
+            [
+                ("annotated".to_string(), true, true),
+                ("lightweight".to_string(), false, false),
+                ("no-tagger".to_string(), true, false),
+            ]
+        );
+    }
+}
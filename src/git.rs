@@ -8,24 +8,27 @@ use gix::{
     bstr::{BStr, BString, ByteSlice, ByteVec},
     diff::blob::{platform::prepare_diff::Operation, Sink},
     object::{tree::EntryKind, Kind},
-    objs::{tree::EntryRef, CommitRef, TagRef},
+    objs::{commit::MessageRef, tree::EntryRef, CommitRef, TagRef},
     prelude::TreeEntryRefExt,
-    traverse::tree::visit::Action,
+    refs::Category,
+    traverse::{commit::simple::CommitTimeOrder, tree::visit::Action},
     url::Scheme,
     ObjectId, ThreadSafeRepository, Url,
 };
 use itertools::{Either, Itertools};
 use moka::future::Cache;
+use serde::{Deserialize, Serialize};
 use std::{
     borrow::Cow,
-    collections::{BTreeMap, VecDeque},
+    collections::{BTreeMap, HashSet, VecDeque},
     ffi::OsStr,
     fmt::{self, Arguments, Write},
     io::ErrorKind,
     iter::Copied,
     path::{Path, PathBuf},
+    process::Stdio,
     str::FromStr,
-    sync::Arc,
+    sync::{Arc, LazyLock},
     time::Duration,
 };
 use tar::Builder;
@@ -34,15 +37,20 @@ use tracing::{error, instrument, warn};
 use yoke::{Yoke, Yokeable};
 
 use crate::{
+    gitattributes::GitAttributes,
     methods::filters::DisplayHexBuffer,
-    syntax_highlight::{format_file, format_file_inner, ComrakHighlightAdapter, FileIdentifier},
+    syntax_highlight::{
+        format_file, format_file_inner, format_file_plain, highlight_lines, ComrakHighlightAdapter,
+        FileIdentifier,
+    },
     unified_diff_builder::{Callback, UnifiedDiffBuilder},
 };
 
 type ReadmeCacheKey = (PathBuf, Option<Arc<str>>);
 
 pub struct Git {
-    commits: Cache<(ObjectId, bool), Arc<Commit>, hashbrown::hash_map::DefaultHashBuilder>,
+    commits:
+        Cache<(ObjectId, bool, DiffOptions), Arc<Commit>, hashbrown::hash_map::DefaultHashBuilder>,
     readme_cache: Cache<
         ReadmeCacheKey,
         Option<(ReadmeFormat, Arc<str>)>,
@@ -50,11 +58,58 @@ pub struct Git {
     >,
     open_repositories:
         Cache<PathBuf, ThreadSafeRepository, hashbrown::hash_map::DefaultHashBuilder>,
+    /// Repositories given via `--pin-repos`, which are kept open indefinitely once opened rather
+    /// than expiring from `open_repositories` when they've been idle for a while
+    pinned_repositories:
+        Cache<PathBuf, ThreadSafeRepository, hashbrown::hash_map::DefaultHashBuilder>,
+    pinned_paths: HashSet<PathBuf>,
+    signatures: Cache<ObjectId, SignatureStatus, hashbrown::hash_map::DefaultHashBuilder>,
+    signature_verification: Option<SignatureVerification>,
+    contained_refs:
+        Cache<(PathBuf, ObjectId), Arc<ContainedRefs>, hashbrown::hash_map::DefaultHashBuilder>,
+    contained_refs_max_commits: Option<usize>,
+    show_notes: bool,
+    file_limits: FileLimits,
+    lfs_store: Option<PathBuf>,
+    diff_algorithm: Option<DiffAlgorithm>,
+    /// Bytes of decompressed objects each request keeps around for reuse
+    object_cache_size: Option<usize>,
+}
+
+/// Settings for [`Git`], the default leaves every optional feature turned off.
+#[derive(Debug, Default)]
+pub struct GitConfig {
+    /// How commit signatures are verified, `None` if `--verify-signatures` isn't set
+    pub signature_verification: Option<SignatureVerification>,
+    pub file_limits: FileLimits,
+    /// Directory LFS objects are served from, set via `--lfs-store`
+    pub lfs_store: Option<PathBuf>,
+    /// Maximum number of commits walked to find the references containing a commit, `None` if
+    /// they aren't shown at all
+    pub contained_refs_max_commits: Option<usize>,
+    pub show_notes: bool,
+    /// The diff algorithm used unless a request asks for another, `None` for gix's default
+    pub diff_algorithm: Option<DiffAlgorithm>,
+    /// Bytes of decompressed objects each request keeps around for reuse
+    pub object_cache_size: Option<usize>,
+    /// Repositories kept open indefinitely once opened, set via `--pin-repos`
+    pub pinned_paths: HashSet<PathBuf>,
 }
 
 impl Git {
     #[instrument]
-    pub fn new() -> Self {
+    pub fn new(config: GitConfig) -> Self {
+        let GitConfig {
+            signature_verification,
+            file_limits,
+            lfs_store,
+            contained_refs_max_commits,
+            show_notes,
+            diff_algorithm,
+            object_cache_size,
+            pinned_paths,
+        } = config;
+
         Self {
             commits: Cache::builder()
                 .time_to_live(Duration::from_secs(30))
@@ -68,6 +123,27 @@ impl Git {
                 .time_to_idle(Duration::from_secs(120))
                 .max_capacity(100)
                 .build_with_hasher(hashbrown::hash_map::DefaultHashBuilder::default()),
+            pinned_repositories: Cache::builder()
+                .build_with_hasher(hashbrown::hash_map::DefaultHashBuilder::default()),
+            pinned_paths,
+            // objects are immutable, so the only thing that can change a result is the keyring
+            // being updated underneath us
+            signatures: Cache::builder()
+                .time_to_live(Duration::from_secs(600))
+                .max_capacity(10_000)
+                .build_with_hasher(hashbrown::hash_map::DefaultHashBuilder::default()),
+            signature_verification,
+            // references move on push, so this only saves repeated walks from refreshes
+            contained_refs: Cache::builder()
+                .time_to_live(Duration::from_secs(30))
+                .max_capacity(100)
+                .build_with_hasher(hashbrown::hash_map::DefaultHashBuilder::default()),
+            contained_refs_max_commits,
+            show_notes,
+            file_limits,
+            lfs_store,
+            diff_algorithm,
+            object_cache_size,
         }
     }
 }
@@ -79,9 +155,14 @@ impl Git {
         repo_path: PathBuf,
         branch: Option<Arc<str>>,
     ) -> Result<Arc<OpenRepository>> {
+        let cache = if self.pinned_paths.contains(&repo_path) {
+            &self.pinned_repositories
+        } else {
+            &self.open_repositories
+        };
+
         let repo = repo_path.clone();
-        let repo = self
-            .open_repositories
+        let repo = cache
             .try_get_with_by_ref(&repo_path, async move {
                 tokio::task::spawn_blocking(move || {
                     gix::open::Options::isolated()
@@ -115,68 +196,123 @@ pub struct OpenRepository {
 }
 
 impl OpenRepository {
+    /// Opens the repository for use on the current thread, with an object cache so objects read
+    /// repeatedly within a request (such as big trees) are only inflated once.
+    fn thread_local(&self) -> gix::Repository {
+        let mut repo = self.repo.to_thread_local();
+        repo.object_cache_size(self.git.object_cache_size);
+        repo
+    }
+
+    /// Looks up the given path in the tree, returning it alongside the id of the commit (or tree,
+    /// if one was given) it was resolved from so that callers can link to it immutably.
     #[allow(clippy::too_many_lines)]
     pub async fn path(
         self: Arc<Self>,
         path: Option<PathBuf>,
         tree_id: Option<&str>,
         formatted: bool,
-    ) -> Result<PathDestination> {
+    ) -> Result<(PathDestination, ObjectId)> {
         let tree_id = tree_id
             .map(ObjectId::from_str)
             .transpose()
-            .context("Failed to parse tree hash")?;
+            .map_err(|_| NotFound("Invalid tree id"))?;
 
         tokio::task::spawn_blocking(move || {
-            let repo = self.repo.to_thread_local();
-
-            let mut tree = if let Some(tree_id) = tree_id {
-                repo.find_tree(tree_id)
-                    .context("Couldn't find tree with given id")?
-            } else if let Some(branch) = &self.branch {
-                repo.find_reference(branch.as_ref())?
-                    .peel_to_tree()
-                    .context("Couldn't find tree for reference")?
+            let repo = self.thread_local();
+
+            let (mut tree, resolved_id) = if let Some(tree_id) = tree_id {
+                (find_tree(&repo, tree_id)?, tree_id)
             } else {
-                repo.find_reference("HEAD")
-                    .context("Failed to find HEAD")?
-                    .peel_to_tree()
-                    .context("Couldn't find HEAD for reference")?
+                let commit = if let Some(branch) = &self.branch {
+                    find_reference(&repo, branch)?
+                        .peel_to_commit()
+                        .context("Couldn't find commit for reference")?
+                } else {
+                    repo.find_reference("HEAD")
+                        .context("Failed to find HEAD")?
+                        .peel_to_commit()
+                        .context("Couldn't find HEAD for reference")?
+                };
+
+                let tree = commit.tree().context("Couldn't find tree for commit")?;
+                (tree, commit.id)
             };
 
             if let Some(path) = path.as_ref() {
+                // read upfront, peeling to the entry replaces the tree with the file's parent
+                let attributes = GitAttributes::from_tree(&tree, BINARY_ATTRIBUTES)?;
+
                 let item = tree
                     .peel_to_entry_by_path(path)?
-                    .context("Path doesn't exist in tree")?;
+                    .ok_or(NotFound("Path doesn't exist in tree"))?;
                 let object = item.object().context("Path in tree isn't an object")?;
 
                 match object.kind {
                     Kind::Blob => {
                         let mut blob = object.into_blob();
 
+                        let id = blob.id;
                         let size = blob.data.len();
 
-                        let content = match (formatted, simdutf8::basic::from_utf8(&blob.data)) {
-                            (true, Err(_)) => Content::Binary(vec![]),
-                            (true, Ok(data)) => Content::Text(Cow::Owned(format_file(
-                                data,
-                                FileIdentifier::Path(path.as_path()),
-                            )?)),
-                            (false, Err(_)) => Content::Binary(blob.take_data()),
-                            (false, Ok(_data)) => Content::Text(Cow::Owned(unsafe {
-                                String::from_utf8_unchecked(blob.take_data())
-                            })),
+                        let limits = self.git.file_limits;
+                        let lfs_pointer = LfsPointer::parse(&blob.data);
+                        // raw requests for LFS-tracked files are served the actual object if we
+                        // have it, rather than the pointer
+                        let lfs_object = lfs_pointer
+                            .as_ref()
+                            .filter(|_| !formatted)
+                            .zip(self.git.lfs_store.as_deref())
+                            .and_then(|(pointer, store)| pointer.clone().open(store));
+
+                        let content = if let Some(object) = lfs_object {
+                            object
+                        } else if let Some(pointer) = lfs_pointer.filter(|_| formatted) {
+                            Content::LfsPointer(pointer)
+                        } else if formatted && size > limits.max_render_bytes {
+                            // checked upfront so huge blobs aren't even scanned for valid UTF-8
+                            Content::TooLarge
+                        } else {
+                            let binary = is_binary(
+                                &attributes,
+                                &gix::path::into_bstr(path.as_path()),
+                                &blob.data,
+                            );
+
+                            match (formatted, binary) {
+                                (true, true) => Content::Binary(vec![]),
+                                (true, false) => {
+                                    // text with the odd invalid byte is still shown as text
+                                    let data = lossy_utf8(&blob.data);
+
+                                    Content::Text(Cow::Owned(
+                                        if size > limits.max_highlight_bytes {
+                                            format_file_plain(&data)
+                                        } else {
+                                            format_file(
+                                                &data,
+                                                FileIdentifier::Path(path.as_path()),
+                                            )?
+                                        },
+                                    ))
+                                }
+                                (false, binary) => raw_content(blob.take_data(), binary),
+                            }
                         };
 
-                        return Ok(PathDestination::File(FileWithContent {
-                            metadata: File {
-                                mode: item.mode().0,
-                                size,
-                                path: path.clone(),
-                                name: item.filename().to_string(),
-                            },
-                            content,
-                        }));
+                        return Ok((
+                            PathDestination::File(FileWithContent {
+                                id,
+                                metadata: File {
+                                    mode: item.mode().0,
+                                    size,
+                                    path: path.clone(),
+                                    name: item.filename().to_string(),
+                                },
+                                content,
+                            }),
+                            resolved_id,
+                        ));
                     }
                     Kind::Tree => {
                         tree = object.into_tree();
@@ -258,6 +394,7 @@ impl OpenRepository {
                             tree_items.push(TreeItem::Submodule(Submodule {
                                 mode: item.mode().0,
                                 name: item.filename().to_string(),
+                                path,
                                 url,
                                 oid: item.object_id(),
                             }));
@@ -268,7 +405,7 @@ impl OpenRepository {
                 }
             }
 
-            Ok(PathDestination::Tree(tree_items))
+            Ok((PathDestination::Tree(tree_items), resolved_id))
         })
         .await
         .context("Failed to join Tokio task")?
@@ -278,28 +415,51 @@ impl OpenRepository {
     pub async fn tag_info(self: Arc<Self>) -> Result<Yoke<DetailedTag<'static>, Vec<u8>>> {
         tokio::task::spawn_blocking(move || {
             let tag_name = self.branch.clone().context("no tag given")?;
-            let repo = self.repo.to_thread_local();
+            let repo = self.thread_local();
+
+            let mut reference = find_reference(&repo, &format!("refs/tags/{tag_name}"))?;
+
+            // lightweight tags point directly at a commit rather than at a tag object, so we
+            // present the commit they refer to instead
+            let Ok(tag) = reference.peel_to_tag() else {
+                let commit = reference
+                    .peel_to_commit()
+                    .context("Couldn't get to a tag or commit from the given reference")?;
+                let commit_id = commit.id;
+
+                let data = commit.detach().data;
+                let data = repair_commit_signatures(&data).unwrap_or(data);
+
+                return Yoke::try_attach_to_cart(data, move |commit| {
+                    let commit = CommitRef::from_bytes(commit)?;
+
+                    Ok::<_, anyhow::Error>(DetailedTag {
+                        name: tag_name,
+                        id: commit_id,
+                        kind: TagKind::Lightweight,
+                        tagger: None,
+                        tagged_object: Some(TaggedObject::Commit(commit_id)),
+                        message: commit.message,
+                    })
+                });
+            };
 
-            let tag = repo
-                .find_reference(&format!("refs/tags/{tag_name}"))
-                .context("Given tag does not exist in repository")?
-                .peel_to_tag()
-                .context("Couldn't get to a tag from the given reference")?
-                .detach()
-                .data;
+            let tag_id = tag.id;
 
-            Yoke::try_attach_to_cart(tag, move |tag| {
+            Yoke::try_attach_to_cart(tag.detach().data, move |tag| {
                 let tag = TagRef::from_bytes(tag)?;
 
                 let tagged_object = match tag.target_kind {
-                    Kind::Commit => Some(TaggedObject::Commit(tag.target)),
-                    Kind::Tree => Some(TaggedObject::Tree(tag.target)),
+                    Kind::Commit => Some(TaggedObject::Commit(ObjectId::from_hex(tag.target)?)),
+                    Kind::Tree => Some(TaggedObject::Tree(ObjectId::from_hex(tag.target)?)),
                     _ => None,
                 };
 
                 Ok::<_, anyhow::Error>(DetailedTag {
                     name: tag_name,
-                    tagger: tag.tagger.map(TryInto::try_into).transpose()?,
+                    id: tag_id,
+                    kind: TagKind::Annotated,
+                    tagger: tag.tagger.map(CommitUser::from),
                     tagged_object,
                     message: tag.message,
                 })
@@ -320,10 +480,10 @@ impl OpenRepository {
         git.readme_cache
             .try_get_with((self.cache_key.clone(), self.branch.clone()), async move {
                 tokio::task::spawn_blocking(move || {
-                    let repo = self.repo.to_thread_local();
+                    let repo = self.thread_local();
 
                     let mut head = if let Some(reference) = &self.branch {
-                        repo.find_reference(reference.as_ref())?
+                        find_reference(&repo, reference)?
                     } else {
                         repo.find_reference("HEAD")
                             .context("Couldn't find HEAD of repository")?
@@ -371,7 +531,7 @@ impl OpenRepository {
 
     pub async fn default_branch(self: Arc<Self>) -> Result<Option<String>> {
         tokio::task::spawn_blocking(move || {
-            let repo = self.repo.to_thread_local();
+            let repo = self.thread_local();
             let head = repo.head().context("Couldn't find HEAD of repository")?;
             Ok(head.referent_name().map(|v| v.shorten().to_string()))
         })
@@ -380,12 +540,18 @@ impl OpenRepository {
     }
 
     #[instrument(skip(self))]
-    pub async fn latest_commit(self: Arc<Self>, highlighted: bool) -> Result<Commit> {
+    pub async fn latest_commit(
+        self: Arc<Self>,
+        highlighted: bool,
+        options: DiffOptions,
+    ) -> Result<Commit> {
+        let options = options.or_algorithm(self.git.diff_algorithm);
+
         tokio::task::spawn_blocking(move || {
-            let repo = self.repo.to_thread_local();
+            let repo = self.thread_local();
 
             let mut head = if let Some(reference) = &self.branch {
-                repo.find_reference(reference.as_ref())?
+                find_reference(&repo, reference)?
             } else {
                 repo.find_reference("HEAD")
                     .context("Couldn't find HEAD of repository")?
@@ -394,47 +560,85 @@ impl OpenRepository {
             let commit = head
                 .peel_to_commit()
                 .context("Couldn't find commit HEAD of repository refers to")?;
-            let (diff_output, diff_stats) = fetch_diff_and_stats(&repo, &commit, highlighted)?;
+            let (diff_output, diff_stats, diff_truncated) = fetch_diff_and_stats(
+                &repo,
+                &commit,
+                highlighted,
+                options,
+                self.git.file_limits.max_diff_bytes,
+            )?;
+            let notes = self
+                .git
+                .show_notes
+                .then(|| find_notes(&repo, commit.id))
+                .flatten();
 
             let oid = take_oid(commit.id);
-            let inner = Yoke::try_attach_to_cart(commit.detach().data, |commit| {
-                CommitInner::new(CommitRef::from_bytes(commit)?, oid)
+            let data = commit.detach().data;
+            let data = repair_commit_signatures(&data).unwrap_or(data);
+            let inner = Yoke::try_attach_to_cart(data, |commit| {
+                CommitRef::from_bytes(commit).map(|commit| CommitInner::new(commit, oid))
             })?;
 
             Ok(Commit {
                 inner,
                 diff_stats,
                 diff: diff_output,
+                diff_truncated,
+                notes,
             })
         })
         .await
         .context("Failed to join Tokio task")?
     }
 
+    /// Streams a gzipped tarball of the given commit's tree, with every path nested under
+    /// `prefix` (which should be empty or end with a `/`, like `git archive --prefix`).
     #[instrument(skip_all)]
     pub async fn archive(
         self: Arc<Self>,
         res: tokio::sync::mpsc::Sender<Result<Bytes, anyhow::Error>>,
         cont: tokio::sync::oneshot::Sender<()>,
         commit: Option<&str>,
+        prefix: String,
     ) -> Result<(), anyhow::Error> {
         let commit = commit
             .map(ObjectId::from_str)
             .transpose()
-            .context("failed to build oid")?;
+            .map_err(|_| NotFound("Invalid commit id"))?;
 
         tokio::task::spawn_blocking(move || {
-            let repo = self.repo.to_thread_local();
+            let repo = self.thread_local();
 
-            let tree = if let Some(commit) = commit {
-                repo.find_commit(commit)?.tree()?
-            } else if let Some(reference) = &self.branch {
-                repo.find_reference(reference.as_ref())?.peel_to_tree()?
+            let (tree, commit) = if let Some(commit) = commit {
+                let commit = find_commit(&repo, commit)?;
+                (commit.tree()?, Some(commit))
             } else {
-                repo.find_reference("HEAD")
-                    .context("Couldn't find HEAD of repository")?
-                    .peel_to_tree()?
+                let mut reference = if let Some(reference) = &self.branch {
+                    find_reference(&repo, reference)?
+                } else {
+                    repo.find_reference("HEAD")
+                        .context("Couldn't find HEAD of repository")?
+                };
+
+                // references can point straight at a tree, which is archived as is
+                let object = reference
+                    .peel_to_id_in_place()?
+                    .object()?
+                    .peel_tags_to_end()?;
+                if object.kind == Kind::Tree {
+                    (object.into_tree(), None)
+                } else {
+                    let commit = object.try_into_commit()?;
+                    (commit.tree()?, Some(commit))
+                }
             };
+            let attributes = GitAttributes::from_tree(
+                &tree,
+                &[EXPORT_IGNORE_ATTRIBUTE, EXPORT_SUBST_ATTRIBUTE],
+            )?;
+            // like `git archive`, there's nothing to substitute without a commit
+            let export_subst = commit.as_ref().map(ExportSubst::new).transpose()?;
 
             // tell the web server it can send response headers to the requester
             if cont.send(()).is_err() {
@@ -448,6 +652,9 @@ impl OpenRepository {
                 archive: Builder::new(GzEncoder::new(buffer.writer(), flate2::Compression::fast())),
                 path_deque: VecDeque::new(),
                 path: BString::default(),
+                prefix,
+                attributes,
+                export_subst,
             };
 
             tree.traverse().breadthfirst(&mut visitor)?;
@@ -466,38 +673,365 @@ impl OpenRepository {
         Ok(())
     }
 
+    /// Verifies the signature on the given commit or tag by shelling out to `git verify-commit`
+    /// or `git verify-tag`, returning `None` if signature verification isn't enabled.
+    #[instrument(skip(self))]
+    pub async fn signature_status(
+        self: Arc<Self>,
+        oid: ObjectId,
+    ) -> Result<Option<SignatureStatus>, Arc<anyhow::Error>> {
+        // gpg can hang indefinitely, for example waiting on a keyserver or a locked keyring
+        const VERIFY_TIMEOUT: Duration = Duration::from_secs(5);
+
+        let Some(verification) = self.git.signature_verification.clone() else {
+            return Ok(None);
+        };
+
+        let git = self.git.clone();
+
+        git.signatures
+            .try_get_with(oid, async move {
+                let repo = self.repo.clone();
+                let subcommand = tokio::task::spawn_blocking(move || {
+                    let repo = repo.to_thread_local();
+                    let object = repo.find_object(oid)?;
+
+                    let (signed, subcommand) = match object.kind {
+                        Kind::Commit => (
+                            CommitRef::from_bytes(
+                                repair_commit_signatures(&object.data)
+                                    .as_deref()
+                                    .unwrap_or(&object.data),
+                            )?
+                            .extra_headers
+                            .iter()
+                            .any(|(k, _)| k.starts_with(b"gpgsig")),
+                            "verify-commit",
+                        ),
+                        Kind::Tag => (
+                            TagRef::from_bytes(&object.data)?.pgp_signature.is_some(),
+                            "verify-tag",
+                        ),
+                        _ => (false, ""),
+                    };
+
+                    Ok::<_, anyhow::Error>(signed.then_some(subcommand))
+                })
+                .await
+                .context("Failed to join Tokio task")??;
+
+                let Some(subcommand) = subcommand else {
+                    return Ok(SignatureStatus::Unsigned);
+                };
+
+                let mut command = tokio::process::Command::new("git");
+                command.arg("--git-dir").arg(&self.cache_key);
+
+                if let Some(allowed_signers) = &verification.allowed_signers {
+                    command.arg("-c").arg(format!(
+                        "gpg.ssh.allowedSignersFile={}",
+                        allowed_signers.display()
+                    ));
+                }
+
+                if let Some(gnupg_home) = &verification.gnupg_home {
+                    command.env("GNUPGHOME", gnupg_home);
+                }
+
+                let status = command
+                    .arg(subcommand)
+                    .arg(oid.to_string())
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .kill_on_drop(true)
+                    .status();
+
+                let status = tokio::time::timeout(VERIFY_TIMEOUT, status)
+                    .await
+                    .context("Timed out verifying signature")?
+                    .context("Failed to run git to verify signature")?;
+
+                Ok(if status.success() {
+                    SignatureStatus::Verified
+                } else {
+                    SignatureStatus::Unverified
+                })
+            })
+            .await
+    }
+
+    /// Finds the branches and tags whose history contains the given commit, like
+    /// `git branch --contains`, returning `None` if `--show-contained-refs` isn't enabled.
+    ///
+    /// References pointing straight at the commit are always found, the history of the others
+    /// is walked newest first until the commit's own committer time, sharing a budget of
+    /// `--contained-refs-max-commits` between them.
+    #[instrument(skip(self))]
+    pub async fn refs_containing(
+        self: Arc<Self>,
+        oid: ObjectId,
+    ) -> Result<Option<Arc<ContainedRefs>>, Arc<anyhow::Error>> {
+        // commit times can be skewed between machines, so don't stop walking right at the
+        // commit's own time
+        const CLOCK_SKEW_SECS: i64 = 24 * 60 * 60;
+
+        let Some(max_commits) = self.git.contained_refs_max_commits else {
+            return Ok(None);
+        };
+
+        let git = self.git.clone();
+
+        git.contained_refs
+            .try_get_with((self.cache_key.clone(), oid), async move {
+                tokio::task::spawn_blocking(move || {
+                    let repo = self.thread_local();
+
+                    // a commit with an unparseable committer has no usable time to cut off
+                    // at, so the walk is left to the commit budget instead
+                    let cutoff = repo
+                        .find_commit(oid)?
+                        .committer()
+                        .map_or(0, |v| v.time.seconds)
+                        .saturating_sub(CLOCK_SKEW_SECS);
+
+                    let mut tips = Vec::new();
+                    for reference in repo.references()?.all()? {
+                        let Ok(mut reference) = reference else {
+                            continue;
+                        };
+
+                        let (is_tag, name) = match reference.name().category_and_short_name() {
+                            Some((Category::LocalBranch, name)) => (false, name.to_string()),
+                            Some((Category::Tag, name)) => (true, name.to_string()),
+                            _ => continue,
+                        };
+
+                        // tags can point at trees and blobs, which have no history to walk
+                        if let Ok(tip) = reference.peel_to_commit() {
+                            tips.push((is_tag, name, tip.id));
+                        }
+                    }
+
+                    let mut out = ContainedRefs::default();
+
+                    // the common case is looking at the tip of a branch, which needs no walking
+                    let (matched, unmatched): (Vec<_>, Vec<_>) =
+                        tips.into_iter().partition(|(_, _, tip)| *tip == oid);
+                    for (is_tag, name, _) in matched {
+                        out.push(is_tag, name);
+                    }
+
+                    let mut budget = max_commits;
+                    for (is_tag, name, tip) in unmatched {
+                        if budget == 0 {
+                            out.truncated = true;
+                            break;
+                        }
+
+                        // a broken history only hides the one reference, not the whole page
+                        let Ok(walk) = repo
+                            .rev_walk([tip])
+                            .sorting(gix::revision::walk::Sorting::ByCommitTimeCutoff {
+                                order: CommitTimeOrder::NewestFirst,
+                                seconds: cutoff,
+                            })
+                            .all()
+                        else {
+                            continue;
+                        };
+
+                        for info in walk {
+                            if budget == 0 {
+                                out.truncated = true;
+                                break;
+                            }
+                            budget -= 1;
+
+                            match info {
+                                Ok(info) if info.id == oid => {
+                                    out.push(is_tag, name);
+                                    break;
+                                }
+                                Ok(_) => {}
+                                Err(_) => break,
+                            }
+                        }
+                    }
+
+                    Ok(Arc::new(out))
+                })
+                .await
+                .context("Failed to join Tokio task")?
+            })
+            .await
+            .map(Some)
+    }
+
+    /// Describes the object with the given (possibly abbreviated) id, whatever type it is. Blobs
+    /// are only ever described by their size so huge ones don't have to be read.
+    #[instrument(skip(self))]
+    pub async fn object_info(self: Arc<Self>, id: &str) -> Result<ObjectInfo> {
+        let id = id.to_string();
+
+        tokio::task::spawn_blocking(move || {
+            let repo = self.thread_local();
+
+            let id = match ObjectId::from_str(&id) {
+                Ok(id) => id,
+                Err(_) => lookup_prefix(&repo, &id)?,
+            };
+
+            let header = repo
+                .try_find_header(id)?
+                .ok_or(NotFound("Couldn't find object with given id"))?;
+
+            let mut info = ObjectInfo {
+                id: id.to_string(),
+                kind: header.kind().into(),
+                size: header.size(),
+                entries: None,
+                target: None,
+                tree: None,
+                parents: None,
+            };
+
+            match header.kind() {
+                Kind::Tree => {
+                    let tree = repo.find_object(id)?.into_tree();
+                    let tree = tree.decode()?;
+
+                    info.entries = Some(
+                        tree.entries
+                            .iter()
+                            .map(|entry| ObjectTreeEntry {
+                                mode: entry.mode.as_octal_str().to_string(),
+                                kind: match entry.mode.kind() {
+                                    EntryKind::Tree => ObjectKind::Tree,
+                                    EntryKind::Commit => ObjectKind::Commit,
+                                    EntryKind::Blob
+                                    | EntryKind::BlobExecutable
+                                    | EntryKind::Link => ObjectKind::Blob,
+                                },
+                                id: entry.oid.to_string(),
+                                name: entry.filename.to_string(),
+                            })
+                            .collect(),
+                    );
+                }
+                Kind::Tag => {
+                    let tag = repo.find_object(id)?;
+                    let tag = TagRef::from_bytes(&tag.data)?;
+
+                    info.target = Some(ObjectTagTarget {
+                        id: tag.target.to_string(),
+                        kind: tag.target_kind.into(),
+                        name: tag.name.to_string(),
+                    });
+                }
+                Kind::Commit => {
+                    let commit = repo.find_object(id)?.detach().data;
+                    let commit = repair_commit_signatures(&commit).unwrap_or(commit);
+                    let commit = CommitRef::from_bytes(&commit)?;
+
+                    info.tree = Some(commit.tree().to_string());
+                    info.parents = Some(commit.parents().map(|v| v.to_string()).collect());
+                }
+                Kind::Blob => {}
+            }
+
+            Ok(info)
+        })
+        .await
+        .context("Failed to join Tokio task")?
+    }
+
+    /// Reads a blob by its id for serving raw, independent of any branch or path. Without a path
+    /// there's no `.gitattributes` to consult, so blobs are served as text if they're valid UTF-8
+    /// and as binary otherwise.
+    #[instrument(skip(self))]
+    pub async fn blob(self: Arc<Self>, id: ObjectId) -> Result<Content> {
+        tokio::task::spawn_blocking(move || {
+            let repo = self.thread_local();
+
+            let object = repo
+                .try_find_object(id)?
+                .filter(|v| v.kind == Kind::Blob)
+                .ok_or(NotFound("Couldn't find blob with given id"))?;
+            let data = object.into_blob().take_data();
+
+            let lfs_object = LfsPointer::parse(&data)
+                .zip(self.git.lfs_store.as_deref())
+                .and_then(|(pointer, store)| pointer.open(store));
+
+            Ok(if let Some(object) = lfs_object {
+                object
+            } else {
+                raw_content(data, false)
+            })
+        })
+        .await
+        .context("Failed to join Tokio task")?
+    }
+
     #[instrument(skip(self))]
     pub async fn commit(
         self: Arc<Self>,
         commit: &str,
         highlighted: bool,
+        options: DiffOptions,
     ) -> Result<Arc<Commit>, Arc<anyhow::Error>> {
-        let commit = ObjectId::from_str(commit)
-            .map_err(anyhow::Error::from)
-            .map_err(Arc::new)?;
+        let commit = if let Ok(commit) = ObjectId::from_str(commit) {
+            commit
+        } else {
+            // resolved up front rather than in the cache loader so the cache is only ever keyed
+            // by full ids, and branches are followed to wherever they point now
+            let this = self.clone();
+            let spec = commit.to_string();
+            tokio::task::spawn_blocking(move || resolve_commit_id(&this.thread_local(), &spec))
+                .await
+                .context("Failed to join Tokio task")
+                .map_err(Arc::new)?
+                .map_err(Arc::new)?
+        };
+        let options = options.or_algorithm(self.git.diff_algorithm);
 
         let git = self.git.clone();
 
         git.commits
-            .try_get_with((commit, highlighted), async move {
+            .try_get_with((commit, highlighted, options), async move {
                 tokio::task::spawn_blocking(move || {
-                    let repo = self.repo.to_thread_local();
+                    let repo = self.thread_local();
 
-                    let commit = repo.find_commit(commit)?;
+                    let commit = find_commit(&repo, commit)?;
 
-                    let (diff_output, diff_stats) =
-                        fetch_diff_and_stats(&repo, &commit, highlighted)?;
+                    let (diff_output, diff_stats, diff_truncated) = fetch_diff_and_stats(
+                        &repo,
+                        &commit,
+                        highlighted,
+                        options,
+                        self.git.file_limits.max_diff_bytes,
+                    )?;
+                    let notes = self
+                        .git
+                        .show_notes
+                        .then(|| find_notes(&repo, commit.id))
+                        .flatten();
 
                     let oid = take_oid(commit.id);
 
-                    let inner = Yoke::try_attach_to_cart(commit.detach().data, |commit| {
-                        CommitInner::new(CommitRef::from_bytes(commit)?, oid)
+                    let data = commit.detach().data;
+                    let data = repair_commit_signatures(&data).unwrap_or(data);
+                    let inner = Yoke::try_attach_to_cart(data, |commit| {
+                        CommitRef::from_bytes(commit).map(|commit| CommitInner::new(commit, oid))
                     })?;
 
                     Ok(Arc::new(Commit {
                         inner,
                         diff_stats,
                         diff: diff_output,
+                        diff_truncated,
+                        notes,
                     }))
                 })
                 .await
@@ -505,6 +1039,296 @@ impl OpenRepository {
             })
             .await
     }
+
+    /// Returns the commits after `from` up to and including `to` (or the head of the branch),
+    /// oldest first, by following first parents like `git format-patch` would for a linear
+    /// series.
+    #[instrument(skip(self))]
+    pub async fn commit_range(
+        self: Arc<Self>,
+        from: &str,
+        to: Option<&str>,
+    ) -> Result<Vec<String>> {
+        const MAX_COMMITS: usize = 250;
+
+        let from = ObjectId::from_str(from).map_err(|_| NotFound("Invalid commit id"))?;
+        let to = to
+            .map(ObjectId::from_str)
+            .transpose()
+            .map_err(|_| NotFound("Invalid commit id"))?;
+
+        tokio::task::spawn_blocking(move || {
+            let repo = self.thread_local();
+
+            let mut commit = if let Some(to) = to {
+                find_commit(&repo, to)?
+            } else if let Some(reference) = &self.branch {
+                find_reference(&repo, reference)?
+                    .peel_to_commit()
+                    .context("Couldn't find commit for reference")?
+            } else {
+                repo.find_reference("HEAD")
+                    .context("Couldn't find HEAD of repository")?
+                    .peel_to_commit()
+                    .context("Couldn't find commit HEAD of repository refers to")?
+            };
+
+            let mut ids = Vec::new();
+
+            while commit.id != from {
+                if ids.len() == MAX_COMMITS {
+                    return Err(BadRequest("Too many commits in the given range").into());
+                }
+
+                ids.push(commit.id.to_string());
+
+                let Some(parent) = commit.parent_ids().next() else {
+                    return Err(
+                        BadRequest("Base commit isn't an ancestor of the given commit").into(),
+                    );
+                };
+
+                commit = find_commit(&repo, parent.detach())?;
+            }
+
+            ids.reverse();
+
+            Ok(ids)
+        })
+        .await
+        .context("Failed to join Tokio task")?
+    }
+}
+
+/// Size thresholds, in bytes, past which files are rendered more cheaply when viewed in the
+/// browser. Raw downloads are unaffected.
+#[derive(Debug, Clone, Copy)]
+pub struct FileLimits {
+    /// Files larger than this are shown without syntax highlighting
+    pub max_highlight_bytes: usize,
+    /// Files larger than this aren't shown inline at all, only offered as a download
+    pub max_render_bytes: usize,
+    /// The size a commit's rendered diff can grow to, the diff of any file that would take it
+    /// past this is left out
+    pub max_diff_bytes: usize,
+}
+
+impl Default for FileLimits {
+    fn default() -> Self {
+        Self {
+            max_highlight_bytes: 1024 * 1024,
+            max_render_bytes: 10 * 1024 * 1024,
+            max_diff_bytes: 2 * 1024 * 1024,
+        }
+    }
+}
+
+/// The algorithm used to diff files, set by `--diff-algorithm` or overridden with `?algo=`.
+#[derive(clap::ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum DiffAlgorithm {
+    /// Finds the smallest diff quickly, but tends to produce confusing diffs when blocks of
+    /// code are moved or common lines (like braces) appear throughout the file
+    Myers,
+    /// Like `myers` but always finds the smallest possible diff, at a much higher cost on large
+    /// files
+    MyersMinimal,
+    /// Anchors the diff on lines that are rare in both files, which usually produces more
+    /// readable diffs for restructured code at a similar cost to `myers`. This is an extension
+    /// of git's `patience` algorithm, which is accepted as an alias for it
+    #[value(alias = "patience")]
+    #[serde(alias = "patience")]
+    Histogram,
+}
+
+impl From<DiffAlgorithm> for gix::diff::blob::Algorithm {
+    fn from(value: DiffAlgorithm) -> Self {
+        match value {
+            DiffAlgorithm::Myers => Self::Myers,
+            DiffAlgorithm::MyersMinimal => Self::MyersMinimal,
+            DiffAlgorithm::Histogram => Self::Histogram,
+        }
+    }
+}
+
+/// How a commit's diff is built. Each distinct set of options is rendered & cached separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DiffOptions {
+    /// Lines of unchanged context kept around each change
+    pub context_lines: u32,
+    /// The algorithm to diff with, or `None` to use the one given by the repository's
+    /// `diff.algorithm`
+    pub algorithm: Option<DiffAlgorithm>,
+}
+
+impl DiffOptions {
+    /// Falls back to `algorithm` if the options don't ask for a specific one.
+    fn or_algorithm(self, algorithm: Option<DiffAlgorithm>) -> Self {
+        Self {
+            algorithm: self.algorithm.or(algorithm),
+            ..self
+        }
+    }
+}
+
+/// The references found to contain a commit by [`OpenRepository::refs_containing`].
+#[derive(Debug, Default)]
+pub struct ContainedRefs {
+    pub branches: Vec<String>,
+    pub tags: Vec<String>,
+    /// Whether the walk budget ran out before every reference could be checked
+    pub truncated: bool,
+}
+
+impl ContainedRefs {
+    fn push(&mut self, is_tag: bool, name: String) {
+        if is_tag {
+            self.tags.push(name);
+        } else {
+            self.branches.push(name);
+        }
+    }
+}
+
+/// Where to find the keys used to verify signatures on commits and tags, enabled via
+/// `--verify-signatures`.
+#[derive(Debug, Clone, Default)]
+pub struct SignatureVerification {
+    /// The `allowed_signers` file used to verify SSH signatures
+    pub allowed_signers: Option<PathBuf>,
+    /// The GnuPG home directory containing the keyring used to verify GPG signatures
+    pub gnupg_home: Option<PathBuf>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SignatureStatus {
+    /// Signed by a key in the configured keyring or allowed signers file
+    Verified,
+    /// Signed, but by an unknown key or the signature doesn't match the object
+    Unverified,
+    Unsigned,
+}
+
+/// A lookup for something that doesn't exist in the repository, such as a path that isn't in the
+/// tree or a commit id that was mistyped. Returned so handlers can surface these to the user as a
+/// 404 rather than an internal error.
+#[derive(Debug, Clone, Copy)]
+pub struct NotFound(pub &'static str);
+
+impl fmt::Display for NotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl std::error::Error for NotFound {}
+
+/// A request that can't be answered as asked, such as a commit range that's too long or doesn't
+/// connect, even though everything it refers to exists. Surfaced to the user as a 400.
+#[derive(Debug, Clone, Copy)]
+pub struct BadRequest(pub &'static str);
+
+impl fmt::Display for BadRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl std::error::Error for BadRequest {}
+
+fn find_reference<'a>(repo: &'a gix::Repository, name: &str) -> Result<gix::Reference<'a>> {
+    repo.try_find_reference(name)?
+        .ok_or_else(|| NotFound("Couldn't find the given reference").into())
+}
+
+/// Resolves an abbreviated commit id or the name of a branch or tag to the id of the commit it
+/// refers to. References are tried first, like git does when a name could be either.
+fn resolve_commit_id(repo: &gix::Repository, spec: &str) -> Result<ObjectId> {
+    if let Ok(Some(mut reference)) = repo.try_find_reference(spec) {
+        return Ok(reference
+            .peel_to_commit()
+            .map_err(|_| NotFound("Given reference doesn't point to a commit"))?
+            .id);
+    }
+
+    lookup_prefix(repo, spec)
+}
+
+/// Resolves an abbreviated object id to the single object it refers to.
+fn lookup_prefix(repo: &gix::Repository, hex: &str) -> Result<ObjectId> {
+    let prefix = gix::hash::Prefix::from_hex(hex).map_err(|_| NotFound("Invalid object id"))?;
+
+    match repo.objects.lookup_prefix(prefix, None)? {
+        Some(Ok(id)) => Ok(id),
+        Some(Err(())) => Err(BadRequest("Object id prefix is ambiguous, try a longer one").into()),
+        None => Err(NotFound("Couldn't find object with given id").into()),
+    }
+}
+
+fn find_commit(repo: &gix::Repository, id: ObjectId) -> Result<gix::Commit<'_>> {
+    repo.try_find_object(id)?
+        .ok_or(NotFound("Couldn't find commit with given id"))?
+        .try_into_commit()
+        .map_err(|_| NotFound("Given id isn't a commit").into())
+}
+
+/// Looks up the note attached to `oid` in the default notes ref, returning `None` if there isn't
+/// one. Notes are only ever supplementary, so failures are logged rather than failing the page.
+fn find_notes(repo: &gix::Repository, oid: ObjectId) -> Option<String> {
+    find_notes_inner(repo, oid).unwrap_or_else(|error| {
+        warn!(%error, "Failed to read notes for {oid}");
+        None
+    })
+}
+
+fn find_notes_inner(repo: &gix::Repository, oid: ObjectId) -> Result<Option<String>> {
+    let Some(mut reference) = repo.try_find_reference("refs/notes/commits")? else {
+        return Ok(None);
+    };
+
+    let mut tree = reference.peel_to_commit()?.tree()?;
+    let hex = oid.to_string();
+    let mut remaining = hex.as_str();
+
+    // notes are stored in a file named after the oid, but once there are many of them git fans
+    // them out into directories named after the leading bytes, eg. `ab/cdef...`
+    loop {
+        if let Some(entry) = tree.lookup_entry_by_path(remaining)? {
+            if entry.mode().is_blob() {
+                let note = entry.object()?.detach().data;
+                return Ok(Some(String::from_utf8_lossy(&note).into_owned()));
+            }
+        }
+
+        let Some((fanout, rest)) = remaining.split_at_checked(2).filter(|(_, v)| !v.is_empty())
+        else {
+            return Ok(None);
+        };
+
+        match tree.lookup_entry_by_path(fanout)? {
+            Some(entry) if entry.mode().is_tree() => {
+                tree = entry.object()?.into_tree();
+                remaining = rest;
+            }
+            _ => return Ok(None),
+        }
+    }
+}
+
+/// Finds the tree with the given id, or the root tree of the commit with the given id.
+fn find_tree(repo: &gix::Repository, id: ObjectId) -> Result<gix::Tree<'_>> {
+    let object = repo
+        .try_find_object(id)?
+        .ok_or(NotFound("Couldn't find tree with given id"))?;
+
+    match object.kind {
+        Kind::Tree => Ok(object.into_tree()),
+        Kind::Commit => Ok(object
+            .into_commit()
+            .tree()
+            .context("Couldn't get the tree of the given commit")?),
+        _ => Err(NotFound("Given id isn't a tree or commit").into()),
+    }
 }
 
 fn take_oid(v: ObjectId) -> [u8; 20] {
@@ -513,14 +1337,232 @@ fn take_oid(v: ObjectId) -> [u8; 20] {
     }
 }
 
+/// Rewrites any author or committer lines in the given commit object that gix can't parse, such
+/// as empty ones or ones missing an email, to `unknown <unknown>` at the epoch so the rest of the
+/// commit can still be indexed and shown.
+///
+/// Returns `None` if there was nothing to repair.
+pub fn repair_commit_signatures(data: &[u8]) -> Option<Vec<u8>> {
+    let headers_end = data.find(b"\n\n").unwrap_or(data.len());
+    let mut repaired: Option<Vec<u8>> = None;
+    let mut copied = 0;
+    let mut offset = 0;
+
+    for line in data[..headers_end].lines_with_terminator() {
+        let start = offset;
+        offset += line.len();
+
+        let content = line.strip_suffix(b"\n").unwrap_or(line);
+        let Some((header, value)) =
+            [b"author".as_slice(), b"committer"]
+                .into_iter()
+                .find_map(|header| {
+                    let rest = content.strip_prefix(header)?;
+                    let value = rest.strip_prefix(b" ");
+                    (rest.is_empty() || value.is_some())
+                        .then(|| (header, value.unwrap_or_default()))
+                })
+        else {
+            continue;
+        };
+
+        // gix is lenient about everything but the name and email, so anything it can parse is
+        // left exactly as it is
+        if gix::actor::SignatureRef::from_bytes::<()>(value).is_ok() {
+            continue;
+        }
+
+        let out = repaired.get_or_insert_with(|| Vec::with_capacity(data.len()));
+        out.extend_from_slice(&data[copied..start]);
+        out.extend_from_slice(header);
+        out.extend_from_slice(b" unknown <unknown> 0 +0000");
+
+        copied = start + content.len();
+    }
+
+    let mut repaired = repaired?;
+    repaired.extend_from_slice(&data[copied..]);
+    Some(repaired)
+}
+
+/// The attributes [`is_binary`] reads from `.gitattributes`.
+const BINARY_ATTRIBUTES: &[&str] = &["binary", "text", "diff"];
+
+/// Whether a file should be treated as binary rather than shown as text, used by both the file
+/// view and diffs.
+///
+/// `.gitattributes` is honoured first, `binary`, `-text` and `-diff` mark a file as binary and
+/// `text` marks it as text. Otherwise, like git, the start of the file is checked for NUL bytes,
+/// and files that are mostly control characters are assumed to be binary too.
+fn is_binary(attributes: &GitAttributes, path: &BStr, data: &[u8]) -> bool {
+    const SNIFF_BYTES: usize = 8000;
+
+    if attributes.is_set(path, false, "binary")
+        || attributes.state(path, false, "text") == Some(false)
+        || attributes.state(path, false, "diff") == Some(false)
+    {
+        return true;
+    }
+
+    if attributes.is_set(path, false, "text") {
+        return false;
+    }
+
+    let head = &data[..data.len().min(SNIFF_BYTES)];
+
+    if memchr::memchr(0, head).is_some() {
+        return true;
+    }
+
+    let control_characters = head
+        .iter()
+        .filter(|&&c| (c < 0x20 && !matches!(c, b'\t' | b'\n' | b'\r' | 0x0c | 0x1b)) || c == 0x7f)
+        .count();
+
+    // more than 10% control characters
+    control_characters * 10 > head.len()
+}
+
+/// Decodes text that's mostly valid UTF-8, replacing any invalid bytes rather than failing.
+fn lossy_utf8(data: &[u8]) -> Cow<'_, str> {
+    match simdutf8::basic::from_utf8(data) {
+        Ok(data) => Cow::Borrowed(data),
+        Err(_) => String::from_utf8_lossy(data),
+    }
+}
+
 const BUFFER_CAP: usize = 512 * 1024;
 
+/// Paths with this attribute set are left out of archives, like `git archive` does.
+const EXPORT_IGNORE_ATTRIBUTE: &str = "export-ignore";
+/// Files with this attribute set have their `$Format:...$` placeholders expanded in archives.
+const EXPORT_SUBST_ATTRIBUTE: &str = "export-subst";
+
 pub struct ArchivalVisitor<'a> {
     repository: &'a gix::Repository,
     res: tokio::sync::mpsc::Sender<Result<Bytes, anyhow::Error>>,
     archive: Builder<GzEncoder<Writer<BytesMut>>>,
     path_deque: VecDeque<BString>,
     path: BString,
+    prefix: String,
+    attributes: GitAttributes,
+    export_subst: Option<ExportSubst>,
+}
+
+/// Expands the `$Format:...$` placeholders `git archive` substitutes in files marked
+/// `export-subst`, for the subset of `git log --format` placeholders that describe the commit
+/// being archived. Unsupported placeholders are left as they are.
+struct ExportSubst {
+    placeholders: Vec<(&'static str, String)>,
+}
+
+impl ExportSubst {
+    fn new(commit: &gix::Commit<'_>) -> Result<Self> {
+        // a signature gix can't parse expands to nothing rather than failing the snapshot
+        let author = find_signature(&commit.data, "author");
+        let committer = find_signature(&commit.data, "committer");
+        let tree = commit.tree_id()?;
+        let parents = commit.parent_ids().collect::<Vec<_>>();
+
+        let placeholders = vec![
+            ("H", commit.id.to_string()),
+            ("h", commit.id().shorten_or_id().to_string()),
+            ("T", tree.to_string()),
+            ("t", tree.shorten_or_id().to_string()),
+            ("P", parents.iter().join(" ")),
+            ("p", parents.iter().map(gix::Id::shorten_or_id).join(" ")),
+            ("an", author.map(|v| v.name.to_string()).unwrap_or_default()),
+            (
+                "ae",
+                author.map(|v| v.email.to_string()).unwrap_or_default(),
+            ),
+            (
+                "at",
+                author
+                    .map(|v| v.time.seconds.to_string())
+                    .unwrap_or_default(),
+            ),
+            (
+                "cn",
+                committer.map(|v| v.name.to_string()).unwrap_or_default(),
+            ),
+            (
+                "ce",
+                committer.map(|v| v.email.to_string()).unwrap_or_default(),
+            ),
+            (
+                "ct",
+                committer
+                    .map(|v| v.time.seconds.to_string())
+                    .unwrap_or_default(),
+            ),
+            (
+                "s",
+                MessageRef::from_bytes(commit.message_raw_sloppy())
+                    .summary()
+                    .to_string(),
+            ),
+            ("n", "\n".to_string()),
+            ("%", "%".to_string()),
+        ];
+
+        Ok(Self { placeholders })
+    }
+
+    fn expand(&self, data: &[u8]) -> Vec<u8> {
+        const START: &[u8] = b"$Format:";
+
+        let mut out = Vec::with_capacity(data.len());
+        let mut rest = data;
+
+        while let Some(start) = memchr::memmem::find(rest, START) {
+            let format_start = start + START.len();
+            let Some(end) = memchr::memchr(b'$', &rest[format_start..]) else {
+                break;
+            };
+
+            out.extend_from_slice(&rest[..start]);
+            self.expand_format(&rest[format_start..format_start + end], &mut out);
+            rest = &rest[format_start + end + 1..];
+        }
+
+        out.extend_from_slice(rest);
+        out
+    }
+
+    fn expand_format(&self, mut format: &[u8], out: &mut Vec<u8>) {
+        while let Some(idx) = memchr::memchr(b'%', format) {
+            out.extend_from_slice(&format[..idx]);
+            format = &format[idx + 1..];
+
+            // placeholders are tried longest first, so `%an` isn't read as `%a` followed by `n`
+            let placeholder = self
+                .placeholders
+                .iter()
+                .filter(|(key, _)| format.starts_with(key.as_bytes()))
+                .max_by_key(|(key, _)| key.len());
+
+            if let Some((key, value)) = placeholder {
+                out.extend_from_slice(value.as_bytes());
+                format = &format[key.len()..];
+            } else {
+                out.push(b'%');
+            }
+        }
+
+        out.extend_from_slice(format);
+    }
+}
+
+/// Parses the given `author` or `committer` header of a raw commit on its own, since decoding the
+/// commit through gix stops at the first header it can't parse.
+fn find_signature<'a>(data: &'a [u8], header: &str) -> Option<SignatureRef<'a>> {
+    let headers_end = data.find(b"\n\n").unwrap_or(data.len());
+
+    data[..headers_end]
+        .lines()
+        .find_map(|line| line.strip_prefix(header.as_bytes())?.strip_prefix(b" "))
+        .and_then(|value| SignatureRef::from_bytes::<()>(value).ok())
 }
 
 impl<'a> ArchivalVisitor<'a> {
@@ -562,10 +1604,24 @@ impl<'a> gix::traverse::tree::Visit for ArchivalVisitor<'a> {
     }
 
     fn visit_tree(&mut self, _entry: &EntryRef<'_>) -> Action {
-        Action::Continue
+        if self
+            .attributes
+            .is_set(self.path.as_bstr(), true, EXPORT_IGNORE_ATTRIBUTE)
+        {
+            Action::Skip
+        } else {
+            Action::Continue
+        }
     }
 
     fn visit_nontree(&mut self, entry: &EntryRef<'_>) -> Action {
+        if self
+            .attributes
+            .is_set(self.path.as_bstr(), false, EXPORT_IGNORE_ATTRIBUTE)
+        {
+            return Action::Continue;
+        }
+
         let entry = entry.attach(self.repository);
 
         let Ok(object) = entry.object() else {
@@ -576,19 +1632,51 @@ impl<'a> gix::traverse::tree::Visit for ArchivalVisitor<'a> {
             return Action::Continue;
         }
 
-        let blob = object.into_blob();
+        let mut blob = object.into_blob();
 
-        let mut header = tar::Header::new_gnu();
-        if let Err(error) = header.set_path(self.path.to_path_lossy()) {
-            warn!(%error, "Attempted to write invalid path to archive");
-            return Action::Continue;
+        if let Some(export_subst) = &self.export_subst {
+            if self
+                .attributes
+                .is_set(self.path.as_bstr(), false, EXPORT_SUBST_ATTRIBUTE)
+            {
+                blob.data = export_subst.expand(&blob.data);
+            }
         }
-        header.set_size(blob.data.len() as u64);
-        #[allow(clippy::cast_sign_loss)]
-        header.set_mode(entry.mode().0.into());
-        header.set_cksum();
 
-        if let Err(error) = self.archive.append(&header, blob.data.as_slice()) {
+        let mut path = BString::from(self.prefix.as_str());
+        path.push_str(&self.path);
+        let path = path.to_path_lossy();
+
+        // git modes carry the file type alongside the permissions, whereas tar only wants the
+        // permissions with the type given by the entry type
+        let mut header = tar::Header::new_gnu();
+        let result = match entry.mode().kind() {
+            EntryKind::Link => {
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_mode(0o777);
+                header.set_size(0);
+
+                // the target of a symlink is stored as the contents of its blob
+                self.archive
+                    .append_link(&mut header, &path, blob.data.to_path_lossy())
+            }
+            kind => {
+                header.set_entry_type(tar::EntryType::Regular);
+                header.set_mode(if kind == EntryKind::BlobExecutable {
+                    0o755
+                } else {
+                    0o644
+                });
+                header.set_size(blob.data.len() as u64);
+
+                // unlike `Header::set_path`, these write a GNU long name entry ahead of any path
+                // that doesn't fit in the header rather than failing
+                self.archive
+                    .append_data(&mut header, &path, blob.data.as_slice())
+            }
+        };
+
+        if let Err(error) = result {
             warn!(%error, "Failed to append to archive");
             return Action::Cancel;
         }
@@ -619,8 +1707,58 @@ fn parse_and_transform_markdown(s: &str) -> String {
     options.extension.table = true;
     options.extension.tagfilter = true;
     options.extension.tasklist = true;
+    // generate anchors on headings so sections of READMEs can be deep-linked to
+    options.extension.header_ids = Some(String::new());
+    // READMEs are attacker-controlled, so ensure raw HTML and dangerous URLs are never emitted
+    options.render.unsafe_ = false;
+
+    sanitize_html(&comrak::markdown_to_html_with_plugins(
+        s, &options, &plugins,
+    ))
+}
 
-    comrak::markdown_to_html_with_plugins(s, &options, &plugins)
+/// Runs rendered README HTML through an allowlist sanitizer, comrak's own filtering is a
+/// best-effort blocklist and isn't enough to trust content pushed by arbitrary users.
+fn sanitize_html(html: &str) -> String {
+    /// Prefixed to every id so a README can't clobber elements of the page around it, like
+    /// GitHub does. Links to fragments within the README are rewritten to match.
+    const ID_PREFIX: &str = "user-content-";
+
+    static SANITIZER: LazyLock<ammonia::Builder<'static>> = LazyLock::new(|| {
+        // only the classes we generate ourselves, so a README can't pass itself off as part of
+        // the surrounding page by borrowing its styles
+        let highlight_classes = std::iter::once("highlight")
+            .chain(HIGHLIGHT_CLASSES.iter().flat_map(|v| v.split_whitespace()));
+
+        let mut builder = ammonia::Builder::default();
+        builder
+            // heading anchors and footnotes are linked to by id
+            .add_generic_attributes(["id"])
+            .id_prefix(Some(ID_PREFIX))
+            .add_generic_attribute_prefixes(["data-footnote"])
+            .add_allowed_classes("span", highlight_classes)
+            .add_allowed_classes("a", ["anchor", "footnote-backref"])
+            .add_allowed_classes("section", ["footnotes"])
+            .add_tags(["input", "section"])
+            // task list items are the only inputs comrak emits
+            .add_tag_attributes("input", ["checked"])
+            .set_tag_attribute_value("input", "type", "checkbox")
+            .set_tag_attribute_value("input", "disabled", "")
+            .add_tag_attributes("a", ["aria-hidden"])
+            .attribute_filter(|element, attribute, value| match value.strip_prefix('#') {
+                Some(fragment)
+                    if element == "a"
+                        && attribute == "href"
+                        && !fragment.starts_with(ID_PREFIX) =>
+                {
+                    Some(format!("#{ID_PREFIX}{fragment}").into())
+                }
+                _ => Some(value.into()),
+            });
+        builder
+    });
+
+    SANITIZER.clean(html).to_string()
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -644,6 +1782,7 @@ pub enum TreeItem {
 pub struct Submodule {
     pub mode: u16,
     pub name: String,
+    pub path: PathBuf,
     pub url: Url,
     pub oid: ObjectId,
 }
@@ -667,14 +1806,106 @@ pub struct File {
 #[derive(Debug)]
 #[allow(unused)]
 pub struct FileWithContent {
+    /// Id of the blob, for use as a cache validator since it changes whenever the content does
+    pub id: ObjectId,
     pub metadata: File,
     pub content: Content,
 }
 
-#[derive(Debug)]
-pub enum Content {
-    Text(Cow<'static, str>),
-    Binary(Vec<u8>),
+#[derive(Debug)]
+pub enum Content {
+    Text(Cow<'static, str>),
+    Binary(Vec<u8>),
+    /// The file is over `--max-render-bytes` so wasn't loaded for inline display
+    TooLarge,
+    /// The file is a pointer to an object stored with Git LFS
+    LfsPointer(LfsPointer),
+    /// The object a Git LFS pointer refers to, read from `--lfs-store`
+    LfsObject(LfsPointer, std::fs::File),
+}
+
+/// A Git LFS pointer file, which is committed in place of the actual content of a file.
+///
+/// See <https://github.com/git-lfs/git-lfs/blob/main/docs/spec.md>.
+#[derive(Debug, Clone)]
+pub struct LfsPointer {
+    /// Hex-encoded SHA-256 of the object
+    pub oid: String,
+    /// Size of the object in bytes
+    pub size: u64,
+}
+
+impl LfsPointer {
+    /// Pointer files are tiny, so anything larger than this can be skipped without parsing.
+    const MAX_POINTER_SIZE: usize = 1024;
+
+    fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() > Self::MAX_POINTER_SIZE {
+            return None;
+        }
+
+        let data = simdutf8::basic::from_utf8(data).ok()?;
+        let mut lines = data.lines();
+
+        if !lines
+            .next()?
+            .starts_with("version https://git-lfs.github.com/spec/")
+        {
+            return None;
+        }
+
+        let mut oid = None;
+        let mut size = None;
+
+        for line in lines {
+            if let Some(v) = line.strip_prefix("oid sha256:") {
+                oid = Some(v);
+            } else if let Some(v) = line.strip_prefix("size ") {
+                size = Some(v.parse().ok()?);
+            }
+        }
+
+        let oid = oid.filter(|v| v.len() == 64 && v.bytes().all(|c| c.is_ascii_hexdigit()))?;
+
+        Some(Self {
+            oid: oid.to_ascii_lowercase(),
+            size: size?,
+        })
+    }
+
+    /// Opens the object from an LFS store laid out like `.git/lfs/objects`.
+    fn open(self, store: &Path) -> Option<Content> {
+        let path = store
+            .join(&self.oid[..2])
+            .join(&self.oid[2..4])
+            .join(&self.oid);
+
+        let file = std::fs::File::open(path).ok()?;
+        Some(Content::LfsObject(self, file))
+    }
+}
+
+/// Content for serving a blob raw. Text is served as-is, so has to actually be valid UTF-8 to
+/// be served as text.
+fn raw_content(data: Vec<u8>, binary: bool) -> Content {
+    if !binary && simdutf8::basic::from_utf8(&data).is_ok() {
+        Content::Text(Cow::Owned(unsafe { String::from_utf8_unchecked(data) }))
+    } else {
+        Content::Binary(data)
+    }
+}
+
+impl Content {
+    /// Whether this is a Git LFS pointer rather than the object it points to, which may only
+    /// make it into `--lfs-store` later on.
+    pub fn is_lfs_pointer(&self) -> bool {
+        match self {
+            Self::Text(text) => LfsPointer::parse(text.as_bytes()).is_some(),
+            Self::Binary(data) => LfsPointer::parse(data).is_some(),
+            Self::LfsPointer(_) => true,
+            Self::TooLarge | Self::LfsObject(..) => false,
+        }
+    }
 }
 
 impl IntoResponse for Content {
@@ -698,42 +1929,141 @@ impl IntoResponse for Content {
 
                 (headers, b).into_response()
             }
+            Self::TooLarge => (
+                http::StatusCode::PAYLOAD_TOO_LARGE,
+                "File too large to display",
+            )
+                .into_response(),
+            Self::LfsPointer(_) => {
+                (http::StatusCode::NOT_FOUND, "LFS object not available").into_response()
+            }
+            Self::LfsObject(_, file) => {
+                let headers = [(
+                    http::header::CONTENT_TYPE,
+                    http::HeaderValue::from_static("application/octet-stream"),
+                )];
+
+                let file = tokio::fs::File::from_std(file);
+                (
+                    headers,
+                    axum::body::Body::from_stream(tokio_util::io::ReaderStream::new(file)),
+                )
+                    .into_response()
+            }
         }
     }
 }
 
 #[derive(Debug)]
-pub enum TaggedObject<'a> {
-    Commit(&'a BStr),
-    Tree(&'a BStr),
+pub enum TaggedObject {
+    Commit(ObjectId),
+    Tree(ObjectId),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TagKind {
+    /// A tag object with its own tagger and message
+    Annotated,
+    /// A plain reference to a commit
+    Lightweight,
+}
+
+/// The type of a git object, as named by `git cat-file -t`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ObjectKind {
+    Blob,
+    Tree,
+    Commit,
+    Tag,
+}
+
+impl From<Kind> for ObjectKind {
+    fn from(kind: Kind) -> Self {
+        match kind {
+            Kind::Blob => Self::Blob,
+            Kind::Tree => Self::Tree,
+            Kind::Commit => Self::Commit,
+            Kind::Tag => Self::Tag,
+        }
+    }
+}
+
+impl fmt::Display for ObjectKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Blob => "blob",
+            Self::Tree => "tree",
+            Self::Commit => "commit",
+            Self::Tag => "tag",
+        })
+    }
+}
+
+/// A description of a single object, with whatever else is cheap to show for its type.
+#[derive(Debug, Serialize)]
+pub struct ObjectInfo {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: ObjectKind,
+    /// Size of the object's content in bytes
+    pub size: u64,
+    /// Entries of a tree
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entries: Option<Vec<ObjectTreeEntry>>,
+    /// What an annotated tag points at
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<ObjectTagTarget>,
+    /// The root tree of a commit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tree: Option<String>,
+    /// The parents of a commit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parents: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ObjectTreeEntry {
+    pub mode: String,
+    #[serde(rename = "type")]
+    pub kind: ObjectKind,
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ObjectTagTarget {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: ObjectKind,
+    pub name: String,
 }
 
 #[derive(Debug, Yokeable)]
 pub struct DetailedTag<'a> {
     pub name: Arc<str>,
+    /// The id of the tag object for annotated tags, or the tagged commit for lightweight ones
+    pub id: ObjectId,
+    pub kind: TagKind,
     pub tagger: Option<CommitUser<'a>>,
     pub message: &'a BStr,
-    pub tagged_object: Option<TaggedObject<'a>>,
+    pub tagged_object: Option<TaggedObject>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub struct CommitUser<'a> {
     name: &'a BStr,
     email: &'a BStr,
     time: (i64, i32),
 }
 
-impl<'a> TryFrom<SignatureRef<'a>> for CommitUser<'a> {
-    type Error = anyhow::Error;
-
-    fn try_from(v: SignatureRef<'a>) -> Result<Self> {
-        Ok(CommitUser {
+impl<'a> From<SignatureRef<'a>> for CommitUser<'a> {
+    fn from(v: SignatureRef<'a>) -> Self {
+        CommitUser {
             name: v.name,
             email: v.email,
             time: (v.time.seconds, v.time.offset),
-            // time: OffsetDateTime::from_unix_timestamp(v.time.seconds)?
-            //     .to_offset(UtcOffset::from_whole_seconds(v.time.offset)?),
-        })
+        }
     }
 }
 
@@ -747,9 +2077,10 @@ impl CommitUser<'_> {
     }
 
     pub fn time(&self) -> OffsetDateTime {
-        OffsetDateTime::from_unix_timestamp(self.time.0)
-            .unwrap()
-            .to_offset(UtcOffset::from_whole_seconds(self.time.1).unwrap())
+        let offset = UtcOffset::from_whole_seconds(self.time.1).unwrap_or(UtcOffset::UTC);
+        let utc =
+            OffsetDateTime::from_unix_timestamp(self.time.0).unwrap_or(OffsetDateTime::UNIX_EPOCH);
+        utc.checked_to_offset(offset).unwrap_or(utc)
     }
 }
 
@@ -758,6 +2089,10 @@ pub struct Commit {
     inner: yoke::Yoke<CommitInner<'static>, Vec<u8>>,
     pub diff_stats: String,
     pub diff: String,
+    /// Whether the diff of any file was left out of `diff` for being too large
+    pub diff_truncated: bool,
+    /// The note attached to the commit in `refs/notes/commits`, if `--show-notes` is enabled
+    pub notes: Option<String>,
 }
 
 impl Commit {
@@ -798,12 +2133,12 @@ impl<T: Copy> SmallVec<T> {
 }
 
 impl<'a> CommitInner<'a> {
-    pub fn new(commit: gix::worktree::object::CommitRef<'a>, oid: [u8; 20]) -> Result<Self> {
+    pub fn new(commit: gix::worktree::object::CommitRef<'a>, oid: [u8; 20]) -> Self {
         let message = commit.message();
 
-        Ok(CommitInner {
-            author: CommitUser::try_from(commit.author)?,
-            committer: CommitUser::try_from(commit.committer)?,
+        CommitInner {
+            author: CommitUser::from(commit.author),
+            committer: CommitUser::from(commit.committer),
             oid,
             tree: commit.tree,
             parents: commit.parents.into_inner().map_or_else(
@@ -819,7 +2154,7 @@ impl<'a> CommitInner<'a> {
 
             summary: message.summary(),
             body: message.body.unwrap_or_else(|| BStr::new("")),
-        })
+        }
     }
 }
 
@@ -832,6 +2167,10 @@ impl CommitInner<'_> {
         &self.committer
     }
 
+    pub fn object_id(&self) -> ObjectId {
+        ObjectId::Sha1(self.oid)
+    }
+
     pub fn oid(&self) -> DisplayHexBuffer<20> {
         let mut buf = const_hex::Buffer::new();
         buf.format(&self.oid);
@@ -846,6 +2185,15 @@ impl CommitInner<'_> {
         self.parents.iter()
     }
 
+    /// The first parent of this commit, which is the one the commit was made on top of when
+    /// it's a merge.
+    pub fn first_parent(&self) -> Option<[u8; 20]> {
+        self.parents
+            .iter()
+            .next()
+            .and_then(|v| const_hex::decode_to_array(v).ok())
+    }
+
     pub fn summary(&self) -> &BStr {
         &self.summary
     }
@@ -855,12 +2203,17 @@ impl CommitInner<'_> {
     }
 }
 
+/// Builds the diff of the commit against its first parent along with its diffstat, returning
+/// whether any file's diff was left out for exceeding `max_diff_bytes`. Plain diffs are served
+/// as raw patches, so are never cut short.
 #[instrument(skip(repo, commit))]
 fn fetch_diff_and_stats(
     repo: &gix::Repository,
     commit: &gix::Commit<'_>,
     highlight: bool,
-) -> Result<(String, String)> {
+    options: DiffOptions,
+    max_diff_bytes: usize,
+) -> Result<(String, String, bool)> {
     const WIDTH: usize = 80;
 
     let current_tree = commit.tree().context("Couldn't get tree for the commit")?;
@@ -875,9 +2228,11 @@ fn fetch_diff_and_stats(
         .map(|v| v.tree())
         .transpose()?
         .unwrap_or_else(|| repo.empty_tree());
+    let attributes = GitAttributes::from_tree(&current_tree, BINARY_ATTRIBUTES)?;
 
     let mut diffs = Vec::new();
     let mut diff_output = String::new();
+    let mut diff_truncated = false;
 
     let mut resource_cache = repo.diff_resource_cache_for_tree_diff()?;
 
@@ -894,6 +2249,10 @@ fn fetch_diff_and_stats(
                     output: &mut diff_output,
                     resource_cache: &mut resource_cache,
                     diffs: &mut diffs,
+                    attributes: &attributes,
+                    options,
+                    max_bytes: Some(max_diff_bytes),
+                    truncated: &mut diff_truncated,
                     formatter: SyntaxHighlightedDiffFormatter::new(
                         change.location().to_path().unwrap(),
                     ),
@@ -904,6 +2263,10 @@ fn fetch_diff_and_stats(
                     output: &mut diff_output,
                     resource_cache: &mut resource_cache,
                     diffs: &mut diffs,
+                    attributes: &attributes,
+                    options,
+                    max_bytes: None,
+                    truncated: &mut diff_truncated,
                     formatter: PlainDiffFormatter,
                 }
                 .handle(change)
@@ -918,8 +2281,7 @@ fn fetch_diff_and_stats(
              stats| {
                 (
                     max_file_name_length.max(stats.path.len()),
-                    max_change_length
-                        .max(((stats.insertions + stats.deletions + 1).ilog10() + 1) as usize),
+                    max_change_length.max(decimal_width(stats.insertions + stats.deletions)),
                     files_changed + 1,
                     insertions + stats.insertions,
                     deletions + stats.deletions,
@@ -929,39 +2291,54 @@ fn fetch_diff_and_stats(
 
     let mut diff_stats = String::new();
 
-    let total_changes = insertions + deletions;
+    let max_changes = diffs
+        .iter()
+        .map(|v| v.insertions + v.deletions)
+        .max()
+        .unwrap_or_default();
+    let graph_width =
+        diffstat_graph_width(WIDTH, max_file_name_length, max_change_length, max_changes);
 
     for diff in &diffs {
         let local_changes = diff.insertions + diff.deletions;
-        let width = WIDTH.min(local_changes);
-
-        // Calculate proportions of `+` and `-` within the total width
-        let addition_width = (width * diff.insertions) / total_changes;
-        let deletion_width = (width * diff.deletions) / total_changes;
-
-        // Handle edge case where total width is less than total changes
-        let remaining_width = width - (addition_width + deletion_width);
-        let adjusted_addition_width = addition_width + remaining_width.min(diff.insertions);
-        let adjusted_deletion_width =
-            deletion_width + (remaining_width - remaining_width.min(diff.insertions));
+        let (addition_width, deletion_width) =
+            diffstat_bar(diff.insertions, diff.deletions, graph_width, max_changes);
 
-        // Generate the string representation
-        let plus_str = "+".repeat(adjusted_addition_width);
-        let minus_str = "-".repeat(adjusted_deletion_width);
+        let plus_str = "+".repeat(addition_width);
+        let minus_str = "-".repeat(deletion_width);
 
+        // like git, files without any line changes are listed with just a "0"
+        let separator = if local_changes == 0 { "" } else { " " };
         let file = diff.path.as_str();
-        writeln!(diff_stats, " {file:max_file_name_length$} | {local_changes:max_change_length$} {plus_str}{minus_str}").unwrap();
+        writeln!(diff_stats, " {file:max_file_name_length$} | {local_changes:max_change_length$}{separator}{plus_str}{minus_str}").unwrap();
     }
 
-    for (i, (singular_desc, plural_desc, amount)) in [
-        ("file changed", "files changed", files_changed),
-        ("insertion(+)", "insertions(+)", insertions),
-        ("deletion(-)", "deletions(-)", deletions),
+    // like git, commits without any line changes say "0 insertions(+), 0 deletions(-)" rather
+    // than leaving both out
+    for (i, (singular_desc, plural_desc, amount, shown)) in [
+        (
+            "file changed",
+            "files changed",
+            files_changed,
+            files_changed > 0,
+        ),
+        (
+            "insertion(+)",
+            "insertions(+)",
+            insertions,
+            files_changed > 0 && (insertions > 0 || deletions == 0),
+        ),
+        (
+            "deletion(-)",
+            "deletions(-)",
+            deletions,
+            files_changed > 0 && (deletions > 0 || insertions == 0),
+        ),
     ]
     .into_iter()
     .enumerate()
     {
-        if amount == 0 {
+        if !shown {
             continue;
         }
 
@@ -976,11 +2353,81 @@ fn fetch_diff_and_stats(
         write!(diff_stats, "{prefix} {amount} {desc}")?;
     }
 
-    // TODO: emit 'create mode 100644 pure-black-background-f82588d3.jpg' here
-
     writeln!(diff_stats)?;
 
-    Ok((diff_output, diff_stats))
+    for summary in diffs.iter().filter_map(|v| v.summary.as_deref()) {
+        writeln!(diff_stats, " {summary}")?;
+    }
+
+    Ok((diff_output, diff_stats, diff_truncated))
+}
+
+/// The number of digits needed to print `n`.
+fn decimal_width(n: usize) -> usize {
+    n.checked_ilog10().unwrap_or_default() as usize + 1
+}
+
+/// Works out how many columns the `+`/`-` graph of a diffstat gets, the same way `git diff --stat`
+/// does for a terminal `width` columns wide. The graph is only as wide as the largest change
+/// needs, unless that would overflow the line, in which case it shrinks to whatever space the
+/// file names leave but never below `3/8` of the width.
+fn diffstat_graph_width(
+    width: usize,
+    name_width: usize,
+    number_width: usize,
+    max_changes: usize,
+) -> usize {
+    // " name | count graph", the separator and padding take up 6 columns
+    let fixed = number_width + 6;
+
+    if name_width + fixed + max_changes <= width {
+        return max_changes;
+    }
+
+    let minimum = (width * 3 / 8)
+        .saturating_sub(fixed)
+        .max(6)
+        .min(max_changes);
+    width
+        .saturating_sub(fixed + name_width)
+        .max(minimum)
+        .min(max_changes)
+}
+
+/// Splits a file's changes into the number of `+` and `-` to draw in a graph `graph_width`
+/// columns wide, scaled against the largest change in the diff like `git` does. Files that
+/// changed at all always get at least one column per side that changed, and files without any
+/// line changes (ie. mode changes) don't get a bar at all.
+fn diffstat_bar(
+    insertions: usize,
+    deletions: usize,
+    graph_width: usize,
+    max_changes: usize,
+) -> (usize, usize) {
+    fn scale_linear(it: usize, width: usize, max: usize) -> usize {
+        if it == 0 || max == 0 {
+            0
+        } else {
+            1 + (it * width.saturating_sub(1)) / max
+        }
+    }
+
+    if max_changes <= graph_width {
+        return (insertions, deletions);
+    }
+
+    let mut total = scale_linear(insertions + deletions, graph_width, max_changes);
+    if total < 2 && insertions > 0 && deletions > 0 {
+        total = 2;
+    }
+
+    if insertions < deletions {
+        let additions = scale_linear(insertions, total, insertions + deletions);
+        (additions, total - additions)
+    } else {
+        let removals = scale_linear(deletions, total, insertions + deletions);
+        (total - removals, removals)
+    }
 }
 
 #[derive(Default, Debug)]
@@ -988,9 +2435,16 @@ struct FileDiff {
     path: String,
     insertions: usize,
     deletions: usize,
+    /// The `create mode`/`delete mode`/`mode change` line git summarises the file with after
+    /// the diffstat, if any
+    summary: Option<String>,
 }
 
 trait DiffFormatter {
+    /// Called with the full content of both sides of a text diff before any of its lines are
+    /// written.
+    fn prepare(&mut self, _old: &str, _new: &str) {}
+
     fn file_header(&self, output: &mut String, data: fmt::Arguments<'_>);
 
     fn binary(
@@ -1001,12 +2455,22 @@ trait DiffFormatter {
         left_content: &[u8],
         right_content: &[u8],
     );
+
+    /// Written in place of a text diff that's too large to show.
+    fn too_large(&self, output: &mut String);
 }
 
 struct DiffBuilder<'a, F> {
     output: &'a mut String,
     resource_cache: &'a mut gix::diff::blob::Platform,
     diffs: &'a mut Vec<FileDiff>,
+    attributes: &'a GitAttributes,
+    options: DiffOptions,
+    /// The size `output` can grow to, the diff of any file that would take it past this is left
+    /// out
+    max_bytes: Option<usize>,
+    /// Set once the diff of any file has been left out
+    truncated: &'a mut bool,
     formatter: F,
 }
 
@@ -1024,6 +2488,7 @@ impl<'a, F: DiffFormatter + Callback> DiffBuilder<'a, F> {
             path: change.location().to_string(),
             insertions: 0,
             deletions: 0,
+            summary: None,
         };
         let change = change.diff(self.resource_cache)?;
 
@@ -1042,11 +2507,21 @@ impl<'a, F: DiffFormatter + Callback> DiffBuilder<'a, F> {
                 self.output,
                 format_args!("new file mode {}", prep.new.mode.as_octal_str()),
             );
+            diff.summary = Some(format!(
+                "create mode {} {}",
+                prep.new.mode.as_octal_str(),
+                diff.path
+            ));
         } else if prep.new.id.is_null() {
             self.formatter.file_header(
                 self.output,
                 format_args!("deleted file mode {}", prep.old.mode.as_octal_str()),
             );
+            diff.summary = Some(format!(
+                "delete mode {} {}",
+                prep.old.mode.as_octal_str(),
+                diff.path
+            ));
         } else if prep.new.mode != prep.old.mode {
             self.formatter.file_header(
                 self.output,
@@ -1056,6 +2531,12 @@ impl<'a, F: DiffFormatter + Callback> DiffBuilder<'a, F> {
                 self.output,
                 format_args!("new mode {}", prep.new.mode.as_octal_str()),
             );
+            diff.summary = Some(format!(
+                "mode change {} => {} {}",
+                prep.old.mode.as_octal_str(),
+                prep.new.mode.as_octal_str(),
+                diff.path
+            ));
         }
 
         // copy from
@@ -1082,11 +2563,23 @@ impl<'a, F: DiffFormatter + Callback> DiffBuilder<'a, F> {
         let new_path = if prep.new.id.is_null() {
             Cow::Borrowed("/dev/null")
         } else {
-            Cow::Owned(format!("a/{}", prep.new.rela_path))
+            Cow::Owned(format!("b/{}", prep.new.rela_path))
         };
 
+        let binary = is_binary(
+            self.attributes,
+            prep.old.rela_path,
+            prep.old.data.as_slice().unwrap_or_default(),
+        ) || is_binary(
+            self.attributes,
+            prep.new.rela_path,
+            prep.new.data.as_slice().unwrap_or_default(),
+        );
+
         match prep.operation {
-            Operation::InternalDiff { algorithm } => {
+            Operation::InternalDiff { algorithm } if !binary => {
+                let algorithm = self.options.algorithm.map_or(algorithm, Into::into);
+
                 self.formatter.file_header(
                     self.output,
                     format_args!(
@@ -1100,26 +2593,67 @@ impl<'a, F: DiffFormatter + Callback> DiffBuilder<'a, F> {
                 self.formatter
                     .file_header(self.output, format_args!("+++ {new_path}"));
 
-                let old_source = gix::diff::blob::sources::lines_with_terminator(
-                    simdutf8::basic::from_utf8(prep.old.data.as_slice().unwrap_or_default())?,
-                );
-                let new_source = gix::diff::blob::sources::lines_with_terminator(
-                    simdutf8::basic::from_utf8(prep.new.data.as_slice().unwrap_or_default())?,
-                );
+                let old_data = lossy_utf8(prep.old.data.as_slice().unwrap_or_default());
+                let new_data = lossy_utf8(prep.new.data.as_slice().unwrap_or_default());
+                let (old_data, new_data) = (old_data.as_ref(), new_data.as_ref());
+
+                // checked before anything is written so a huge file doesn't get highlighted or
+                // rendered only to be thrown away
+                let too_large = self.max_bytes.is_some_and(|max| {
+                    self.output.len() >= max || old_data.len() + new_data.len() > max
+                });
+
+                let old_source = gix::diff::blob::sources::lines_with_terminator(old_data);
+                let new_source = gix::diff::blob::sources::lines_with_terminator(new_data);
                 let input = gix::diff::blob::intern::InternedInput::new(old_source, new_source);
 
-                let output = gix::diff::blob::diff(
-                    algorithm,
-                    &input,
-                    UnifiedDiffBuilder::with_writer(&input, &mut *self.output, &mut self.formatter)
+                let (removals, insertions) = if too_large {
+                    *self.truncated = true;
+                    self.formatter.too_large(self.output);
+
+                    // the line counts are still needed for the diffstat
+                    let counter = gix::diff::blob::diff(
+                        algorithm,
+                        &input,
+                        gix::diff::blob::sink::Counter::default(),
+                    );
+                    (counter.removals, counter.insertions)
+                } else {
+                    self.formatter.prepare(old_data, new_data);
+
+                    let mut rendered = String::new();
+                    let output = gix::diff::blob::diff(
+                        algorithm,
+                        &input,
+                        UnifiedDiffBuilder::with_writer(
+                            &input,
+                            &mut rendered,
+                            &mut self.formatter,
+                            self.options.context_lines,
+                        )
                         .with_counter(),
-                );
+                    );
+
+                    // highlighting can make the rendered diff several times larger than the
+                    // files themselves, so the limit is checked again on what was rendered
+                    if self
+                        .max_bytes
+                        .is_some_and(|max| self.output.len() + rendered.len() > max)
+                    {
+                        *self.truncated = true;
+                        self.formatter.too_large(self.output);
+                    } else {
+                        self.output.push_str(&rendered);
+                    }
+
+                    (output.removals, output.insertions)
+                };
 
-                diff.deletions += output.removals as usize;
-                diff.insertions += output.insertions as usize;
+                diff.deletions += removals as usize;
+                diff.insertions += insertions as usize;
             }
             Operation::ExternalCommand { .. } => {}
-            Operation::SourceOrDestinationIsBinary => {
+            Operation::InternalDiff { .. } | Operation::SourceOrDestinationIsBinary => {
                 self.formatter.file_header(
                     self.output,
                     format_args!(
@@ -1163,39 +2697,61 @@ impl DiffFormatter for PlainDiffFormatter {
         // todo: actually perform the diff and write a `GIT binary patch` out
         writeln!(output, "Binary files {left} and {right} differ").unwrap();
     }
+
+    fn too_large(&self, output: &mut String) {
+        writeln!(output, "Diff too large to display").unwrap();
+    }
 }
 
 impl Callback for PlainDiffFormatter {
-    fn addition(&mut self, data: &str, dst: &mut String) {
+    fn addition(&mut self, _line: usize, data: &str, dst: &mut String) {
         write!(dst, "+{data}").unwrap();
     }
 
-    fn remove(&mut self, data: &str, dst: &mut String) {
+    fn remove(&mut self, _line: usize, data: &str, dst: &mut String) {
         write!(dst, "-{data}").unwrap();
     }
 
-    fn context(&mut self, data: &str, dst: &mut String) {
+    fn context(&mut self, _line: usize, data: &str, dst: &mut String) {
         write!(dst, " {data}").unwrap();
     }
 }
 
 struct SyntaxHighlightedDiffFormatter<'a> {
     path: &'a Path,
+    old_lines: Vec<String>,
+    new_lines: Vec<String>,
 }
 
 impl<'a> SyntaxHighlightedDiffFormatter<'a> {
     fn new(path: &'a Path) -> Self {
-        Self { path }
+        Self {
+            path,
+            old_lines: Vec::new(),
+            new_lines: Vec::new(),
+        }
     }
 
-    fn write(&self, output: &mut String, class: &str, data: &str) {
+    fn write(&self, output: &mut String, class: &str, highlighted: Option<&str>, data: &str) {
         write!(output, r#"<span class="diff-{class}">"#).unwrap();
-        format_file_inner(output, data, FileIdentifier::Path(self.path), false).unwrap();
+        if let Some(highlighted) = highlighted {
+            output.push_str(highlighted);
+            output.push('\n');
+        } else {
+            format_file_inner(output, data, FileIdentifier::Path(self.path), false).unwrap();
+        }
         write!(output, r#"</span>"#).unwrap();
     }
 }
 
 impl<'a> DiffFormatter for SyntaxHighlightedDiffFormatter<'a> {
+    fn prepare(&mut self, old: &str, new: &str) {
+        // highlight both sides in full upfront, as highlighting each line of the diff on its own
+        // loses the context of constructs spanning multiple lines
+        self.old_lines = highlight_lines(old, FileIdentifier::Path(self.path));
+        self.new_lines = highlight_lines(new, FileIdentifier::Path(self.path));
+    }
+
     fn file_header(&self, output: &mut String, data: Arguments<'_>) {
         write!(output, r#"<span class="diff-file-header">"#).unwrap();
         write!(output, "{data}").unwrap();
@@ -1212,18 +2768,773 @@ impl<'a> DiffFormatter for SyntaxHighlightedDiffFormatter<'a> {
     ) {
         write!(output, "Binary files {left} and {right} differ").unwrap();
     }
+
+    fn too_large(&self, output: &mut String) {
+        writeln!(
+            output,
+            r#"<span class="diff-too-large">Diff too large to display</span>"#
+        )
+        .unwrap();
+    }
 }
 
 impl<'a> Callback for SyntaxHighlightedDiffFormatter<'a> {
-    fn addition(&mut self, data: &str, dst: &mut String) {
-        self.write(dst, "add-line", data);
+    fn addition(&mut self, line: usize, data: &str, dst: &mut String) {
+        self.write(
+            dst,
+            "add-line",
+            self.new_lines.get(line).map(String::as_str),
+            data,
+        );
+    }
+
+    fn remove(&mut self, line: usize, data: &str, dst: &mut String) {
+        self.write(
+            dst,
+            "remove-line",
+            self.old_lines.get(line).map(String::as_str),
+            data,
+        );
+    }
+
+    fn context(&mut self, line: usize, data: &str, dst: &mut String) {
+        self.write(
+            dst,
+            "context",
+            self.old_lines.get(line).map(String::as_str),
+            data,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, io::Read, path::PathBuf, str::FromStr, sync::Arc};
+
+    use axum::{http::StatusCode, response::IntoResponse};
+    use gix::ObjectId;
+
+    use super::{
+        parse_and_transform_markdown, repair_commit_signatures, sanitize_html, BadRequest, Content,
+        DiffAlgorithm, DiffOptions, FileLimits, GitConfig, OpenRepository, PathDestination,
+        ReadmeFormat,
+    };
+    use crate::test_util::{self, git_with, Fixture};
+
+    async fn file_content(repo: &Arc<OpenRepository>, path: &str) -> Content {
+        file_content_as(repo, path, true).await
+    }
+
+    async fn file_content_as(repo: &Arc<OpenRepository>, path: &str, formatted: bool) -> Content {
+        let (destination, _) = repo
+            .clone()
+            .path(Some(PathBuf::from(path)), None, formatted)
+            .await
+            .unwrap();
+
+        let PathDestination::File(file) = destination else {
+            panic!("{path} isn't a file");
+        };
+
+        file.content
+    }
+
+    /// A Rust comment of exactly `size` bytes, which is highlighted if it's highlighted at all.
+    fn comment_of_size(size: usize) -> String {
+        format!("// {}\n", "a".repeat(size - 4))
+    }
+
+    /// Builds a snapshot the same way the snapshot endpoint does, returning the path and content
+    /// of every file in it.
+    async fn snapshot(
+        repo: &Arc<OpenRepository>,
+        commit: Option<&str>,
+    ) -> BTreeMap<String, Vec<u8>> {
+        let (res, mut recv) = tokio::sync::mpsc::channel(1);
+        let (cont, _cont) = tokio::sync::oneshot::channel();
+
+        let (archived, gzipped) = tokio::join!(
+            repo.clone().archive(res, cont, commit, String::new()),
+            async move {
+                let mut gzipped = Vec::new();
+                while let Some(chunk) = recv.recv().await {
+                    gzipped.extend_from_slice(&chunk.unwrap());
+                }
+                gzipped
+            }
+        );
+        archived.unwrap();
+
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(gzipped.as_slice()));
+        archive
+            .entries()
+            .unwrap()
+            .map(Result::unwrap)
+            .filter(|entry| entry.header().entry_type().is_file())
+            .map(|mut entry| {
+                let path = entry.path().unwrap().to_string_lossy().into_owned();
+                let mut content = Vec::new();
+                entry.read_to_end(&mut content).unwrap();
+                (path, content)
+            })
+            .collect()
     }
 
-    fn remove(&mut self, data: &str, dst: &mut String) {
-        self.write(dst, "remove-line", data);
+    #[tokio::test]
+    async fn file_size_limits_are_inclusive() {
+        let mut fixture = Fixture::new();
+        for (name, size) in [
+            ("highlight-under.rs", 99),
+            ("highlight-at.rs", 100),
+            ("highlight-over.rs", 101),
+            ("render-under.rs", 199),
+            ("render-at.rs", 200),
+            ("render-over.rs", 201),
+        ] {
+            fixture.write(name, comment_of_size(size));
+        }
+        fixture.commit("sized files");
+
+        let git = git_with(GitConfig {
+            file_limits: FileLimits {
+                max_highlight_bytes: 100,
+                max_render_bytes: 200,
+                max_diff_bytes: 1024,
+            },
+            ..GitConfig::default()
+        });
+        let repo = fixture.open(&git).await;
+
+        for (path, highlighted) in [
+            ("highlight-under.rs", true),
+            ("highlight-at.rs", true),
+            ("highlight-over.rs", false),
+        ] {
+            let Content::Text(text) = file_content(&repo, path).await else {
+                panic!("{path} wasn't rendered as text");
+            };
+            assert_eq!(text.contains("<span"), highlighted, "{path}: {text}");
+        }
+
+        for (path, rendered) in [
+            ("render-under.rs", true),
+            ("render-at.rs", true),
+            ("render-over.rs", false),
+        ] {
+            let content = file_content(&repo, path).await;
+            assert_eq!(
+                matches!(content, Content::Text(_)),
+                rendered,
+                "{path}: {content:?}"
+            );
+            assert_eq!(matches!(content, Content::TooLarge), !rendered, "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn root_gitattributes_apply_to_nested_files() {
+        let mut fixture = Fixture::new();
+        fixture.write(".gitattributes", "*.dat binary\n");
+        fixture.write("top.dat", "plain text\n");
+        fixture.write("nested/deeper/file.dat", "plain text\n");
+        fixture.write("nested/deeper/file.txt", "plain text\n");
+        fixture.commit("attributes");
+
+        let repo = fixture.open(&test_util::git()).await;
+
+        for path in ["top.dat", "nested/deeper/file.dat"] {
+            let content = file_content(&repo, path).await;
+            assert!(matches!(content, Content::Binary(_)), "{path}: {content:?}");
+        }
+
+        let content = file_content(&repo, "nested/deeper/file.txt").await;
+        assert!(matches!(content, Content::Text(_)), "{content:?}");
+    }
+
+    #[tokio::test]
+    async fn refs_containing_skips_refs_to_non_commits() {
+        let mut fixture = Fixture::new();
+        fixture.write("file", "a");
+        let first = fixture.commit("first");
+        fixture.write("file", "b");
+        fixture.commit("second");
+        fixture.git(&["branch", "old", &first]);
+        fixture.git(&["tag", "v1.0", &first]);
+        fixture.git(&["tag", "tree-tag", "HEAD^{tree}"]);
+        fixture.git(&["tag", "-a", "-m", "blob", "blob-tag", "HEAD:file"]);
+
+        let git = git_with(GitConfig {
+            contained_refs_max_commits: Some(100),
+            ..GitConfig::default()
+        });
+        let repo = fixture.open(&git).await;
+
+        let contained = repo
+            .refs_containing(ObjectId::from_str(&first).unwrap())
+            .await
+            .unwrap()
+            .unwrap();
+
+        let mut branches = contained.branches.clone();
+        branches.sort();
+        assert_eq!(branches, ["main", "old"]);
+        assert_eq!(contained.tags, ["v1.0"]);
+        assert!(!contained.truncated);
+    }
+
+    #[test]
+    fn readme_html_is_sanitized() {
+        let clean = sanitize_html(concat!(
+            r#"<img src="x" onerror="alert(1)">"#,
+            r#"<a href="javascript:alert(1)">js</a>"#,
+            r#"<form id="config"><input type="password" name="pass" value="x"></form>"#,
+            r#"<span class="highlight keyword language-bar">k</span>"#,
+            r##"<a href="#section" id="section">s</a>"##,
+        ));
+
+        assert!(!clean.contains("onerror"), "{clean}");
+        assert!(!clean.contains("javascript"), "{clean}");
+        assert!(!clean.contains("<form"), "{clean}");
+        assert!(!clean.contains("password"), "{clean}");
+        assert!(!clean.contains("name="), "{clean}");
+        assert!(clean.contains(r#"type="checkbox""#), "{clean}");
+        assert!(clean.contains(r#"disabled="""#), "{clean}");
+        assert!(!clean.contains(r#"id="config""#), "{clean}");
+        assert!(clean.contains(r#"id="user-content-config""#), "{clean}");
+        assert!(
+            clean.contains(r#"<span class="highlight keyword">"#),
+            "{clean}"
+        );
+        assert!(
+            clean.contains(r##"href="#user-content-section""##),
+            "{clean}"
+        );
+        assert!(clean.contains(r#"id="user-content-section""#), "{clean}");
+    }
+
+    #[test]
+    fn readme_markdown_keeps_task_lists_and_anchors() {
+        let html = parse_and_transform_markdown(
+            "# Getting started\n\n- [x] done\n- [ ] todo\n\n[back](#getting-started)\n",
+        );
+
+        assert_eq!(html.matches(r#"type="checkbox""#).count(), 2, "{html}");
+        assert_eq!(html.matches("checked").count(), 1, "{html}");
+        assert!(
+            html.contains(r#"id="user-content-getting-started""#),
+            "{html}"
+        );
+        assert!(
+            html.contains(r##"href="#user-content-getting-started""##),
+            "{html}"
+        );
+    }
+
+    #[test]
+    fn only_signatures_gix_cant_parse_are_repaired() {
+        let commit = |author: &str| {
+            format!(
+                "tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+                 author {author}\n\
+                 committer Jane <jane@example.com> 1700000000 +0000\n\
+                 \n\
+                 message\n"
+            )
+        };
+
+        // gix reads these fine, so they're left as they are
+        for author in [
+            "Jane <jane@example.com> 1700000000 +0000",
+            "Jane <jane@example.com> 1700000000 +01",
+            "Jane <jane@example.com>",
+            "<jane@example.com> 1700000000 +0000",
+        ] {
+            assert_eq!(
+                repair_commit_signatures(commit(author).as_bytes()),
+                None,
+                "{author}"
+            );
+        }
+
+        for author in ["", "Jane", "Jane jane@example.com 1700000000 +0000"] {
+            let repaired = repair_commit_signatures(commit(author).as_bytes())
+                .unwrap_or_else(|| panic!("{author:?} wasn't repaired"));
+            assert_eq!(
+                String::from_utf8(repaired).unwrap(),
+                commit("unknown <unknown> 0 +0000"),
+                "{author:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn commits_with_broken_signatures_are_shown() {
+        let fixture = Fixture::new();
+        let tree = fixture.git_with_stdin(&["mktree"], b"");
+        let commit = fixture.write_raw_object(
+            "commit",
+            format!(
+                "tree {tree}\n\
+                 author Jane\n\
+                 committer Jane <jane@example.com> 1700000000 +0000\n\
+                 \n\
+                 broken author\n"
+            )
+            .as_bytes(),
+        );
+        fixture.git(&["update-ref", "refs/heads/main", &commit]);
+
+        let repo = fixture.open(&test_util::git()).await;
+        let commit = repo
+            .commit(
+                &commit,
+                false,
+                DiffOptions {
+                    context_lines: 3,
+                    algorithm: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(commit.get().author().name(), "unknown");
+        assert_eq!(commit.get().committer().name(), "Jane");
+        assert_eq!(
+            commit.get().committer().time().unix_timestamp(),
+            1_700_000_000
+        );
+    }
+
+    #[tokio::test]
+    async fn lfs_pointers_are_detected_and_resolved() {
+        const OID: &str = "4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393";
+
+        let mut fixture = Fixture::new();
+        fixture.write_lfs_pointer("model.bin", OID, 12345);
+        fixture.write_lfs_pointer("missing.bin", &"0".repeat(64), 1);
+        fixture.commit("lfs pointers");
+
+        let store = test_util::lfs_store(&[(OID, b"actual content")]);
+
+        let git = git_with(GitConfig {
+            lfs_store: Some(store.path().to_path_buf()),
+            ..GitConfig::default()
+        });
+        let repo = fixture.open(&git).await;
+
+        let Content::LfsPointer(pointer) = file_content(&repo, "model.bin").await else {
+            panic!("pointer wasn't detected");
+        };
+        assert_eq!(pointer.oid, OID);
+        assert_eq!(pointer.size, 12345);
+        assert_eq!(
+            Content::LfsPointer(pointer).into_response().status(),
+            StatusCode::NOT_FOUND
+        );
+
+        let Content::LfsObject(pointer, _) = file_content_as(&repo, "model.bin", false).await
+        else {
+            panic!("object wasn't served from the store");
+        };
+        assert_eq!(pointer.oid, OID);
+
+        // pointers to objects the store doesn't have are served as they are
+        assert!(matches!(
+            file_content_as(&repo, "missing.bin", false).await,
+            Content::Text(text) if text.starts_with("version https://git-lfs")
+        ));
+    }
+
+    #[tokio::test]
+    async fn snapshots_respect_export_attributes() {
+        let mut fixture = Fixture::new();
+        fixture.write(
+            ".gitattributes",
+            "secret.txt export-ignore\nignored export-ignore\nversion.txt export-subst\n",
+        );
+        fixture.write("secret.txt", "hidden\n");
+        fixture.write("ignored/nested/file.txt", "hidden\n");
+        fixture.write("kept/file.txt", "kept\n");
+        fixture.write("version.txt", "$Format:%H %an$\n");
+        let commit = fixture.commit("export attributes");
+
+        let repo = fixture.open(&test_util::git()).await;
+        let files = snapshot(&repo, None).await;
+
+        assert_eq!(
+            files.keys().map(String::as_str).collect::<Vec<_>>(),
+            [".gitattributes", "kept/file.txt", "version.txt"]
+        );
+        assert_eq!(files["version.txt"], format!("{commit} Test\n").as_bytes());
+    }
+
+    /// The plain diffstat of a commit, as shown at the top of its patch.
+    async fn diff_stats(repo: &Arc<OpenRepository>, commit: &str) -> String {
+        repo.clone()
+            .commit(
+                commit,
+                false,
+                DiffOptions {
+                    context_lines: 3,
+                    algorithm: None,
+                },
+            )
+            .await
+            .unwrap()
+            .diff_stats
+            .clone()
+    }
+
+    fn make_executable(fixture: &Fixture, path: &str) {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = fixture.path().join(path);
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[tokio::test]
+    async fn diffstats_of_commits_without_line_changes() {
+        let mut fixture = Fixture::new();
+        fixture.write("run.sh", "echo hi\n");
+        fixture.commit("script");
+        make_executable(&fixture, "run.sh");
+        let commit = fixture.commit("make executable");
+
+        let repo = fixture.open(&test_util::git()).await;
+        assert_eq!(
+            diff_stats(&repo, &commit).await,
+            " run.sh | 0\n\
+             \x201 file changed, 0 insertions(+), 0 deletions(-)\n\
+             \x20mode change 100644 => 100755 run.sh\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn diffstats_are_scaled_like_git() {
+        let mut fixture = Fixture::new();
+        fixture.write("small.txt", "a\nb\nc\n");
+        fixture.write("gone.txt", "1\n2\n");
+        fixture.write("run.sh", "echo hi\n");
+        fixture.commit("initial");
+
+        let big: String = (1..=100).map(|i| format!("{i}\n")).collect();
+        fixture.write("big.txt", big);
+        fixture.write("small.txt", "a\nb\nx\ny\nz\n");
+        std::fs::remove_file(fixture.path().join("gone.txt")).unwrap();
+        make_executable(&fixture, "run.sh");
+        let commit = fixture.commit("mixed");
+
+        // as printed by `git show --stat=80 --summary`
+        let repo = fixture.open(&test_util::git()).await;
+        assert_eq!(
+            diff_stats(&repo, &commit).await,
+            format!(
+                " big.txt   | 100 {}\n\
+                 \x20gone.txt  |   2 --\n\
+                 \x20run.sh    |   0\n\
+                 \x20small.txt |   4 ++-\n\
+                 \x204 files changed, 103 insertions(+), 3 deletions(-)\n\
+                 \x20create mode 100644 big.txt\n\
+                 \x20delete mode 100644 gone.txt\n\
+                 \x20mode change 100644 => 100755 run.sh\n",
+                "+".repeat(62)
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn snapshots_keep_paths_longer_than_a_tar_header() {
+        let nested = format!("{}/{}/file.txt", "a".repeat(60), "b".repeat(60));
+        let long_name = format!("{}.txt", "c".repeat(150));
+
+        let mut fixture = Fixture::new();
+        fixture.write(&nested, "nested\n");
+        fixture.write(&long_name, "long\n");
+        fixture.commit("long paths");
+
+        let repo = fixture.open(&test_util::git()).await;
+        let files = snapshot(&repo, None).await;
+
+        assert_eq!(files.len(), 2, "{:?}", files.keys());
+        assert_eq!(files[&nested], b"nested\n");
+        assert_eq!(files[&long_name], b"long\n");
+    }
+
+    #[tokio::test]
+    async fn snapshots_of_refs_to_trees_are_left_unsubstituted() {
+        let mut fixture = Fixture::new();
+        fixture.write(".gitattributes", "version.txt export-subst\n");
+        fixture.write("version.txt", "$Format:%H$\n");
+        fixture.commit("export attributes");
+        let tree = fixture.git(&["rev-parse", "HEAD^{tree}"]);
+        fixture.git(&["update-ref", "refs/tags/tree", &tree]);
+
+        let repo = test_util::git()
+            .repo(fixture.path(), Some(Arc::from("refs/tags/tree")))
+            .await
+            .unwrap();
+        let files = snapshot(&repo, None).await;
+
+        assert_eq!(files["version.txt"], b"$Format:%H$\n");
+    }
+
+    #[tokio::test]
+    async fn snapshots_of_commits_with_broken_signatures_substitute_nothing() {
+        let mut fixture = Fixture::new();
+        fixture.write(".gitattributes", "version.txt export-subst\n");
+        fixture.write("version.txt", "$Format:[%an] [%cn]$\n");
+        fixture.commit("export attributes");
+        let tree = fixture.git(&["rev-parse", "HEAD^{tree}"]);
+        let commit = fixture.write_raw_object(
+            "commit",
+            format!(
+                "tree {tree}\n\
+                 author Jane\n\
+                 committer Jane <jane@example.com> 1700000000 +0000\n\
+                 \n\
+                 broken author\n"
+            )
+            .as_bytes(),
+        );
+        fixture.git(&["update-ref", "refs/heads/main", &commit]);
+
+        let repo = fixture.open(&test_util::git()).await;
+        let files = snapshot(&repo, None).await;
+
+        assert_eq!(files["version.txt"], b"[] [Jane]\n");
+    }
+
+    #[tokio::test]
+    async fn unusable_commit_ranges_are_bad_requests() {
+        let mut fixture = Fixture::new();
+        let base = fixture.commit("base");
+        fixture.git(&["checkout", "-q", "--orphan", "unrelated"]);
+        let unrelated = fixture.commit("unrelated");
+        fixture.git(&["checkout", "-q", "main"]);
+        fixture.import_commits("long", 251);
+        let long = fixture.git(&["rev-parse", "long"]);
+
+        let repo = fixture.open(&test_util::git()).await;
+
+        for (from, to) in [(&unrelated, &base), (&base, &long)] {
+            let error = repo.clone().commit_range(from, Some(to)).await.unwrap_err();
+            assert!(error.downcast_ref::<BadRequest>().is_some(), "{error:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn commits_are_found_by_short_id_and_branch_name() {
+        const OPTIONS: DiffOptions = DiffOptions {
+            context_lines: 3,
+            algorithm: None,
+        };
+
+        let mut fixture = Fixture::new();
+        let first = fixture.commit("first");
+        fixture.git(&["branch", "feature"]);
+        let second = fixture.commit("second");
+
+        let repo = fixture.open(&test_util::git()).await;
+
+        for (spec, expected) in [
+            (&first[..7], &first),
+            ("feature", &first),
+            ("main", &second),
+        ] {
+            let commit = repo.clone().commit(spec, false, OPTIONS).await.unwrap();
+            assert_eq!(commit.get().object_id().to_string(), *expected, "{spec}");
+        }
+    }
+
+    #[tokio::test]
+    async fn ambiguous_short_ids_are_bad_requests() {
+        let mut fixture = Fixture::new();
+        // enough objects that some are bound to share their first four hex digits
+        fixture.import_commits("main", 400);
+
+        let objects = fixture.git(&["rev-list", "--objects", "--all"]);
+        let mut prefixes = std::collections::HashSet::new();
+        let ambiguous = objects
+            .lines()
+            .map(|line| &line[..4])
+            .find(|prefix| !prefixes.insert(*prefix))
+            .expect("no objects share a prefix");
+
+        let repo = fixture.open(&test_util::git()).await;
+        let error = repo
+            .commit(
+                ambiguous,
+                false,
+                DiffOptions {
+                    context_lines: 3,
+                    algorithm: None,
+                },
+            )
+            .await
+            .unwrap_err();
+
+        let message = error.downcast_ref::<BadRequest>().map(|v| v.0);
+        assert!(
+            message.is_some_and(|v| v.contains("ambiguous")),
+            "{error:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn diff_size_limit_applies_to_the_rendered_diff() {
+        let mut fixture = Fixture::new();
+        // well under the limit as source, but not once every token is wrapped in a span
+        fixture.write("code.rs", "fn a() {}\n".repeat(90));
+        let commit = fixture.commit("code");
+
+        let git = git_with(GitConfig {
+            file_limits: FileLimits {
+                max_highlight_bytes: 1024 * 1024,
+                max_render_bytes: 1024 * 1024,
+                max_diff_bytes: 1000,
+            },
+            ..GitConfig::default()
+        });
+        let repo = fixture.open(&git).await;
+        let commit = repo
+            .commit(
+                &commit,
+                true,
+                DiffOptions {
+                    context_lines: 3,
+                    algorithm: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        assert!(commit.diff_truncated);
+        assert!(commit.diff.len() <= 1000, "{}", commit.diff);
+    }
+
+    #[tokio::test]
+    async fn readme_code_blocks_are_highlighted_by_language_name_or_extension() {
+        let mut fixture = Fixture::new();
+        fixture.write(
+            "README.md",
+            "```rust\nfn main() {}\n```\n\n\
+             ```rs\nfn main() {}\n```\n\n\
+             ```sh\necho \"hello\"\n```\n\n\
+             ```Python\ndef main(): pass\n```\n\n\
+             ```not-a-language\nfn main() {}\n```\n",
+        );
+        fixture.commit("readme");
+
+        let repo = fixture.open(&test_util::git()).await;
+        let (format, html) = repo.readme().await.unwrap().unwrap();
+        assert_eq!(format, ReadmeFormat::Markdown);
+
+        let blocks: Vec<_> = html.split("<pre>").skip(1).collect();
+        assert_eq!(blocks.len(), 5, "{html}");
+
+        for block in &blocks[..4] {
+            assert!(block.contains(r#"<span class="highlight"#), "{block}");
+        }
+        assert!(
+            !blocks[4].contains(r#"<span class="highlight"#),
+            "{}",
+            blocks[4]
+        );
+    }
+
+    #[tokio::test]
+    async fn diff_lines_inside_block_comments_are_highlighted_as_comments() {
+        let mut fixture = Fixture::new();
+        fixture.write("lib.rs", "/*\n first\n second\n*/\nfn main() {}\n");
+        fixture.commit("comment");
+        fixture.write("lib.rs", "/*\n first\n changed\n*/\nfn main() {}\n");
+        let commit = fixture.commit("change comment");
+
+        let repo = fixture.open(&test_util::git()).await;
+        let commit = repo
+            .commit(
+                &commit,
+                true,
+                DiffOptions {
+                    context_lines: 3,
+                    algorithm: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        // none of these lines look like a comment on their own, only as part of the whole file
+        for (class, content) in [
+            ("context", " first"),
+            ("remove-line", " second"),
+            ("add-line", " changed"),
+        ] {
+            let line = commit
+                .diff
+                .split(&format!(r#"<span class="diff-{class}">"#))
+                .map(|line| line.lines().next().unwrap_or_default())
+                .find(|line| line.contains(content))
+                .unwrap_or_else(|| panic!("no {class} line in {}", commit.diff));
+
+            assert!(
+                line.starts_with(r#"<span class="highlight comment"#),
+                "{class}: {line}"
+            );
+        }
     }
 
-    fn context(&mut self, data: &str, dst: &mut String) {
-        self.write(dst, "context", data);
+    #[tokio::test]
+    async fn histogram_keeps_moved_functions_together() {
+        const COPY: &str = "void chunk_copy(Chunk *src, Chunk *dst, size_t n)\n{\n    \
+                            if (!chunk_bounds_check(src, n)) return;\n    \
+                            if (!chunk_bounds_check(dst, n)) return;\n\n    \
+                            memcpy(dst->data, src->data, n);\n}\n";
+        const CHECK: &str = "int chunk_bounds_check(Chunk *chunk, size_t n)\n{\n    \
+                             if (chunk == NULL) return 0;\n\n    \
+                             return n <= chunk->length;\n}\n";
+
+        let mut fixture = Fixture::new();
+        fixture.write("chunk.c", format!("{COPY}\n{CHECK}"));
+        fixture.commit("chunks");
+        fixture.write("chunk.c", format!("{CHECK}\n{COPY}"));
+        let commit = fixture.commit("reorder");
+
+        let repo = fixture.open(&test_util::git()).await;
+
+        // the number of separate runs of changed lines in the diff
+        let runs = |algorithm| {
+            let repo = repo.clone();
+            let commit = commit.clone();
+            async move {
+                let commit = repo
+                    .commit(
+                        &commit,
+                        false,
+                        DiffOptions {
+                            context_lines: 3,
+                            algorithm: Some(algorithm),
+                        },
+                    )
+                    .await
+                    .unwrap();
+
+                let changed: Vec<_> = commit
+                    .diff
+                    .lines()
+                    .skip_while(|line| !line.starts_with("@@"))
+                    .map(|line| line.starts_with(['+', '-']))
+                    .collect();
+                let starts = changed.windows(2).filter(|w| !w[0] && w[1]).count();
+                (starts, commit.diff.clone())
+            }
+        };
+
+        // myers matches up the braces and blank lines of the two functions, interleaving them
+        let (myers, diff) = runs(DiffAlgorithm::Myers).await;
+        assert!(myers > 2, "{diff}");
+
+        // whereas histogram moves one function past the other untouched
+        let (histogram, diff) = runs(DiffAlgorithm::Histogram).await;
+        assert_eq!(histogram, 2, "{diff}");
     }
 }
@@ -0,0 +1,111 @@
+//! Reads the handful of attributes rgit acts on from the `.gitattributes` at the root of a
+//! tree, such as `export-ignore` for archives.
+//!
+//! Only the root `.gitattributes` is read, attributes set in subdirectories or by macros aren't
+//! taken into account.
+
+use anyhow::Context;
+use gix::{
+    attrs::StateRef,
+    bstr::{BStr, ByteSlice},
+    glob::{pattern::Case, wildmatch::Mode, Pattern},
+};
+use tracing::warn;
+
+#[derive(Default)]
+pub struct GitAttributes {
+    rules: Vec<(Pattern, Vec<(&'static str, Option<bool>)>)>,
+}
+
+impl GitAttributes {
+    /// Reads the rules for the given attributes from the `.gitattributes` at the root of the
+    /// tree, if there is one.
+    pub fn from_tree(tree: &gix::Tree<'_>, attributes: &[&'static str]) -> anyhow::Result<Self> {
+        let Some(entry) = tree.lookup_entry_by_path(".gitattributes")? else {
+            return Ok(Self::default());
+        };
+
+        if !entry.mode().is_blob() {
+            return Ok(Self::default());
+        }
+
+        let blob = entry.object().context("Failed to read .gitattributes")?;
+        Ok(Self::parse(&blob.data, attributes))
+    }
+
+    /// Parses the rules for the given attributes out of a `.gitattributes` file, ignoring
+    /// everything else.
+    pub fn parse(data: &[u8], attributes: &[&'static str]) -> Self {
+        let mut rules = Vec::new();
+
+        for line in gix::attrs::parse(data) {
+            let (kind, assignments, _) = match line {
+                Ok(v) => v,
+                Err(error) => {
+                    warn!(%error, "Failed to parse .gitattributes");
+                    continue;
+                }
+            };
+
+            let gix::attrs::parse::Kind::Pattern(pattern) = kind else {
+                continue;
+            };
+
+            let assignments = assignments
+                .filter_map(Result::ok)
+                .filter_map(|assignment| {
+                    let name = attributes
+                        .iter()
+                        .find(|v| **v == assignment.name.as_str())?;
+
+                    let state = match assignment.state {
+                        StateRef::Set => Some(true),
+                        StateRef::Unset => Some(false),
+                        StateRef::Value(v) if v.as_bstr() == "true" => Some(true),
+                        StateRef::Value(v) if v.as_bstr() == "false" => Some(false),
+                        StateRef::Value(_) | StateRef::Unspecified => None,
+                    };
+
+                    Some((*name, state))
+                })
+                .collect::<Vec<_>>();
+
+            if !assignments.is_empty() {
+                rules.push((pattern, assignments));
+            }
+        }
+
+        Self { rules }
+    }
+
+    /// Whether `attribute` ends up set for the repository-relative path.
+    pub fn is_set(&self, path: &BStr, is_dir: bool, attribute: &str) -> bool {
+        self.state(path, is_dir, attribute) == Some(true)
+    }
+
+    /// Whether `attribute` ends up set (`attr`) or unset (`-attr`) for the repository-relative
+    /// path, or `None` if it's unspecified. Later rules take precedence over earlier ones like
+    /// they do in git.
+    pub fn state(&self, path: &BStr, is_dir: bool, attribute: &str) -> Option<bool> {
+        let basename_start = path.rfind_byte(b'/').map(|v| v + 1);
+        let mut state = None;
+
+        for (pattern, assignments) in &self.rules {
+            let Some((_, value)) = assignments.iter().rev().find(|(v, _)| *v == attribute) else {
+                continue;
+            };
+
+            if pattern.matches_repo_relative_path(
+                path,
+                basename_start,
+                Some(is_dir),
+                Case::Sensitive,
+                Mode::NO_MATCH_SLASH_LITERAL,
+            ) {
+                state = *value;
+            }
+        }
+
+        state
+    }
+}
@@ -0,0 +1,190 @@
+//! Picks the locale relative times and dates are rendered in from the request's
+//! `Accept-Language` header.
+//!
+//! Templates are rendered through askama filters which can't be handed any request state, so the
+//! locale is held in a task-local for the duration of the request instead, falling back to
+//! English outside of one.
+
+use std::{
+    future::Future,
+    task::{Context, Poll},
+};
+
+use axum::http::{header, HeaderValue, Request, Response};
+use futures_util::future::{FutureExt, Map};
+use time::{format_description::BorrowedFormatItem, macros::format_description};
+use tokio::task::futures::TaskLocalFuture;
+use tower_service::Service;
+
+tokio::task_local! {
+    static LOCALE: Locale;
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Locale {
+    #[default]
+    English,
+    German,
+    French,
+    Spanish,
+}
+
+impl Locale {
+    /// The locale of the request currently being handled.
+    pub fn current() -> Self {
+        LOCALE.try_with(|v| *v).unwrap_or_default()
+    }
+
+    /// Picks the supported locale the client prefers most, going by the primary subtag of each
+    /// language in the header, ie. `de-AT` is served German.
+    pub fn from_accept_language(header: &str) -> Self {
+        let mut preferred = None;
+
+        for language in header.split(',') {
+            let mut parts = language.split(';');
+            let tag = parts.next().unwrap_or_default().trim();
+            let quality = parts
+                .find_map(|v| v.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |v| v.trim().parse::<f32>().ok());
+
+            let (Some(locale), Some(quality)) = (Self::from_tag(tag), quality) else {
+                continue;
+            };
+
+            if quality > 0.0 && preferred.is_none_or(|(_, best)| quality > best) {
+                preferred = Some((locale, quality));
+            }
+        }
+
+        preferred.map(|(locale, _)| locale).unwrap_or_default()
+    }
+
+    fn from_tag(tag: &str) -> Option<Self> {
+        let primary = tag.split(['-', '_']).next()?;
+
+        match primary.to_ascii_lowercase().as_str() {
+            "en" => Some(Self::English),
+            "de" => Some(Self::German),
+            "fr" => Some(Self::French),
+            "es" => Some(Self::Spanish),
+            _ => None,
+        }
+    }
+
+    pub fn timeago(self) -> timeago::Formatter<timeago::BoxedLanguage> {
+        use timeago::languages::{boxup, french, german, spanish};
+
+        timeago::Formatter::with_language(match self {
+            Self::English => boxup(timeago::English),
+            Self::German => boxup(german::German),
+            Self::French => boxup(french::French),
+            Self::Spanish => boxup(spanish::Spanish),
+        })
+    }
+
+    /// How absolute dates are written for display, machine-readable timestamps are always
+    /// RFC 3339.
+    pub fn date_format(self) -> &'static [BorrowedFormatItem<'static>] {
+        match self {
+            Self::English => format_description!(
+                "[year]-[month]-[day] [hour]:[minute]:[second] [offset_hour sign:mandatory]:[offset_minute]"
+            ),
+            Self::German => format_description!(
+                "[day].[month].[year] [hour]:[minute]:[second] [offset_hour sign:mandatory]:[offset_minute]"
+            ),
+            Self::French | Self::Spanish => format_description!(
+                "[day]/[month]/[year] [hour]:[minute]:[second] [offset_hour sign:mandatory]:[offset_minute]"
+            ),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct LocaleMiddleware<S>(pub S);
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for LocaleMiddleware<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Map<
+        TaskLocalFuture<Locale, S::Future>,
+        fn(<S::Future as Future>::Output) -> <S::Future as Future>::Output,
+    >;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let locale = req
+            .headers()
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|v| v.to_str().ok())
+            .map(Locale::from_accept_language)
+            .unwrap_or_default();
+
+        LOCALE.scope(locale, self.0.call(req)).map(|response| {
+            // pages differ by language, so shared caches have to key on it. nothing else is
+            // localised, so raw files and the like stay cacheable across languages
+            response.map(|mut response| {
+                let html = response
+                    .headers()
+                    .get(header::CONTENT_TYPE)
+                    .and_then(|v| v.to_str().ok())
+                    .is_some_and(|v| v.starts_with("text/html"));
+
+                if html {
+                    response
+                        .headers_mut()
+                        .append(header::VARY, HeaderValue::from_static("accept-language"));
+                }
+
+                response
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header, Request},
+        response::Html,
+        routing::get,
+        Router,
+    };
+    use tower::ServiceExt;
+    use tower_layer::layer_fn;
+
+    use super::LocaleMiddleware;
+
+    #[tokio::test]
+    async fn only_pages_vary_by_language() {
+        let app = Router::new()
+            .route("/page", get(|| async { Html("<p>page</p>") }))
+            .route("/raw", get(|| async { "raw" }))
+            .layer(layer_fn(LocaleMiddleware));
+
+        for (path, varies) in [("/page", true), ("/raw", false)] {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::get(path)
+                        .header(header::ACCEPT_LANGUAGE, "de")
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(
+                response.headers().contains_key(header::VARY),
+                varies,
+                "{path}"
+            );
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use std::convert::Infallible;
 
+pub mod locale;
 pub mod logger;
 
 pub trait UnwrapInfallible<T> {
@@ -2,12 +2,16 @@
 
 use std::{
     borrow::Cow,
+    ffi::OsStr,
     fmt::{Display, Formatter},
     future::IntoFuture,
     net::SocketAddr,
-    path::PathBuf,
+    path::{Path, PathBuf},
     str::FromStr,
-    sync::{Arc, OnceLock},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, OnceLock,
+    },
     time::Duration,
 };
 
@@ -15,44 +19,64 @@ use anyhow::Context;
 use askama::Template;
 use axum::{
     body::Body,
+    extract::DefaultBodyLimit,
     http,
     http::{HeaderValue, StatusCode},
     response::{IntoResponse, Response},
-    routing::get,
+    routing::{get, post},
     Extension, Router,
 };
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use const_format::formatcp;
 use database::schema::SCHEMA_VERSION;
-use rocksdb::{Options, SliceTransform};
+use path_clean::PathClean;
+use rocksdb::{BlockBasedOptions, Cache, DBCompressionType, ErrorKind, Options, SliceTransform};
 use tokio::{
     net::TcpListener,
     signal::unix::{signal, SignalKind},
-    sync::mpsc,
+    sync::{mpsc, mpsc::error::TrySendError},
+};
+use tower_http::{
+    compression::{predicate::DefaultPredicate, CompressionLayer, Predicate},
+    cors::CorsLayer,
+    timeout::TimeoutLayer,
 };
-use tower_http::{cors::CorsLayer, timeout::TimeoutLayer};
 use tower_layer::layer_fn;
-use tracing::{error, info, instrument, warn};
+use tracing::{debug, error, info, instrument, warn};
 use tracing_subscriber::{
     fmt::format::FmtSpan, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter,
 };
 use xxhash_rust::const_xxh3;
 
 use crate::{
-    database::schema::prefixes::{
-        COMMIT_COUNT_FAMILY, COMMIT_FAMILY, REFERENCE_FAMILY, REPOSITORY_FAMILY, TAG_FAMILY,
+    database::{
+        indexer::{build_exclusions, IndexStatus, IndexerConfig, ReindexQueue},
+        schema::prefixes::{
+            COMMIT_COUNT_FAMILY, COMMIT_FAMILY, COMMIT_OBJECT_FAMILY, COMMIT_STATS_FAMILY,
+            COMMIT_TIME_FAMILY, REFERENCE_FAMILY, REPOSITORY_FAMILY, TAG_FAMILY,
+        },
     },
-    git::Git,
-    layers::logger::LoggingMiddleware,
-    syntax_highlight::prime_highlighters,
+    git::{DiffAlgorithm, FileLimits, Git, GitConfig, SignatureVerification},
+    layers::{locale::LocaleMiddleware, logger::LoggingMiddleware},
+    methods::admin::AdminToken,
+    methods::filters::AvatarHash,
+    methods::repo::{cgit::CgitUrls, dumb_http::DumbHttp, reindex::ReindexToken, RequestTimeouts},
+    precompressed::PrecompressedAsset,
+    syntax_highlight::{prime_highlighters, MAX_HIGHLIGHT_INJECTIONS},
     theme::Theme,
 };
 
+mod backup;
 mod database;
 mod git;
+mod gitattributes;
 mod layers;
 mod methods;
+mod notify;
+mod precompressed;
 mod syntax_highlight;
+#[cfg(test)]
+mod test_util;
 mod theme;
 mod unified_diff_builder;
 
@@ -63,27 +87,293 @@ const GLOBAL_CSS_HASH: &str = const_hex::Buffer::<16, false>::new()
     .const_format(&const_xxh3::xxh3_128(GLOBAL_CSS).to_be_bytes())
     .as_str();
 
+/// Served at `/robots.txt` unless overridden by `--robots`, pages generated per-commit are both
+/// expensive to render and near-infinite in number on repositories with large histories.
+///
+/// Rules match by prefix, so each is anchored to stop them also matching pages such as
+/// `/*/commits` or repositories whose names start with the same word.
+const DEFAULT_ROBOTS_TXT: &str = "User-agent: *
+Disallow: /*/commit$
+Disallow: /*/commit?
+Disallow: /*/commit/
+Disallow: /*/diff$
+Disallow: /*/diff?
+Disallow: /*/diff/
+Disallow: /*/patch$
+Disallow: /*/patch?
+Disallow: /*/patch/
+Disallow: /*/snapshot$
+Disallow: /*/snapshot?
+Disallow: /*/snapshot/
+";
+
 static HIGHLIGHT_CSS_HASH: OnceLock<Box<str>> = OnceLock::new();
 static DARK_HIGHLIGHT_CSS_HASH: OnceLock<Box<str>> = OnceLock::new();
+static SITE: OnceLock<SiteConfig> = OnceLock::new();
+/// Whether the full error chain should be shown to users on error pages, set by `--debug-errors`
+static DEBUG_ERRORS: AtomicBool = AtomicBool::new(false);
 
 #[derive(Parser, Debug)]
-#[clap(author, version, about)]
+#[clap(
+    author,
+    version,
+    about,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 pub struct Args {
     /// Path to a directory in which the `RocksDB` database should be stored, will be created if it doesn't already exist
     ///
     /// The `RocksDB` database is very quick to generate, so this can be pointed to temporary storage
-    #[clap(short, long, value_parser)]
-    db_store: PathBuf,
+    #[clap(short, long, value_parser, required = true)]
+    db_store: Option<PathBuf>,
     /// The socket address to bind to (eg. 0.0.0.0:3333)
-    bind_address: SocketAddr,
+    #[clap(required = true)]
+    bind_address: Option<SocketAddr>,
     /// The path in which your bare Git repositories reside (will be scanned recursively)
-    scan_path: PathBuf,
+    #[clap(required = true)]
+    scan_path: Option<PathBuf>,
     /// Configures the metadata refresh interval (eg. "never" or "60s")
     #[clap(long, default_value_t = RefreshInterval::Duration(Duration::from_secs(300)))]
     refresh_interval: RefreshInterval,
     /// Configures the request timeout.
     #[clap(long, default_value_t = Duration::from_secs(10).into())]
     request_timeout: humantime::Duration,
+    /// Configures the timeout for clones & snapshot downloads, which aren't subject to
+    /// `--request-timeout` as they can legitimately take much longer. Unlimited if unset.
+    #[clap(long)]
+    streaming_request_timeout: Option<humantime::Duration>,
+    /// Token required to trigger a single repository reindex via `POST /<repo>/reindex`
+    ///
+    /// The token should be passed as an `Authorization: Bearer <token>` header, reindexing via
+    /// HTTP is disabled if this isn't set.
+    #[clap(long)]
+    reindex_token: Option<String>,
+    /// Token required to access the operator endpoints under `/admin`, such as
+    /// `/admin/index-status`
+    ///
+    /// The token should be passed as an `Authorization: Bearer <token>` header, the endpoints
+    /// aren't served at all if this isn't set.
+    #[clap(long)]
+    admin_token: Option<String>,
+    /// Fail to start if the `RocksDB` database can't be opened, rather than attempting to repair
+    /// it or recreating it from scratch
+    #[clap(long)]
+    no_db_autorepair: bool,
+    /// Size of the block cache shared between the read-heavy commit and tag column families, in
+    /// bytes
+    #[clap(long, default_value_t = 64 * 1024 * 1024)]
+    db_block_cache_size: usize,
+    /// Disables zstd compression of the read-heavy commit and tag column families, trading disk
+    /// space for slightly cheaper reads
+    #[clap(long)]
+    no_db_compression: bool,
+    /// Derive the IDs of newly indexed repositories from their path rather than generating them
+    /// randomly, so they stay the same when the database is rebuilt
+    ///
+    /// Repositories that are already indexed keep their existing IDs until the database is
+    /// rebuilt.
+    #[clap(long)]
+    deterministic_repository_ids: bool,
+    /// Number of commits to write to the database at once whilst indexing, larger batches index
+    /// faster at the cost of holding more commits in memory
+    #[clap(long, default_value_t = 250, value_parser = clap::value_parser!(u32).range(1..))]
+    index_batch_size: u32,
+    /// Write indexed commits through `RocksDB`'s write-ahead log
+    ///
+    /// By default the log is skipped to speed up indexing, so commits indexed shortly before a
+    /// crash may be lost and have to be indexed again. This is safe, but may mean a slow restart
+    /// for large instances.
+    #[clap(long)]
+    index_wal: bool,
+    /// Maximum number of repositories indexed from the scan path, any found past this are
+    /// skipped with a warning. Protects against the scan path accidentally containing a runaway
+    /// number of repositories, such as checkouts with nested submodules
+    #[clap(long)]
+    max_repos: Option<usize>,
+    /// Maximum number of directories deep repositories are looked for under the scan path, where
+    /// a repository directly within the scan path is at a depth of 1
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..))]
+    max_scan_depth: Option<u32>,
+    /// Glob pattern matching paths, relative to the scan path, of repositories to skip when
+    /// indexing and to refuse to serve (eg. "*/archived/*" or "*.backup.git"). Can be given
+    /// multiple times
+    #[clap(long)]
+    exclude: Vec<String>,
+    /// Show the full internal error on error pages, this may leak details about the host so
+    /// should only be used whilst debugging
+    #[clap(long)]
+    debug_errors: bool,
+    /// Path to a favicon to serve instead of the default one
+    #[clap(long)]
+    favicon: Option<PathBuf>,
+    /// Path to a robots.txt to serve instead of the default, which asks crawlers to stay away from
+    /// expensive pages (commits, diffs, patches & snapshots)
+    #[clap(long)]
+    robots: Option<PathBuf>,
+    /// How long browsers may cache the favicon for, which is served at a fixed URL so can't be
+    /// cached indefinitely like the other static assets
+    #[clap(long, default_value_t = Duration::from_secs(24 * 60 * 60).into())]
+    favicon_max_age: humantime::Duration,
+    /// Path to an image to show in the page header as the instance's logo
+    #[clap(long)]
+    logo: Option<PathBuf>,
+    /// Name of the instance, shown in the page header and title
+    #[clap(long)]
+    site_name: Option<String>,
+    /// Base URL shown in clone instructions (eg. "https://git.example.com"), defaults to https://
+    /// followed by the host the page was requested from
+    #[clap(long)]
+    clone_base: Option<String>,
+    /// URL template for an "open in editor" link on file pages, eg.
+    /// "vscode://file/home/me/src/{repo}/{path}:{line}". `{repo}` is replaced with the
+    /// repository's path relative to the scan path without its `.git` suffix, `{path}` with the
+    /// file's path within the repository, both percent-encoded, and `{line}` with the line to
+    /// open the file at, which is currently always 1
+    #[clap(long)]
+    editor_link: Option<String>,
+    /// Public base URL of this instance (eg. "https://git.example.com"), used wherever an
+    /// absolute link is required such as in the sitemap. Defaults to https:// followed by the host
+    /// the page was requested from
+    #[clap(long)]
+    site_url: Option<String>,
+    /// Path rgit is served under when it's behind a reverse proxy (eg. "/git"), all routes are
+    /// mounted under it and every generated link is prefixed with it. `--site-url` and
+    /// `--clone-base` should include it if they're given
+    #[clap(long)]
+    base_path: Option<String>,
+    /// Base URL avatars are fetched from, the hash of the committer's email is appended to it
+    #[clap(long, default_value = "https://www.gravatar.com/avatar")]
+    avatar_base: String,
+    /// Hashing scheme used to build avatar URLs
+    #[clap(long, value_enum, default_value_t)]
+    avatar_hash: AvatarHash,
+    /// Render generated identicons instead of fetching avatars from an external provider, so
+    /// browsers never make requests to third parties
+    #[clap(long)]
+    no_avatars: bool,
+    /// Comma-separated list of branches to fall back to, in order, when a repository doesn't
+    /// have a default branch configured
+    #[clap(long, value_delimiter = ',', default_value = "master,main")]
+    default_branches: Vec<String>,
+    /// Number of commits shown on a repository's summary page
+    #[clap(long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    summary_commits: u64,
+    /// Number of commits shown on each page of a repository's log
+    #[clap(long, default_value_t = 100, value_parser = clap::value_parser!(u64).range(1..))]
+    log_page_size: u64,
+    /// Verify signatures on commits and tags, showing whether they're verified on their pages.
+    /// This shells out to git for each signed object, so is fairly expensive
+    #[clap(long)]
+    verify_signatures: bool,
+    /// `allowed_signers` file to verify SSH signatures against, requires `--verify-signatures`
+    #[clap(long, requires = "verify_signatures")]
+    allowed_signers_file: Option<PathBuf>,
+    /// GnuPG home directory whose keyring GPG signatures are verified against, defaults to
+    /// `$GNUPGHOME` or `~/.gnupg`, requires `--verify-signatures`
+    #[clap(long, requires = "verify_signatures")]
+    gnupg_home: Option<PathBuf>,
+    /// Show which branches and tags contain a commit on its page. This walks the history of
+    /// every reference, so is bounded by `--contained-refs-max-commits`
+    #[clap(long)]
+    show_contained_refs: bool,
+    /// Maximum number of commits walked per request when looking for the references containing
+    /// a commit, references that can't be checked within this budget are left out
+    #[clap(long, default_value_t = 10_000, requires = "show_contained_refs")]
+    contained_refs_max_commits: usize,
+    /// Show the notes attached to commits in `refs/notes/commits` on their pages, which costs an
+    /// extra lookup per commit
+    #[clap(long)]
+    show_notes: bool,
+    /// Serve repositories to clients using git's dumb HTTP protocol, which requires
+    /// `git update-server-info` to be run on each push
+    #[clap(long)]
+    enable_dumb_http: bool,
+    /// Redirect cgit-style URLs (eg. `/<repo>/plain/<path>` or
+    /// `/<repo>/snapshot/<repo>-<ref>.tar.gz`) to their rgit equivalents, so links from before a
+    /// migration from cgit keep working
+    #[clap(long)]
+    cgit_urls: bool,
+    /// How to treat a trailing slash on repository URLs. By default browsers are redirected to
+    /// the URL without it so each page has one canonical URL
+    #[clap(long, value_enum, default_value_t)]
+    trailing_slash: TrailingSlash,
+    /// Files larger than this many bytes are shown without syntax highlighting
+    #[clap(long, default_value_t = 1024 * 1024)]
+    max_highlight_bytes: usize,
+    /// Files larger than this many bytes aren't shown inline, and are instead offered as a
+    /// download
+    #[clap(long, default_value_t = 10 * 1024 * 1024)]
+    max_render_bytes: usize,
+    /// The size in bytes a commit's rendered diff can grow to, including syntax highlighting.
+    /// The diff of any file that would take it past this is left out in favour of the raw patch
+    #[clap(long, default_value_t = 2 * 1024 * 1024)]
+    max_diff_bytes: usize,
+    /// The algorithm to diff files with, which can be overridden per request with `?algo=`. By
+    /// default each repository's `diff.algorithm` is used
+    #[clap(long, value_enum)]
+    diff_algorithm: Option<DiffAlgorithm>,
+    /// Bytes of decompressed git objects to cache per repository, both whilst indexing and for
+    /// the duration of each request. Larger caches help with repositories that have big trees,
+    /// 0 disables the cache
+    #[clap(long, default_value_t = 10 * 1024 * 1024)]
+    object_cache_size: usize,
+    /// Paths of repositories, relative to the scan path, to keep open once they've been opened
+    /// rather than closing them after two minutes without requests, so the busiest repositories
+    /// never pay the cost of reopening
+    #[clap(long, value_delimiter = ',')]
+    pin_repos: Vec<PathBuf>,
+    /// Path to a Git LFS object store (laid out like `.git/lfs/objects`) to serve the content of
+    /// LFS-tracked files from when they're downloaded
+    #[clap(long)]
+    lfs_store: Option<PathBuf>,
+    /// Build the highlighter for every language on startup rather than when it's first needed,
+    /// trading memory usage for the latency of the first file viewed in each language
+    #[clap(long)]
+    prime_highlighters: bool,
+    /// Maximum number of embedded languages (eg. code blocks within markdown, or scripts within
+    /// HTML) highlighted within a single file, any past this are shown without highlighting
+    #[clap(long, default_value_t = 256)]
+    max_highlight_injections: usize,
+    /// Enables `POST /-/highlight`, which takes a JSON body of `{"language": ..., "code": ...}` and
+    /// returns the snippet as highlighted HTML, rejecting request bodies larger than this many
+    /// bytes
+    #[clap(long)]
+    highlight_api_max_bytes: Option<usize>,
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+impl Args {
+    // the following are only optional when a subcommand is given, which clap enforces for us
+    // via `subcommand_negates_reqs`
+
+    fn db_store(&self) -> &Path {
+        self.db_store.as_deref().expect("db_store required")
+    }
+
+    fn bind_address(&self) -> SocketAddr {
+        self.bind_address.expect("bind_address required")
+    }
+
+    fn scan_path(&self) -> &Path {
+        self.scan_path.as_deref().expect("scan_path required")
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Notifies a running rgit instance that a repository has been pushed to, intended to be
+    /// called from a repository's `post-receive` hook
+    NotifyReindex(notify::NotifyReindexArgs),
+    /// Compiles the highlighting queries for every bundled grammar and reports any that fail,
+    /// which would otherwise only be noticed when the server starts
+    CheckHighlighters,
+    /// Snapshots the index into a tarball that can be restored with `import`, so it can be moved
+    /// to another host without a full reindex. rgit must be stopped whilst exporting
+    Export(backup::ExportArgs),
+    /// Restores an index snapshot written by `export`. rgit must be stopped whilst importing
+    Import(backup::ImportArgs),
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -120,6 +410,15 @@ impl FromStr for RefreshInterval {
 async fn main() -> Result<(), anyhow::Error> {
     let args: Args = Args::parse();
 
+    if let Some(command) = args.command {
+        return match command {
+            Command::NotifyReindex(args) => notify::run(args).await,
+            Command::CheckHighlighters => check_highlighters(),
+            Command::Export(args) => backup::export(&args),
+            Command::Import(args) => backup::import(&args),
+        };
+    }
+
     if std::env::var_os("RUST_LOG").is_none() {
         std::env::set_var("RUST_LOG", "info");
     }
@@ -132,15 +431,41 @@ async fn main() -> Result<(), anyhow::Error> {
         .with(logger_layer)
         .init();
 
+    DEBUG_ERRORS.store(args.debug_errors, Ordering::Relaxed);
+    MAX_HIGHLIGHT_INJECTIONS.store(args.max_highlight_injections, Ordering::Relaxed);
+
+    let indexer_config = Arc::new(IndexerConfig {
+        deterministic_repository_ids: args.deterministic_repository_ids,
+        commit_batch_size: args.index_batch_size as usize,
+        wal: args.index_wal,
+        max_repositories: args.max_repos.unwrap_or(usize::MAX),
+        max_scan_depth: args.max_scan_depth.map_or(usize::MAX, |v| v as usize),
+        excluded: build_exclusions(&args.exclude)?,
+        object_cache_size: args.object_cache_size,
+    });
+
     let db = open_db(&args)?;
 
-    let indexer_wakeup_task =
-        run_indexer(db.clone(), args.scan_path.clone(), args.refresh_interval);
+    let indexer_ready = Arc::new(AtomicBool::new(false));
+    let index_status = Arc::new(Mutex::new(IndexStatus::default()));
+    let indexer_wakeup_task = run_indexer(
+        db.clone(),
+        args.scan_path().to_path_buf(),
+        args.refresh_interval,
+        indexer_ready.clone(),
+        index_status.clone(),
+        indexer_config.clone(),
+    );
+    let reindex_queue = ReindexQueue::spawn(
+        args.scan_path().to_path_buf(),
+        db.clone(),
+        index_status.clone(),
+        indexer_config.clone(),
+    );
 
     let css = {
-        let theme = basic_toml::from_str::<Theme>(include_str!("../themes/github_light.toml"))
-            .unwrap()
-            .build_css();
+        let theme =
+            load_theme("github_light", include_str!("../themes/github_light.toml")).build_css();
         let css = Box::leak(
             format!(r#"@media (prefers-color-scheme: light){{{theme}}}"#)
                 .into_boxed_str()
@@ -151,9 +476,7 @@ async fn main() -> Result<(), anyhow::Error> {
     };
 
     let dark_css = {
-        let theme = basic_toml::from_str::<Theme>(include_str!("../themes/onedark.toml"))
-            .unwrap()
-            .build_css();
+        let theme = load_theme("onedark", include_str!("../themes/onedark.toml")).build_css();
         let css = Box::leak(
             format!(r#"@media (prefers-color-scheme: dark){{{theme}}}"#)
                 .into_boxed_str()
@@ -163,62 +486,262 @@ async fn main() -> Result<(), anyhow::Error> {
         css
     };
 
-    let static_favicon = |content: &'static [u8]| {
-        move || async move {
-            let mut resp = Response::new(Body::from(content));
-            resp.headers_mut().insert(
-                http::header::CONTENT_TYPE,
-                HeaderValue::from_static("image/x-icon"),
-            );
-            resp
-        }
+    let favicon = if let Some(path) = &args.favicon {
+        let content: &'static [u8] = Box::leak(
+            std::fs::read(path)
+                .with_context(|| format!("Failed to read favicon {}", path.display()))?
+                .into_boxed_slice(),
+        );
+        (content, image_content_type(path))
+    } else {
+        (
+            include_bytes!("../statics/favicon.ico").as_slice(),
+            "image/x-icon",
+        )
+    };
+
+    let base_path = args
+        .base_path
+        .as_deref()
+        .map(|v| v.trim_matches('/'))
+        .filter(|v| !v.is_empty())
+        .map(|v| format!("/{v}"))
+        .unwrap_or_default();
+
+    let robots: &'static [u8] = if let Some(path) = &args.robots {
+        Box::leak(
+            std::fs::read(path)
+                .with_context(|| format!("Failed to read robots.txt {}", path.display()))?
+                .into_boxed_slice(),
+        )
+    } else {
+        // crawlers only look for robots.txt at the root, so this is only useful under a base
+        // path if the proxy serves it from there too
+        Box::leak(
+            DEFAULT_ROBOTS_TXT
+                .replace("Disallow: /", &format!("Disallow: {base_path}/"))
+                .into_bytes()
+                .into_boxed_slice(),
+        )
+    };
+
+    let logo = if let Some(path) = &args.logo {
+        let content: &'static [u8] = Box::leak(
+            std::fs::read(path)
+                .with_context(|| format!("Failed to read logo {}", path.display()))?
+                .into_boxed_slice(),
+        );
+        let extension = path.extension().and_then(OsStr::to_str).unwrap_or("img");
+        let served_at = format!("/logo-{}.{extension}", build_asset_hash(content));
+        Some((served_at, content, image_content_type(path)))
+    } else {
+        None
     };
 
-    let static_css = |content: &'static [u8]| {
-        move || async move {
-            let mut resp = Response::new(Body::from(content));
-            resp.headers_mut().insert(
-                http::header::CONTENT_TYPE,
-                HeaderValue::from_static("text/css"),
-            );
+    SITE.set(SiteConfig {
+        name: args.site_name.as_deref().map(Box::from),
+        clone_base: args
+            .clone_base
+            .as_deref()
+            .map(|v| Box::from(v.trim_end_matches('/'))),
+        editor_link: args.editor_link.as_deref().map(Box::from),
+        url: args
+            .site_url
+            .as_deref()
+            .map(|v| Box::from(v.trim_end_matches('/'))),
+        base_path: base_path.clone().into_boxed_str(),
+        logo: logo.as_ref().map(|(path, _, _)| Box::from(path.as_str())),
+        avatar_base: Box::from(args.avatar_base.trim_end_matches('/')),
+        avatar_hash: args.avatar_hash,
+        no_avatars: args.no_avatars,
+        default_branches: args
+            .default_branches
+            .iter()
+            .map(|v| v.as_str().trim())
+            .filter(|v| !v.is_empty())
+            .map(|v| {
+                if v.starts_with("refs/") {
+                    Box::from(v)
+                } else {
+                    format!("refs/heads/{v}").into_boxed_str()
+                }
+            })
+            .collect(),
+        summary_commits: args.summary_commits,
+        log_page_size: args.log_page_size,
+    })
+    .unwrap();
+
+    // assets served at a URL containing a hash of their content can be cached forever, since a
+    // change in content results in a new URL
+    let immutable = HeaderValue::from_static("public, max-age=31536000, immutable");
+
+    let favicon_cache_control = HeaderValue::try_from(format!(
+        "public, max-age={}",
+        args.favicon_max_age.as_secs()
+    ))?;
+
+    let static_file =
+        |content: &'static [u8], content_type: &'static str, cache_control: HeaderValue| {
+            move || async move {
+                let mut resp = Response::new(Body::from(content));
+                resp.headers_mut().insert(
+                    http::header::CONTENT_TYPE,
+                    HeaderValue::from_static(content_type),
+                );
+                resp.headers_mut()
+                    .insert(http::header::CACHE_CONTROL, cache_control);
+                resp
+            }
+        };
+
+    let static_css = |asset: PrecompressedAsset| {
+        let cache_control = immutable.clone();
+
+        move |headers: http::HeaderMap| async move {
+            let mut resp = asset.respond(&headers, "text/css");
+            resp.headers_mut()
+                .insert(http::header::CACHE_CONTROL, cache_control);
             resp
         }
     };
 
-    info!("Priming highlighters...");
-    prime_highlighters();
+    if args.prime_highlighters {
+        info!("Priming highlighters...");
+        prime_highlighters();
+    }
+
     info!("Server starting up...");
 
-    let app = Router::new()
+    let mut app = Router::new()
         .route("/", get(methods::index::handle))
         .route(
             formatcp!("/style-{}.css", GLOBAL_CSS_HASH),
-            get(static_css(GLOBAL_CSS)),
+            get(static_css(PrecompressedAsset {
+                raw: GLOBAL_CSS,
+                gzip: include_bytes!(concat!(env!("OUT_DIR"), "/statics/css/style.css.gz")),
+                brotli: include_bytes!(concat!(env!("OUT_DIR"), "/statics/css/style.css.br")),
+            })),
         )
         .route(
             &format!("/highlight-{}.css", HIGHLIGHT_CSS_HASH.get().unwrap()),
-            get(static_css(css)),
+            get(static_css(PrecompressedAsset::compress(css))),
         )
         .route(
             &format!(
                 "/highlight-dark-{}.css",
                 DARK_HIGHLIGHT_CSS_HASH.get().unwrap()
             ),
-            get(static_css(dark_css)),
+            get(static_css(PrecompressedAsset::compress(dark_css))),
         )
         .route(
             "/favicon.ico",
-            get(static_favicon(include_bytes!("../statics/favicon.ico"))),
+            get(static_file(favicon.0, favicon.1, favicon_cache_control)),
         )
+        .route("/sitemap.xml", get(methods::sitemap::handle))
+        .route(
+            "/robots.txt",
+            get(static_file(
+                robots,
+                "text/plain; charset=utf-8",
+                HeaderValue::from_static("no-cache"),
+            )),
+        );
+
+    if let Some((path, content, content_type)) = logo {
+        app = app.route(
+            &path,
+            get(static_file(content, content_type, immutable.clone())),
+        );
+    }
+
+    if let Some(token) = args.admin_token.as_deref() {
+        app = app.route(
+            "/admin/index-status",
+            get(methods::admin::index_status).layer(Extension(AdminToken(Arc::from(token)))),
+        );
+    }
+
+    if let Some(max_bytes) = args.highlight_api_max_bytes {
+        // kept under `/-/` rather than the top level so it doesn't shadow a repository named
+        // `highlight`
+        app = app.route(
+            "/-/highlight",
+            post(methods::highlight::handle).layer(DefaultBodyLimit::max(max_bytes)),
+        );
+    }
+
+    // repository requests apply their own timeouts, since clones & archives legitimately take
+    // much longer than browsing
+    let app = app
+        .route_layer(TimeoutLayer::new(args.request_timeout.into()))
         .fallback(methods::repo::service)
-        .layer(TimeoutLayer::new(args.request_timeout.into()))
+        .layer(
+            CompressionLayer::new()
+                .compress_when(DefaultPredicate::new().and(is_compressible_content_type)),
+        )
+        .layer(layer_fn(LocaleMiddleware))
         .layer(layer_fn(LoggingMiddleware))
-        .layer(Extension(Arc::new(Git::new())))
+        // health checks are registered after the logging middleware so load balancers polling
+        // them don't flood the access log
+        .route("/healthz", get(|| async { "ok" }))
+        .route(
+            "/readyz",
+            get(move || async move {
+                if indexer_ready.load(Ordering::Acquire) {
+                    (StatusCode::OK, "ready")
+                } else {
+                    (StatusCode::SERVICE_UNAVAILABLE, "waiting for initial index")
+                }
+            }),
+        )
+        .layer(Extension(Arc::new(Git::new(GitConfig {
+            signature_verification: args.verify_signatures.then(|| SignatureVerification {
+                allowed_signers: args.allowed_signers_file.clone(),
+                gnupg_home: args.gnupg_home.clone(),
+            }),
+            file_limits: FileLimits {
+                max_highlight_bytes: args.max_highlight_bytes,
+                max_render_bytes: args.max_render_bytes,
+                max_diff_bytes: args.max_diff_bytes,
+            },
+            lfs_store: args.lfs_store.clone(),
+            contained_refs_max_commits: args
+                .show_contained_refs
+                .then_some(args.contained_refs_max_commits),
+            show_notes: args.show_notes,
+            diff_algorithm: args.diff_algorithm,
+            object_cache_size: (args.object_cache_size > 0).then_some(args.object_cache_size),
+            pinned_paths: args
+                .pin_repos
+                .iter()
+                .map(|v| args.scan_path().join(v.clean()))
+                .collect(),
+        }))))
         .layer(Extension(db))
-        .layer(Extension(Arc::new(args.scan_path)))
+        .layer(Extension(Arc::new(args.scan_path().to_path_buf())))
+        .layer(Extension(DumbHttp(args.enable_dumb_http)))
+        .layer(Extension(CgitUrls(args.cgit_urls)))
+        .layer(Extension(args.trailing_slash))
+        .layer(Extension(RequestTimeouts {
+            browse: args.request_timeout.into(),
+            streaming: args.streaming_request_timeout.map(Into::into),
+        }))
+        .layer(Extension(ReindexToken(
+            args.reindex_token.as_deref().map(Arc::from),
+        )))
+        .layer(Extension(reindex_queue))
+        .layer(Extension(indexer_config))
+        .layer(Extension(index_status))
         .layer(CorsLayer::new());
 
-    let listener = TcpListener::bind(&args.bind_address).await?;
+    let app = if base_path.is_empty() {
+        app
+    } else {
+        Router::new().nest(&base_path, app)
+    };
+
+    let listener = TcpListener::bind(args.bind_address()).await?;
     let app = app.into_make_service_with_connect_info::<SocketAddr>();
     let server = axum::serve(listener, app).into_future();
 
@@ -233,34 +756,54 @@ async fn main() -> Result<(), anyhow::Error> {
 }
 
 fn open_db(args: &Args) -> Result<Arc<rocksdb::DB>, anyhow::Error> {
+    let mut recovery = DbRecovery::None;
+    let block_cache = Cache::new_lru_cache(args.db_block_cache_size);
+
     loop {
         let mut db_options = Options::default();
         db_options.create_missing_column_families(true);
         db_options.create_if_missing(true);
 
-        let mut commit_family_options = Options::default();
+        let mut commit_family_options = read_heavy_family_options(args, &block_cache);
         commit_family_options.set_prefix_extractor(SliceTransform::create(
             "commit_prefix",
             |input| memchr::memchr(b'\0', input).map_or(input, |idx| &input[..idx]),
             None,
         ));
 
-        let mut tag_family_options = Options::default();
+        let mut tag_family_options = read_heavy_family_options(args, &block_cache);
         tag_family_options.set_prefix_extractor(SliceTransform::create_fixed_prefix(
             std::mem::size_of::<u64>(),
         )); // repository id prefix
 
-        let db = rocksdb::DB::open_cf_with_opts(
+        let mut commit_object_family_options = read_heavy_family_options(args, &block_cache);
+        commit_object_family_options.set_prefix_extractor(SliceTransform::create_fixed_prefix(
+            std::mem::size_of::<u64>(),
+        )); // repository id prefix
+
+        let db = match rocksdb::DB::open_cf_with_opts(
             &db_options,
-            &args.db_store,
+            args.db_store(),
             vec![
                 (COMMIT_FAMILY, commit_family_options),
                 (REPOSITORY_FAMILY, Options::default()),
                 (TAG_FAMILY, tag_family_options),
                 (REFERENCE_FAMILY, Options::default()),
                 (COMMIT_COUNT_FAMILY, Options::default()),
+                (COMMIT_TIME_FAMILY, Options::default()),
+                (COMMIT_OBJECT_FAMILY, commit_object_family_options),
+                (COMMIT_STATS_FAMILY, Options::default()),
             ],
-        )?;
+        ) {
+            Ok(db) => db,
+            Err(error) if args.no_db_autorepair => {
+                return Err(error).context("Failed to open database, and autorepair is disabled");
+            }
+            Err(error) => {
+                recovery = recover_db(args.db_store(), &db_options, recovery, error)?;
+                continue;
+            }
+        };
 
         let needs_schema_regen = match db.get("schema_version")? {
             Some(v) if v.as_slice() != SCHEMA_VERSION.as_bytes() => Some(Some(v)),
@@ -279,28 +822,92 @@ fn open_db(args: &Args) -> Result<Arc<rocksdb::DB>, anyhow::Error> {
             warn!("Clearing outdated database ({old_version} != {SCHEMA_VERSION})");
 
             drop(db);
-            rocksdb::DB::destroy(&Options::default(), &args.db_store)?;
+            rocksdb::DB::destroy(&Options::default(), args.db_store())?;
         } else {
             break Ok(Arc::new(db));
         }
     }
 }
 
+/// Options for column families that are written once per index and then hammered with prefix
+/// lookups on every page load, so are worth bloom filters and a shared block cache.
+fn read_heavy_family_options(args: &Args, block_cache: &Cache) -> Options {
+    let mut table_options = BlockBasedOptions::default();
+    table_options.set_block_cache(block_cache);
+    table_options.set_bloom_filter(10.0, false);
+    table_options.set_cache_index_and_filter_blocks(true);
+    table_options.set_pin_l0_filter_and_index_blocks_in_cache(true);
+
+    let mut options = Options::default();
+    options.set_block_based_table_factory(&table_options);
+
+    if args.no_db_compression {
+        options.set_compression_type(DBCompressionType::None);
+    } else {
+        options.set_compression_type(DBCompressionType::Zstd);
+        options.set_bottommost_compression_type(DBCompressionType::Zstd);
+    }
+
+    options
+}
+
+/// How far we've gotten through trying to get the database back into a usable state.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum DbRecovery {
+    None,
+    Repaired,
+    Recreated,
+}
+
+/// Attempts to recover the database after it failed to open because it's corrupt, first by
+/// asking `RocksDB` to repair it, and then by destroying it entirely. The database is very quick
+/// to regenerate, so it's better to self-heal than to refuse to start.
+fn recover_db(
+    path: &Path,
+    options: &Options,
+    stage: DbRecovery,
+    error: rocksdb::Error,
+) -> Result<DbRecovery, anyhow::Error> {
+    // anything else, like the lock being held by another instance or a permissions problem,
+    // won't be fixed by a repair and destroying the database would only lose it for nothing
+    if error.kind() != ErrorKind::Corruption {
+        return Err(error).context("Failed to open database");
+    }
+
+    match stage {
+        DbRecovery::None => {
+            error!(%error, "Failed to open database, attempting repair");
+            rocksdb::DB::repair(options, path).context("Failed to repair database")?;
+            Ok(DbRecovery::Repaired)
+        }
+        DbRecovery::Repaired => {
+            error!(%error, "Failed to open database after repair, recreating from scratch");
+            rocksdb::DB::destroy(&Options::default(), path)
+                .context("Failed to destroy database")?;
+            Ok(DbRecovery::Recreated)
+        }
+        DbRecovery::Recreated => {
+            Err(error).context("Failed to open database, even after recreating it")
+        }
+    }
+}
+
 async fn run_indexer(
     db: Arc<rocksdb::DB>,
     scan_path: PathBuf,
     refresh_interval: RefreshInterval,
+    ready: Arc<AtomicBool>,
+    status: Arc<Mutex<IndexStatus>>,
+    config: Arc<IndexerConfig>,
 ) -> Result<(), tokio::task::JoinError> {
-    let (indexer_wakeup_send, mut indexer_wakeup_recv) = mpsc::channel(10);
-
-    std::thread::spawn(move || loop {
+    let wakeup = spawn_indexer(move || {
         info!("Running periodic index");
-        crate::database::indexer::run(&scan_path, &db);
+        crate::database::indexer::run(&scan_path, &db, &status, &config);
         info!("Finished periodic index");
 
-        if indexer_wakeup_recv.blocking_recv().is_none() {
-            break;
-        }
+        // the database is open and has been fully indexed at least once, we're ready to
+        // serve traffic
+        ready.store(true, Ordering::Release);
     });
 
     tokio::spawn({
@@ -319,15 +926,204 @@ async fn run_indexer(
                     () = build_sleeper() => {},
                 }
 
-                if indexer_wakeup_send.send(()).await.is_err() {
-                    error!("Indexing thread has died and is no longer accepting wakeup messages");
-                }
+                wakeup.wake();
             }
         }
     })
     .await
 }
 
+/// Runs `index` on a dedicated thread straight away, then again each time it's woken up through
+/// the returned handle.
+fn spawn_indexer(mut index: impl FnMut() + Send + 'static) -> IndexerWakeup {
+    // a single slot is enough here, since wakeups are dropped whilst one is already pending
+    let (send, mut recv) = mpsc::channel(1);
+    let busy = Arc::new(AtomicBool::new(true));
+
+    std::thread::spawn({
+        let busy = busy.clone();
+
+        move || loop {
+            index();
+            busy.store(false, Ordering::Release);
+
+            if recv.blocking_recv().is_none() {
+                break;
+            }
+        }
+    });
+
+    IndexerWakeup { send, busy }
+}
+
+/// Handle for waking the indexing thread started by [`spawn_indexer`].
+struct IndexerWakeup {
+    send: mpsc::Sender<()>,
+    /// Set from when an index is queued until it finishes running
+    busy: Arc<AtomicBool>,
+}
+
+impl IndexerWakeup {
+    /// Queues another index, unless one is already queued or running since that will pick up
+    /// whatever changes prompted this wakeup anyway. Returns whether an index was queued.
+    fn wake(&self) -> bool {
+        if self.busy.swap(true, Ordering::AcqRel) {
+            warn!("Index is already running or queued, skipping wakeup");
+            return false;
+        }
+
+        match self.send.try_send(()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(())) => {
+                warn!("Index wakeup is already pending, skipping wakeup");
+                false
+            }
+            Err(TrySendError::Closed(())) => {
+                error!("Indexing thread has died and is no longer accepting wakeup messages");
+                false
+            }
+        }
+    }
+}
+
+/// Instance-wide configuration, made available to every template via [`site`] so it doesn't
+/// need threading through each handler.
+#[derive(Debug)]
+pub struct SiteConfig {
+    /// Name of the instance, shown in the page header and title
+    pub name: Option<Box<str>>,
+    /// Base URL to show in clone instructions, without a trailing slash
+    pub clone_base: Option<Box<str>>,
+    /// URL template for opening files in a local editor, with `{repo}`, `{path}` and `{line}`
+    /// placeholders
+    pub editor_link: Option<Box<str>>,
+    /// Public base URL of the instance, without a trailing slash
+    pub url: Option<Box<str>>,
+    /// Path the instance is served under, with a leading slash but no trailing slash, or empty
+    /// if it's served from the root
+    pub base_path: Box<str>,
+    /// Path the custom logo is served from, if one was given
+    pub logo: Option<Box<str>>,
+    /// Base URL avatars are fetched from, without a trailing slash
+    pub avatar_base: Box<str>,
+    /// Hashing scheme used to build avatar URLs
+    pub avatar_hash: AvatarHash,
+    /// Whether to render identicons rather than fetching external avatars
+    pub no_avatars: bool,
+    /// Fully qualified references to try when a repository has no default branch configured
+    pub default_branches: Box<[Box<str>]>,
+    /// Number of commits shown on a repository's summary page
+    pub summary_commits: u64,
+    /// Number of commits shown on each page of a repository's log
+    pub log_page_size: u64,
+}
+
+impl SiteConfig {
+    /// The URL of the repository index, which is the only page served at the base path itself.
+    #[must_use]
+    pub fn index_url(&self) -> &str {
+        if self.base_path.is_empty() {
+            "/"
+        } else {
+            &self.base_path
+        }
+    }
+}
+
+/// Fetches the instance-wide configuration, this is always set before the server starts.
+#[must_use]
+pub fn site() -> &'static SiteConfig {
+    SITE.get().expect("site config not initialised")
+}
+
+fn check_highlighters() -> anyhow::Result<()> {
+    let failures = syntax_highlight::check_highlighters();
+
+    for (grammar, error) in &failures {
+        eprintln!("{grammar:?}: {error}");
+    }
+
+    if !failures.is_empty() {
+        anyhow::bail!("{} grammar(s) have invalid queries", failures.len());
+    }
+
+    println!("All highlighting queries compiled successfully");
+
+    Ok(())
+}
+
+/// Parses one of the bundled themes, rejecting it if it uses colours it doesn't define and
+/// reporting any highlight classes it doesn't style so gaps are noticed when adding new grammars
+/// or themes.
+fn load_theme(name: &str, source: &str) -> Theme {
+    let theme = basic_toml::from_str::<Theme>(source)
+        .unwrap_or_else(|e| panic!("bundled theme {name} is invalid: {e}"));
+
+    let unknown = theme.unknown_colors();
+    assert!(
+        unknown.is_empty(),
+        "bundled theme {name} uses colours missing from its palette: {}",
+        unknown.join(", ")
+    );
+
+    let missing = theme.missing_highlights();
+    if !missing.is_empty() {
+        debug!(
+            "Theme {name} has no rule for highlights: {}",
+            missing.join(", ")
+        );
+    }
+
+    theme
+}
+
+/// Only text-like responses are compressed, leaving alone the likes of snapshots (which are
+/// already gzipped) and packfiles streamed to git clients (which are already deflated).
+fn is_compressible_content_type(
+    _status: StatusCode,
+    _version: http::Version,
+    headers: &http::HeaderMap,
+    _extensions: &http::Extensions,
+) -> bool {
+    let Some(content_type) = headers
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return false;
+    };
+
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    mime.starts_with("text/")
+        || matches!(
+            mime.as_str(),
+            "application/json" | "application/javascript" | "application/xml" | "image/svg+xml"
+        )
+}
+
+/// Guesses the content type of a user-provided image from its file extension.
+fn image_content_type(path: &Path) -> &'static str {
+    match path
+        .extension()
+        .and_then(OsStr::to_str)
+        .map(str::to_ascii_lowercase)
+        .as_deref()
+    {
+        Some("ico") => "image/x-icon",
+        Some("png") => "image/png",
+        Some("svg") => "image/svg+xml",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
 #[must_use]
 pub fn build_asset_hash(v: &[u8]) -> Box<str> {
     let hasher = const_xxh3::xxh3_128(v);
@@ -373,3 +1169,133 @@ impl<A: IntoResponse, B: IntoResponse> IntoResponse for ResponseEither<A, B> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+
+    use clap::Parser;
+
+    use crate::{load_theme, open_db, spawn_indexer, Args, DEFAULT_ROBOTS_TXT};
+
+    /// Whether `path` is matched by a robots.txt `pattern`, which matches by prefix with `*`
+    /// matching anything and a trailing `$` anchoring it to the end.
+    fn robots_matches(pattern: &[u8], path: &[u8]) -> bool {
+        match pattern {
+            b"$" => path.is_empty(),
+            [] => true,
+            [b'*', rest @ ..] => (0..=path.len()).any(|i| robots_matches(rest, &path[i..])),
+            [c, rest @ ..] => path.first() == Some(c) && robots_matches(rest, &path[1..]),
+        }
+    }
+
+    fn robots_disallows(path: &str) -> bool {
+        DEFAULT_ROBOTS_TXT
+            .lines()
+            .filter_map(|line| line.strip_prefix("Disallow: "))
+            .any(|pattern| robots_matches(pattern.as_bytes(), path.as_bytes()))
+    }
+
+    #[test]
+    fn default_robots_txt_only_disallows_per_commit_pages() {
+        for path in [
+            "/repo.git/commit",
+            "/repo.git/commit?id=abc",
+            "/group/repo.git/diff?id=abc",
+            "/repo.git/patch",
+            "/repo.git/snapshot?h=main",
+            "/repo.git/snapshot/",
+        ] {
+            assert!(robots_disallows(path), "{path}");
+        }
+
+        for path in [
+            "/repo.git/commits",
+            "/repo.git/commit-graph",
+            "/repo.git/log",
+            "/group/commitment.git",
+            "/group/diffutils.git/tree",
+            "/group/snapshots.git",
+        ] {
+            assert!(!robots_disallows(path), "{path}");
+        }
+    }
+
+    #[test]
+    fn database_is_left_alone_when_it_cant_be_locked() {
+        let dir = tempfile::tempdir().unwrap();
+        let scan_path = tempfile::tempdir().unwrap();
+        let args = Args::parse_from([
+            OsStr::new("rgit"),
+            OsStr::new("--db-store"),
+            dir.path().as_os_str(),
+            OsStr::new("127.0.0.1:0"),
+            scan_path.path().as_os_str(),
+        ]);
+
+        let db = open_db(&args).unwrap();
+        db.put("marker", "still here").unwrap();
+        db.flush().unwrap();
+
+        // another instance holding the lock isn't corruption, so nothing should be repaired or
+        // destroyed from underneath it
+        assert!(open_db(&args).is_err());
+        assert_eq!(
+            db.get("marker").unwrap().as_deref(),
+            Some(&b"still here"[..])
+        );
+
+        drop(db);
+        let db = open_db(&args).unwrap();
+        assert_eq!(
+            db.get("marker").unwrap().as_deref(),
+            Some(&b"still here"[..])
+        );
+    }
+
+    #[test]
+    fn bundled_themes_load() {
+        load_theme("github_light", include_str!("../themes/github_light.toml"));
+        load_theme("onedark", include_str!("../themes/onedark.toml"));
+    }
+
+    #[test]
+    #[should_panic = "uses colours missing from its palette: red"]
+    fn themes_using_undefined_colours_are_rejected() {
+        load_theme("broken", "\"keyword\" = \"red\"\n[palette]\n");
+    }
+
+    #[test]
+    fn wakeups_during_an_index_are_dropped() {
+        let (started_send, started) = std::sync::mpsc::channel();
+        let (finish, finish_recv) = std::sync::mpsc::channel::<()>();
+
+        // an index that runs until it's told to finish
+        let wakeup = spawn_indexer(move || {
+            started_send.send(()).unwrap();
+            finish_recv.recv().unwrap();
+        });
+        started.recv().unwrap();
+
+        // a burst of sighups whilst the first index is still running
+        for _ in 0..10 {
+            assert!(!wakeup.wake());
+        }
+        finish.send(()).unwrap();
+
+        // the first wakeup once it's finished is queued, and any more are dropped since it's
+        // already pending
+        while !wakeup.wake() {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(!wakeup.wake());
+
+        started.recv().unwrap();
+        assert!(!wakeup.wake());
+        finish.send(()).unwrap();
+
+        assert!(started
+            .recv_timeout(std::time::Duration::from_millis(200))
+            .is_err());
+    }
+}
@@ -0,0 +1,39 @@
+//! Endpoints for operators, only routed when rgit is started with `--admin-token` and requiring
+//! it as an `Authorization: Bearer <token>` header.
+
+use std::sync::{Arc, Mutex, PoisonError};
+
+use axum::{
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+
+use crate::{database::indexer::IndexStatus, methods::repo::reindex::constant_time_eq};
+
+#[derive(Clone)]
+pub struct AdminToken(pub Arc<str>);
+
+/// `GET /admin/index-status`, reports the progress of the running index update or the state the
+/// last one finished in.
+pub async fn index_status(
+    Extension(AdminToken(token)): Extension<AdminToken>,
+    Extension(status): Extension<Arc<Mutex<IndexStatus>>>,
+    headers: HeaderMap,
+) -> Response {
+    let given_token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+
+    if !given_token.is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes())) {
+        return (StatusCode::UNAUTHORIZED, "Invalid admin token").into_response();
+    }
+
+    let status = status
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+
+    Json(status).into_response()
+}
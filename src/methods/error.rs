@@ -0,0 +1,33 @@
+use std::sync::atomic::Ordering;
+
+use askama::Template;
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+
+use crate::{into_response, methods::filters, DEBUG_ERRORS};
+
+#[derive(Template)]
+#[template(path = "error.html")]
+pub struct View {
+    status: StatusCode,
+    message: &'static str,
+    detail: Option<String>,
+}
+
+/// Renders an error page with the site's template. `detail` is only shown to the user if rgit
+/// was started with `--debug-errors`.
+pub fn render(status: StatusCode, message: &'static str, detail: Option<String>) -> Response {
+    let detail = detail.filter(|_| DEBUG_ERRORS.load(Ordering::Relaxed));
+
+    (
+        status,
+        into_response(View {
+            status,
+            message,
+            detail,
+        }),
+    )
+        .into_response()
+}
@@ -3,17 +3,19 @@
 
 use std::{
     borrow::Borrow,
-    fmt::Display,
+    fmt::{Display, Write},
     sync::{Arc, LazyLock},
 };
 
-use arc_swap::ArcSwap;
 use rkyv::{
     rend::{i32_le, i64_le},
     tuple::ArchivedTuple2,
 };
+use sha2::{Digest, Sha256};
 use time::{format_description::well_known::Rfc3339, OffsetDateTime, UtcOffset};
 
+use crate::{layers::locale::Locale, methods::repo::Repository};
+
 // pub fn format_time(s: impl Borrow<time::OffsetDateTime>) -> Result<String, askama::Error> {
 pub fn format_time(s: impl Into<Timestamp>) -> Result<String, askama::Error> {
     let s = s.into().0;
@@ -24,6 +26,15 @@ pub fn format_time(s: impl Into<Timestamp>) -> Result<String, askama::Error> {
         .map_err(askama::Error::Custom)
 }
 
+/// Formats a timestamp for display in the locale of the current request.
+pub fn format_date(s: impl Into<Timestamp>) -> Result<String, askama::Error> {
+    s.into()
+        .0
+        .format(Locale::current().date_format())
+        .map_err(Box::from)
+        .map_err(askama::Error::Custom)
+}
+
 pub fn branch_query(branch: Option<&str>) -> String {
     if let Some(b) = branch {
         format!("?h={b}")
@@ -33,14 +44,51 @@ pub fn branch_query(branch: Option<&str>) -> String {
 }
 
 pub fn timeago(s: impl Into<Timestamp>) -> Result<String, askama::Error> {
-    Ok(timeago::Formatter::new()
-        .convert((OffsetDateTime::now_utc() - s.into().0).try_into().unwrap()))
+    // clock skew and bogus committer dates mean timestamps can be in the future, in which case
+    // the elapsed duration is negative and can't be converted
+    match (OffsetDateTime::now_utc() - s.into().0).try_into() {
+        Ok(elapsed) => Ok(Locale::current().timeago().convert(elapsed)),
+        Err(_) => Ok("in the future".to_string()),
+    }
+}
+
+/// Renders a `<time>` element showing how long ago the timestamp was, with the absolute RFC 3339
+/// time in its tooltip. The output is already escaped, so should be marked `safe`.
+pub fn relative_time(s: impl Into<Timestamp>) -> Result<String, askama::Error> {
+    let s = s.into();
+    let absolute = format_time(s)?;
+
+    let mut out = format!(r#"<time datetime="{absolute}" title="{absolute}">"#);
+    v_htmlescape::b_escape(timeago(s)?.as_bytes(), &mut out);
+    out.push_str("</time>");
+
+    Ok(out)
 }
 
 pub fn file_perms(s: &u16) -> Result<String, askama::Error> {
     Ok(unix_mode::to_string(u32::from(*s)))
 }
 
+/// Formats a size in bytes using binary units, eg. `1.5 MiB`.
+#[allow(clippy::cast_precision_loss)]
+pub fn file_size(s: &u64) -> Result<String, askama::Error> {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut size = *s as f64;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    Ok(if unit == 0 {
+        format!("{s} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    })
+}
+
 pub struct DisplayHexBuffer<const N: usize>(pub const_hex::Buffer<N>);
 
 impl<const N: usize> Display for DisplayHexBuffer<N> {
@@ -55,59 +103,253 @@ pub fn hex(s: &[u8; 20]) -> Result<DisplayHexBuffer<20>, askama::Error> {
     Ok(DisplayHexBuffer(buf))
 }
 
-pub fn gravatar(email: &str) -> Result<&'static str, askama::Error> {
-    static CACHE: LazyLock<ArcSwap<hashbrown::HashMap<&'static str, &'static str>>> =
-        LazyLock::new(|| ArcSwap::new(Arc::new(hashbrown::HashMap::new())));
+/// Hashing scheme used to build avatar URLs, newer Gravatar and Libravatar versions support
+/// SHA-256 alongside the legacy MD5.
+#[derive(clap::ValueEnum, Copy, Clone, Debug, Default)]
+pub enum AvatarHash {
+    #[default]
+    Md5,
+    Sha256,
+}
+
+pub fn gravatar(email: &str) -> Result<Arc<str>, askama::Error> {
+    // bounded so instances with lots of unique committers don't slowly leak memory
+    static CACHE: LazyLock<moka::sync::Cache<String, Arc<str>>> =
+        LazyLock::new(|| moka::sync::Cache::new(10_000));
+
+    Ok(CACHE.get_with_by_ref(email, || {
+        let site = crate::site();
+        let email = email.trim().to_lowercase();
+
+        if site.no_avatars {
+            return Arc::from(identicon(&md5::compute(email).0));
+        }
 
-    if let Some(res) = CACHE.load().get(email).copied() {
-        return Ok(res);
+        let hash = match site.avatar_hash {
+            AvatarHash::Md5 => const_hex::encode(md5::compute(email).0),
+            AvatarHash::Sha256 => const_hex::encode(Sha256::digest(email)),
+        };
+
+        Arc::from(format!("{}/{hash}?s=13&d=retro", site.avatar_base))
+    }))
+}
+
+/// Builds a GitHub-style identicon from the given hash as an inline SVG data URI, so avatars can
+/// be shown without the browser making any external requests.
+fn identicon(hash: &[u8; 16]) -> String {
+    let mut svg = format!(
+        "<svg xmlns='http://www.w3.org/2000/svg' viewBox='0 0 5 5' shape-rendering='crispEdges'>\
+         <rect width='5' height='5' fill='rgb(240,240,240)'/>\
+         <g fill='rgb({},{},{})'>",
+        hash[0] / 2 + 32,
+        hash[1] / 2 + 32,
+        hash[2] / 2 + 32,
+    );
+
+    // the left three columns are filled in from the hash and mirrored to the right
+    for row in 0..5 {
+        for col in 0..3 {
+            let bit = row * 3 + col;
+
+            if hash[3 + bit / 8] & (1 << (bit % 8)) == 0 {
+                continue;
+            }
+
+            for x in [col, 4 - col] {
+                svg.push_str(&format!("<rect x='{x}' y='{row}' width='1' height='1'/>"));
+
+                if x == 2 {
+                    break;
+                }
+            }
+        }
+    }
+
+    svg.push_str("</g></svg>");
+
+    let mut uri = String::from("data:image/svg+xml,");
+    for c in svg.chars() {
+        match c {
+            '<' => uri.push_str("%3C"),
+            '>' => uri.push_str("%3E"),
+            '#' => uri.push_str("%23"),
+            ' ' => uri.push_str("%20"),
+            c => uri.push(c),
+        }
     }
 
-    let url = format!(
-        "https://www.gravatar.com/avatar/{}",
-        const_hex::encode(md5::compute(email).0)
+    uri
+}
+
+/// HTML-escapes the given text, linking issue references (eg. `#123`) to the repository's issue
+/// tracker and anything that looks like an abbreviated or full commit hash to its commit page.
+pub fn linkify(
+    text: &str,
+    repo: &Repository,
+    issues_url: &Option<String>,
+) -> Result<String, askama::Error> {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    let mut after_word = false;
+
+    // repository paths come straight from the filesystem, so can contain anything
+    let mut commit_url = String::new();
+    v_htmlescape::b_escape(
+        format!("{}/{}/commit", crate::site().base_path, repo.display()).as_bytes(),
+        &mut commit_url,
     );
-    let key = Box::leak(Box::from(email));
-    let url = url.leak();
 
-    CACHE.rcu(|curr| {
-        let mut r = (**curr).clone();
-        r.insert(key, url);
-        r
-    });
+    while let Some(c) = rest.chars().next() {
+        let word_len = rest
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(rest.len());
+
+        if word_len > 0 {
+            let word = &rest[..word_len];
+
+            if is_commit_hash(word) {
+                write!(out, r#"<a href="{commit_url}?id={word}">{word}</a>"#).unwrap();
+            } else {
+                out.push_str(word);
+            }
+
+            rest = &rest[word_len..];
+            after_word = true;
+            continue;
+        }
+
+        if let (false, '#', Some(issues_url)) = (after_word, c, issues_url) {
+            let number_len = rest[1..]
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len() - 1);
+            let number = &rest[1..=number_len];
+            let followed_by_word =
+                rest[1 + number_len..].starts_with(|c: char| c.is_ascii_alphanumeric());
+
+            if !number.is_empty() && !followed_by_word {
+                let url = if issues_url.contains("%s") {
+                    issues_url.replace("%s", number)
+                } else {
+                    format!("{}/{number}", issues_url.trim_end_matches('/'))
+                };
+
+                out.push_str(r#"<a href=""#);
+                v_htmlescape::b_escape(url.as_bytes(), &mut out);
+                write!(out, r#"">#{number}</a>"#).unwrap();
 
-    Ok(url)
+                rest = &rest[1 + number_len..];
+                after_word = false;
+                continue;
+            }
+        }
+
+        let mut buf = [0; 4];
+        v_htmlescape::b_escape(c.encode_utf8(&mut buf).as_bytes(), &mut out);
+        rest = &rest[c.len_utf8()..];
+        after_word = false;
+    }
+
+    Ok(out)
 }
 
+/// HTML-escapes the given text, turning any `http://` or `https://` URLs within it into links.
+pub fn autolink(text: &str) -> Result<String, askama::Error> {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest
+        .match_indices("http")
+        .map(|(i, _)| i)
+        .find(|&i| rest[i..].starts_with("http://") || rest[i..].starts_with("https://"))
+    {
+        v_htmlescape::b_escape(rest[..start].as_bytes(), &mut out);
+        rest = &rest[start..];
+
+        let end = rest
+            .find(|c: char| c.is_whitespace() || matches!(c, '<' | '>' | '"' | '\'' | '`'))
+            .unwrap_or(rest.len());
+        // trailing punctuation is far more likely to belong to the surrounding prose
+        let url = rest[..end].trim_end_matches(['.', ',', ':', ';', '!', '?', ')', ']']);
+
+        if url.split_once("://").is_some_and(|(_, v)| !v.is_empty()) {
+            out.push_str(r#"<a href=""#);
+            v_htmlescape::b_escape(url.as_bytes(), &mut out);
+            out.push_str(r#"" rel="nofollow noopener">"#);
+            v_htmlescape::b_escape(url.as_bytes(), &mut out);
+            out.push_str("</a>");
+        } else {
+            v_htmlescape::b_escape(url.as_bytes(), &mut out);
+        }
+
+        rest = &rest[url.len()..];
+    }
+
+    v_htmlescape::b_escape(rest.as_bytes(), &mut out);
+
+    Ok(out)
+}
+
+fn is_commit_hash(word: &str) -> bool {
+    (7..=40).contains(&word.len())
+        && word
+            .bytes()
+            .all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase())
+        && word.bytes().any(|c| c.is_ascii_digit())
+}
+
+/// Splits git trailers (eg. `Signed-off-by: ...`) out from the end of a commit message body,
+/// returning the body without them alongside the trailers themselves.
+pub fn split_trailers(body: &str) -> (&str, Vec<(&str, &str)>) {
+    let trimmed = body.trim_end();
+    let start = trimmed.rfind("\n\n").map_or(0, |i| i + 2);
+
+    let trailers = trimmed[start..]
+        .lines()
+        .map(|line| {
+            let (key, value) = line.split_once(": ")?;
+            let valid_key =
+                !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+            valid_key.then_some((key, value.trim()))
+        })
+        .collect::<Option<Vec<_>>>();
+
+    match trailers {
+        Some(trailers) if !trailers.is_empty() => (trimmed[..start].trim_end(), trailers),
+        _ => (body, Vec::new()),
+    }
+}
+
+#[derive(Copy, Clone)]
 pub struct Timestamp(OffsetDateTime);
 
+impl Timestamp {
+    /// Builds a timestamp from raw signature parts, which can be garbage in repositories with
+    /// corrupt signatures so fall back to the epoch/UTC rather than panicking.
+    fn from_parts(seconds: i64, offset: i32) -> Self {
+        let offset = UtcOffset::from_whole_seconds(offset).unwrap_or(UtcOffset::UTC);
+        let utc =
+            OffsetDateTime::from_unix_timestamp(seconds).unwrap_or(OffsetDateTime::UNIX_EPOCH);
+
+        // shifting a time at the very edge of the supported range can take it out of range
+        Self(utc.checked_to_offset(offset).unwrap_or(utc))
+    }
+}
+
 impl From<&ArchivedTuple2<i64_le, i32_le>> for Timestamp {
     fn from(value: &ArchivedTuple2<i64_le, i32_le>) -> Self {
-        Self(
-            OffsetDateTime::from_unix_timestamp(value.0.to_native())
-                .unwrap()
-                .to_offset(UtcOffset::from_whole_seconds(value.1.to_native()).unwrap()),
-        )
+        Self::from_parts(value.0.to_native(), value.1.to_native())
     }
 }
 
 impl From<(i64, i32)> for Timestamp {
     fn from(value: (i64, i32)) -> Self {
-        Self(
-            OffsetDateTime::from_unix_timestamp(value.0)
-                .unwrap()
-                .to_offset(UtcOffset::from_whole_seconds(value.1).unwrap()),
-        )
+        Self::from_parts(value.0, value.1)
     }
 }
 
 impl From<&(i64, i32)> for Timestamp {
     fn from(value: &(i64, i32)) -> Self {
-        Self(
-            OffsetDateTime::from_unix_timestamp(value.0)
-                .unwrap()
-                .to_offset(UtcOffset::from_whole_seconds(value.1).unwrap()),
-        )
+        Self::from_parts(value.0, value.1)
     }
 }
 
@@ -116,3 +358,88 @@ impl From<OffsetDateTime> for Timestamp {
         Self(value)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use time::UtcOffset;
+
+    use super::{linkify, timeago, Timestamp};
+    use crate::{
+        database::schema::repository::Repository as DbRepository,
+        methods::repo::Repository,
+        test_util::{self, init_site, Fixture},
+    };
+
+    #[test]
+    fn linkify_escapes_repository_path() {
+        init_site();
+
+        let repo = Repository(PathBuf::from("evil\"><script>alert(1)</script>.git"));
+        let out = linkify("fixed in deadbeef1", &repo, &None).unwrap();
+
+        assert!(!out.contains("<script"), "{out}");
+        assert!(out.contains("evil&quot;&gt;&lt;script&gt;"), "{out}");
+        assert!(
+            out.ends_with(r#"commit?id=deadbeef1">deadbeef1</a>"#),
+            "{out}"
+        );
+    }
+
+    #[test]
+    fn timestamps_out_of_range_fall_back_to_utc() {
+        // the very last second of the year 9999, which is in the year 10000 an hour ahead
+        let latest = Timestamp::from_parts(253_402_300_799, 3600);
+        assert_eq!(latest.0.unix_timestamp(), 253_402_300_799);
+        assert_eq!(latest.0.offset(), UtcOffset::UTC);
+
+        let garbage = Timestamp::from_parts(i64::MAX, i32::MAX);
+        assert_eq!(garbage.0.unix_timestamp(), 0);
+        assert_eq!(garbage.0.offset(), UtcOffset::UTC);
+
+        let regular = Timestamp::from_parts(1_700_000_000, -5 * 3600);
+        assert_eq!(regular.0.offset().whole_hours(), -5);
+    }
+
+    #[test]
+    fn future_dated_commits_render_as_in_the_future() {
+        let mut fixture = Fixture::new();
+        let parent = fixture.commit("initial");
+        let tree = fixture.git(&["rev-parse", "HEAD^{tree}"]);
+
+        // committed by someone whose clock is set to the year 2100
+        let future = fixture.write_raw_object(
+            "commit",
+            format!(
+                "tree {tree}\nparent {parent}\n\
+                 author Test <test@example.com> 4102444800 +0000\n\
+                 committer Test <test@example.com> 4102444800 +0000\n\nfrom the future\n"
+            )
+            .as_bytes(),
+        );
+        fixture.git(&["update-ref", "refs/heads/main", &future]);
+
+        let (_db_dir, db) = test_util::open_db(fixture.scan_path());
+        test_util::index(fixture.scan_path(), &db);
+
+        let repository = DbRepository::open(&db, None, fixture.relative_path())
+            .unwrap()
+            .unwrap();
+        let commits = repository
+            .get()
+            .commit_tree(db.clone(), None, "refs/heads/main")
+            .unwrap()
+            .fetch_latest(2, 0)
+            .unwrap();
+
+        assert_eq!(
+            timeago(commits[0].get().committer.time()).unwrap(),
+            "in the future"
+        );
+        assert_ne!(
+            timeago(commits[1].get().committer.time()).unwrap(),
+            "in the future"
+        );
+    }
+}
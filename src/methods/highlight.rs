@@ -0,0 +1,49 @@
+//! `POST /-/highlight`, which renders arbitrary snippets with the same grammars & classes used
+//! throughout the site so other services can share rgit's highlighting and stylesheets.
+
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use tracing::error;
+
+use crate::syntax_highlight::{format_file, FileIdentifier};
+
+#[derive(Deserialize)]
+pub struct HighlightRequest {
+    /// Language name or file extension, as would be given to a fenced code block (eg. `rust`
+    /// or `rs`), unknown languages are rendered as plaintext
+    language: String,
+    code: String,
+}
+
+pub async fn handle(Json(request): Json<HighlightRequest>) -> Response {
+    let res = tokio::task::spawn_blocking(move || {
+        format_file(&request.code, FileIdentifier::Token(&request.language))
+    })
+    .await;
+
+    match res {
+        Ok(Ok(html)) => {
+            ([(header::CONTENT_TYPE, "text/html; charset=utf-8")], html).into_response()
+        }
+        Ok(Err(error)) => {
+            error!(?error, "Failed to highlight snippet");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to highlight snippet",
+            )
+                .into_response()
+        }
+        Err(error) => {
+            error!(?error, "Highlighter panicked");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to highlight snippet",
+            )
+                .into_response()
+        }
+    }
+}
@@ -1,3 +1,7 @@
+pub mod admin;
+pub mod error;
 pub mod filters;
+pub mod highlight;
 pub mod index;
 pub mod repo;
+pub mod sitemap;
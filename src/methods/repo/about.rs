@@ -26,12 +26,14 @@ pub struct View {
     repo: Repository,
     readme: Option<(ReadmeFormat, Arc<str>)>,
     branch: Option<Arc<str>>,
+    description: Option<String>,
 }
 
 pub async fn handle(
     Extension(repo): Extension<Repository>,
     Extension(RepositoryPath(repository_path)): Extension<RepositoryPath>,
     Extension(git): Extension<Arc<Git>>,
+    Extension(db): Extension<Arc<rocksdb::DB>>,
     Query(query): Query<UriQuery>,
 ) -> Result<impl IntoResponse> {
     let open_repo = git
@@ -40,9 +42,13 @@ pub async fn handle(
         .await?;
     let readme = open_repo.readme().await?;
 
+    let description = crate::database::schema::repository::Repository::open(&db, None, &*repo)?
+        .and_then(|v| v.get().description.as_deref().map(ToString::to_string));
+
     Ok(into_response(View {
         repo,
         readme,
         branch: query.branch,
+        description,
     }))
 }
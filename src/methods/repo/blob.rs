@@ -0,0 +1,115 @@
+use std::{path::Path, str::FromStr, sync::Arc};
+
+use axum::{
+    http::{header, HeaderMap, HeaderValue},
+    response::IntoResponse,
+    Extension,
+};
+use gix::ObjectId;
+
+use crate::{
+    git::NotFound,
+    methods::repo::{tree::raw_blob, ChildPath, RepositoryPath, Result},
+    Git,
+};
+
+pub async fn handle(
+    Extension(RepositoryPath(repository_path)): Extension<RepositoryPath>,
+    Extension(ChildPath(child_path)): Extension<ChildPath>,
+    Extension(git): Extension<Arc<Git>>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse> {
+    // only full ids are accepted, an abbreviated one could become ambiguous later on and
+    // wouldn't be safe to cache forever
+    let id = child_path
+        .as_deref()
+        .and_then(Path::to_str)
+        .and_then(|v| ObjectId::from_str(v).ok())
+        .ok_or_else(|| anyhow::Error::new(NotFound("Blob ids must be given in full")))?;
+
+    let open_repo = git.repo(repository_path, None).await?;
+    let content = open_repo.blob(id).await?;
+
+    // blobs are addressed by their content, so whatever is served for an id never changes,
+    // except for an LFS pointer that's served in place of an object the store doesn't have yet
+    let immutable = !content.is_lfs_pointer();
+    let mut response = raw_blob(id, content, &headers);
+
+    if immutable {
+        response.headers_mut().insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static("public, max-age=31536000, immutable"),
+        );
+    } else {
+        // the etag is the blob id, which stays the same once the object turns up
+        response.headers_mut().remove(header::ETAG);
+        response
+            .headers_mut()
+            .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    }
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use axum::{
+        http::{header, HeaderMap},
+        response::IntoResponse,
+        Extension,
+    };
+
+    use super::handle;
+    use crate::{
+        git::GitConfig,
+        methods::repo::{ChildPath, RepositoryPath},
+        test_util::{self, git_with, Fixture},
+    };
+
+    #[tokio::test]
+    async fn only_resolved_blobs_are_cached_forever() {
+        const OID: &str = "4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393";
+
+        let mut fixture = Fixture::new();
+        fixture.write("plain.txt", "plain\n");
+        fixture.write_lfs_pointer("stored.bin", OID, 14);
+        fixture.write_lfs_pointer("missing.bin", &"0".repeat(64), 1);
+        fixture.commit("lfs pointers");
+
+        let store = test_util::lfs_store(&[(OID, b"actual content")]);
+
+        let git = git_with(GitConfig {
+            lfs_store: Some(store.path().to_path_buf()),
+            ..GitConfig::default()
+        });
+
+        for (path, immutable) in [
+            ("plain.txt", true),
+            ("stored.bin", true),
+            ("missing.bin", false),
+        ] {
+            let id = fixture.git(&["rev-parse", &format!("HEAD:{path}")]);
+            let Ok(response) = handle(
+                Extension(RepositoryPath(fixture.path())),
+                Extension(ChildPath(Some(PathBuf::from(id)))),
+                Extension(git.clone()),
+                HeaderMap::new(),
+            )
+            .await
+            else {
+                panic!("{path} wasn't served");
+            };
+            let response = response.into_response();
+
+            let cache_control = response.headers()[header::CACHE_CONTROL].to_str().unwrap();
+            assert_eq!(cache_control.contains("immutable"), immutable, "{path}");
+            assert_eq!(
+                response.headers().contains_key(header::ETAG),
+                immutable,
+                "{path}"
+            );
+        }
+    }
+}
@@ -0,0 +1,250 @@
+//! Redirects from cgit's URL scheme, so links to an instance that was migrated from cgit keep
+//! working once `--cgit-urls` is enabled.
+//!
+//! Most of cgit's URLs (`commit/?id=`, `tree/<path>?h=`, `log/?ofs=`, `patch/`, `tag/`,
+//! `refs/`, `about/`, `stats/`) already mean the same thing to rgit, so only the ones that
+//! differ are rewritten here:
+//!
+//! | cgit                                      | rgit                                     |
+//! |-------------------------------------------|------------------------------------------|
+//! | `/<repo>/plain/<path>?<query>`            | `/<repo>/tree/<path>?<query>&raw=true`   |
+//! | `/<repo>/blob/<path>?<query>`             | `/<repo>/tree/<path>?<query>`            |
+//! | `/<repo>/log/<path>?<query>`              | `/<repo>/log?<query>`                    |
+//! | `/<repo>/refs/heads`, `/<repo>/refs/tags` | `/<repo>/refs`                           |
+//! | `/<repo>/snapshot/<repo>-<ref>.tar.gz`    | `/<repo>/snapshot?h=<ref>`               |
+//!
+//! Every redirect stays under `--base-path`, if one was given.
+//!
+//! rgit only builds `.tar.gz` snapshots, so requests for cgit's other snapshot formats are
+//! rejected rather than served in a different format to the one asked for.
+
+use axum::{
+    http::{header, StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
+
+/// Whether cgit-style URLs should be redirected, set via `--cgit-urls`.
+#[derive(Clone, Copy)]
+pub struct CgitUrls(pub bool);
+
+/// The path segments that separate a repository from the rest of a cgit URL. `tree` is
+/// included so that a tree path containing one of the others isn't mistaken for it.
+const ACTIONS: &[&str] = &["tree", "plain", "blob", "log", "refs", "snapshot"];
+
+/// Snapshot formats cgit can serve which rgit can't.
+const UNSUPPORTED_SNAPSHOT_FORMATS: &[&str] =
+    &[".tar", ".tar.bz2", ".tar.lz", ".tar.xz", ".tar.zst", ".zip"];
+
+/// Maps a cgit URL that rgit wouldn't otherwise understand onto its rgit equivalent, returning
+/// `None` if the URL can be served as-is.
+pub fn redirect(uri: &Uri) -> Option<Response> {
+    let path = uri.path().trim_matches('/');
+    let (repo, action, rest) = split_action(path)?;
+
+    let (location, extra_query) = match action {
+        "plain" => (format!("/{repo}/tree/{rest}"), Some("raw=true")),
+        "blob" => (format!("/{repo}/tree/{rest}"), None),
+        "log" => (format!("/{repo}/log"), None),
+        "refs" if rest == "heads" || rest == "tags" => (format!("/{repo}/refs"), None),
+        "snapshot" => {
+            let Some(name) = rest.strip_suffix(".tar.gz") else {
+                if UNSUPPORTED_SNAPSHOT_FORMATS
+                    .iter()
+                    .any(|format| rest.ends_with(format))
+                {
+                    return Some(
+                        (
+                            StatusCode::NOT_FOUND,
+                            "Only .tar.gz snapshots are available",
+                        )
+                            .into_response(),
+                    );
+                }
+
+                return None;
+            };
+
+            return Some(moved_permanently(&snapshot_location(repo, name)));
+        }
+        _ => return None,
+    };
+
+    let query = [uri.query().filter(|v| !v.is_empty()), extra_query]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join("&");
+
+    Some(if query.is_empty() {
+        moved_permanently(&location)
+    } else {
+        moved_permanently(&format!("{location}?{query}"))
+    })
+}
+
+/// Splits a path into the repository, the first cgit action found in it, and everything after
+/// the action.
+fn split_action(path: &str) -> Option<(&str, &str, &str)> {
+    memchr::memchr_iter(b'/', path.as_bytes()).find_map(|idx| {
+        let (action, rest) = path[idx + 1..].split_once('/')?;
+
+        (ACTIONS.contains(&action) && !rest.is_empty()).then(|| (&path[..idx], action, rest))
+    })
+}
+
+/// cgit names snapshots `<repo>-<ref>`, where `<repo>` is the last component of the repository
+/// path without any `.git` suffix.
+fn snapshot_location(repo: &str, name: &str) -> String {
+    let prefix = repo.rsplit('/').next().unwrap_or(repo);
+    let prefix = prefix.strip_suffix(".git").unwrap_or(prefix);
+
+    let reference = name
+        .strip_prefix(prefix)
+        .and_then(|v| v.strip_prefix('-'))
+        .filter(|v| !v.is_empty())
+        .unwrap_or(name);
+
+    // the reference was percent-encoded as part of the path, so only the characters that mean
+    // something different in a query string need escaping
+    let reference = reference.replace('&', "%26").replace('+', "%2B");
+
+    if reference.len() == 40 && reference.bytes().all(|c| c.is_ascii_hexdigit()) {
+        format!("/{repo}/snapshot?id={reference}")
+    } else {
+        format!("/{repo}/snapshot?h={reference}")
+    }
+}
+
+fn moved_permanently(location: &str) -> Response {
+    (
+        StatusCode::MOVED_PERMANENTLY,
+        [(
+            header::LOCATION,
+            format!("{}{location}", crate::site().base_path),
+        )],
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::Arc};
+
+    use axum::{
+        body::Body,
+        http::{header, Request, StatusCode, Uri},
+        Extension, Router,
+    };
+    use tower::ServiceExt;
+
+    use super::{redirect, CgitUrls};
+    use crate::test_util::{init_site, BASE_PATH};
+
+    fn location(uri: &str) -> Option<String> {
+        init_site();
+
+        let response = redirect(&uri.parse::<Uri>().unwrap())?;
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+
+        Some(
+            response.headers()[header::LOCATION]
+                .to_str()
+                .unwrap()
+                .to_string(),
+        )
+    }
+
+    #[test]
+    fn redirects_stay_under_base_path() {
+        assert_eq!(
+            location("/repo.git/plain/src/main.rs?h=dev").as_deref(),
+            Some("/git/repo.git/tree/src/main.rs?h=dev&raw=true")
+        );
+        assert_eq!(
+            location("/group/repo.git/snapshot/repo-v1.0.tar.gz").as_deref(),
+            Some("/git/group/repo.git/snapshot?h=v1.0")
+        );
+    }
+
+    #[test]
+    fn common_cgit_urls_are_mapped() {
+        for (cgit, rgit) in [
+            (
+                "/repo.git/plain/README.md",
+                "/git/repo.git/tree/README.md?raw=true",
+            ),
+            (
+                "/repo.git/blob/src/lib.rs?id=abc",
+                "/git/repo.git/tree/src/lib.rs?id=abc",
+            ),
+            ("/repo.git/log/src/lib.rs?h=dev", "/git/repo.git/log?h=dev"),
+            ("/repo.git/refs/heads", "/git/repo.git/refs"),
+            ("/repo.git/refs/tags", "/git/repo.git/refs"),
+            (
+                "/repo.git/snapshot/repo-main.tar.gz",
+                "/git/repo.git/snapshot?h=main",
+            ),
+            (
+                "/repo.git/snapshot/repo-0123456789abcdef0123456789abcdef01234567.tar.gz",
+                "/git/repo.git/snapshot?id=0123456789abcdef0123456789abcdef01234567",
+            ),
+            // a tree path that happens to contain another action is still a tree path
+            (
+                "/repo.git/plain/docs/log/index.md",
+                "/git/repo.git/tree/docs/log/index.md?raw=true",
+            ),
+        ] {
+            assert_eq!(location(cgit).as_deref(), Some(rgit), "{cgit}");
+        }
+    }
+
+    #[test]
+    fn cgit_urls_rgit_already_understands_are_left_alone() {
+        for uri in [
+            "/repo.git/commit/?id=abc",
+            "/repo.git/tree/?id=abc",
+            "/repo.git/tree/src/lib.rs?h=dev",
+            "/repo.git/log/?ofs=50",
+            "/repo.git/patch/?id=abc",
+            "/repo.git/about/",
+        ] {
+            assert_eq!(location(uri), None, "{uri}");
+        }
+    }
+
+    #[test]
+    fn unsupported_snapshot_formats_are_not_found() {
+        init_site();
+
+        let response = redirect(&Uri::from_static("/repo.git/snapshot/repo-main.zip")).unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn redirects_when_nested_under_base_path() {
+        init_site();
+
+        let app = Router::new().nest(
+            BASE_PATH,
+            Router::new()
+                .fallback(crate::methods::repo::service)
+                .layer(Extension(CgitUrls(true)))
+                .layer(Extension(Arc::new(PathBuf::from("/nonexistent")))),
+        );
+
+        let response = app
+            .oneshot(
+                Request::get("/git/repo.git/blob/README.md?id=abc")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "/git/repo.git/tree/README.md?id=abc"
+        );
+    }
+}
@@ -2,14 +2,16 @@ use std::sync::Arc;
 
 use askama::Template;
 use axum::{extract::Query, response::IntoResponse, Extension};
+use gix::bstr::ByteSlice;
 use serde::Deserialize;
 
 use crate::{
-    git::{Commit, OpenRepository},
+    database::schema::{commit::CommitNeighbors, repository::YokedRepository, Snapshot},
+    git::{Commit, ContainedRefs, DiffAlgorithm, DiffOptions, OpenRepository, SignatureStatus},
     into_response,
     methods::{
         filters,
-        repo::{Repository, RepositoryPath, Result},
+        repo::{diff::diff_options, log::find_commit_tree, Repository, RepositoryPath, Result},
     },
     Git,
 };
@@ -22,6 +24,12 @@ pub struct View {
     pub branch: Option<Arc<str>>,
     pub dl_branch: Arc<str>,
     pub id: Option<String>,
+    pub body: String,
+    pub trailers: Vec<(String, String)>,
+    pub issues_url: Option<String>,
+    pub signature: Option<SignatureStatus>,
+    pub neighbors: CommitNeighbors,
+    pub contained_in: Option<Arc<ContainedRefs>>,
 }
 
 #[derive(Deserialize)]
@@ -29,38 +37,104 @@ pub struct UriQuery {
     pub id: Option<String>,
     #[serde(rename = "h")]
     pub branch: Option<Arc<str>>,
+    /// Lines of context to show around each change in the diff
+    pub context: Option<u32>,
+    /// Algorithm to diff with, rather than the configured default
+    pub algo: Option<DiffAlgorithm>,
 }
 
 pub async fn handle(
     Extension(repo): Extension<Repository>,
     Extension(RepositoryPath(repository_path)): Extension<RepositoryPath>,
     Extension(git): Extension<Arc<Git>>,
+    Extension(db): Extension<Arc<rocksdb::DB>>,
     Query(query): Query<UriQuery>,
 ) -> Result<impl IntoResponse> {
     let open_repo = git.repo(repository_path, query.branch.clone()).await?;
 
     let (dl_branch, commit) = tokio::try_join!(
         fetch_dl_branch(query.branch.clone(), open_repo.clone()),
-        fetch_commit(query.id.as_deref(), open_repo),
+        fetch_commit(
+            query.id.as_deref(),
+            diff_options(query.context, query.algo),
+            open_repo.clone()
+        ),
     )?;
 
+    let (signature, contained_in) = tokio::try_join!(
+        open_repo.clone().signature_status(commit.get().object_id()),
+        open_repo.clone().refs_containing(commit.get().object_id()),
+    )?;
+
+    let snapshot = db.snapshot();
+    let repository =
+        crate::database::schema::repository::Repository::open(&db, Some(&snapshot), &*repo)?;
+    let issues_url = repository
+        .as_ref()
+        .and_then(|v| v.get().issues_url.as_deref().map(ToString::to_string));
+
+    let gix::ObjectId::Sha1(oid) = commit.get().object_id();
+    let mut neighbors = repository
+        .map(|repository| {
+            fetch_neighbors(&repository, &db, &snapshot, query.branch.as_deref(), &oid)
+        })
+        .transpose()?
+        .flatten()
+        .unwrap_or_default();
+
+    // the commit isn't on the indexed branch, but we can always step back to its parent
+    if neighbors.previous.is_none() {
+        neighbors.previous = commit.get().first_parent();
+    }
+
+    let full_body = commit.get().body().to_str_lossy();
+    let (body, trailers) = filters::split_trailers(&full_body);
+    let body = body.to_string();
+    let trailers = trailers
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+
     Ok(into_response(View {
         repo,
         commit,
         branch: query.branch,
         id: query.id,
         dl_branch,
+        body,
+        trailers,
+        issues_url,
+        signature,
+        neighbors,
+        contained_in,
     }))
 }
 
+/// Finds the commits either side of `oid` on the requested branch or tag, or the default branch
+/// if none was requested.
+fn fetch_neighbors(
+    repository: &YokedRepository,
+    database: &Arc<rocksdb::DB>,
+    snapshot: &Snapshot<'_>,
+    branch: Option<&str>,
+    oid: &[u8; 20],
+) -> Result<Option<CommitNeighbors>> {
+    let Some(tree) = find_commit_tree(repository, database, snapshot, branch)? else {
+        return Ok(None);
+    };
+
+    tree.neighbors(oid)
+}
+
 async fn fetch_commit(
     commit_id: Option<&str>,
+    options: DiffOptions,
     open_repo: Arc<OpenRepository>,
 ) -> Result<Arc<Commit>> {
     Ok(if let Some(commit) = commit_id {
-        open_repo.commit(commit, true).await?
+        open_repo.commit(commit, true, options).await?
     } else {
-        Arc::new(open_repo.latest_commit(true).await?)
+        Arc::new(open_repo.latest_commit(true, options).await?)
     })
 }
 
@@ -9,10 +9,11 @@ use axum::{
 };
 use bytes::{BufMut, BytesMut};
 use clap::crate_version;
+use serde::Deserialize;
 use time::format_description::well_known::Rfc2822;
 
 use crate::{
-    git::Commit,
+    git::{Commit, DiffAlgorithm, DiffOptions},
     http, into_response,
     methods::{
         filters,
@@ -36,10 +37,11 @@ pub async fn handle(
     Query(query): Query<UriQuery>,
 ) -> Result<impl IntoResponse> {
     let open_repo = git.repo(repository_path, query.branch.clone()).await?;
+    let options = diff_options(query.context, query.algo);
     let commit = if let Some(commit) = query.id {
-        open_repo.commit(&commit, true).await?
+        open_repo.commit(&commit, true, options).await?
     } else {
-        Arc::new(open_repo.latest_commit(true).await?)
+        Arc::new(open_repo.latest_commit(true, options).await?)
     };
 
     Ok(into_response(View {
@@ -49,16 +51,58 @@ pub async fn handle(
     }))
 }
 
+#[derive(Deserialize)]
+pub struct PlainUriQuery {
+    pub id: Option<String>,
+    #[serde(rename = "h")]
+    pub branch: Option<Arc<str>>,
+    /// Base of a range of commits to build a patch series from, exclusive
+    pub from: Option<String>,
+    /// Lines of context to show around each change
+    pub context: Option<u32>,
+    /// Algorithm to diff with, rather than the configured default
+    pub algo: Option<DiffAlgorithm>,
+}
+
+/// Lines of context shown around each change when a diff doesn't ask for a specific amount,
+/// the same as git.
+const DEFAULT_CONTEXT_LINES: u32 = 3;
+
+/// Diffs are cached separately for each amount of context, so requests are limited to a
+/// reasonable range.
+const MAX_CONTEXT_LINES: u32 = 100;
+
+/// Builds the options for a diff from its `?context=` and `?algo=` queries.
+pub fn diff_options(context: Option<u32>, algorithm: Option<DiffAlgorithm>) -> DiffOptions {
+    DiffOptions {
+        context_lines: context.map_or(DEFAULT_CONTEXT_LINES, |v| v.min(MAX_CONTEXT_LINES)),
+        algorithm,
+    }
+}
+
 pub async fn handle_plain(
     Extension(RepositoryPath(repository_path)): Extension<RepositoryPath>,
     Extension(git): Extension<Arc<Git>>,
-    Query(query): Query<UriQuery>,
+    Query(query): Query<PlainUriQuery>,
 ) -> Result<Response> {
     let open_repo = git.repo(repository_path, query.branch).await?;
-    let commit = if let Some(commit) = query.id {
-        open_repo.commit(&commit, false).await?
+    let options = diff_options(query.context, query.algo);
+
+    let commits = if let Some(from) = query.from {
+        let ids = open_repo
+            .clone()
+            .commit_range(&from, query.id.as_deref())
+            .await?;
+
+        let mut commits = Vec::with_capacity(ids.len());
+        for id in ids {
+            commits.push(open_repo.clone().commit(&id, false, options).await?);
+        }
+        commits
+    } else if let Some(commit) = query.id {
+        vec![open_repo.commit(&commit, false, options).await?]
     } else {
-        Arc::new(open_repo.latest_commit(false).await?)
+        vec![Arc::new(open_repo.latest_commit(false, options).await?)]
     };
 
     let headers = [(
@@ -68,6 +112,27 @@ pub async fn handle_plain(
 
     let mut data = BytesMut::new();
 
+    // a single commit keeps the plain `[PATCH]` subject, like `git format-patch` does
+    let total = commits.len();
+    for (i, commit) in commits.iter().enumerate() {
+        if i > 0 {
+            writeln!(data).unwrap();
+        }
+
+        let subject_prefix = if total == 1 {
+            "[PATCH]".to_string()
+        } else {
+            format!("[PATCH {}/{total}]", i + 1)
+        };
+
+        data = write_patch(data, commit, &subject_prefix);
+    }
+
+    Ok((headers, data.freeze()).into_response())
+}
+
+/// Writes the given commit out as a single mbox-formatted patch.
+fn write_patch(mut data: BytesMut, commit: &Commit, subject_prefix: &str) -> BytesMut {
     writeln!(data, "From {} Mon Sep 17 00:00:00 2001", commit.get().oid()).unwrap();
     writeln!(
         data,
@@ -88,7 +153,12 @@ pub async fn handle_plain(
     let mut data = writer.into_inner();
     writeln!(data).unwrap();
 
-    writeln!(data, "Subject: [PATCH] {}\n", commit.get().summary()).unwrap();
+    writeln!(
+        data,
+        "Subject: {subject_prefix} {}\n",
+        commit.get().summary()
+    )
+    .unwrap();
 
     write!(data, "{}", commit.get().body()).unwrap();
 
@@ -100,5 +170,62 @@ pub async fn handle_plain(
 
     writeln!(data, "--\nrgit {}", crate_version!()).unwrap();
 
-    Ok((headers, data.freeze()).into_response())
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{extract::Query, Extension};
+
+    use super::{handle_plain, PlainUriQuery};
+    use crate::{
+        methods::repo::RepositoryPath,
+        test_util::{self, Fixture},
+    };
+
+    #[tokio::test]
+    async fn patches_apply_with_git() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mut fixture = Fixture::new();
+        fixture.write("changed.txt", "a\nb\nc\n");
+        fixture.write("deleted.txt", "gone\n");
+        fixture.write("run.sh", "echo hi\n");
+        fixture.commit("initial");
+
+        fixture.write("changed.txt", "a\nB\nc\nd\n");
+        fixture.write("created/file.txt", "new\n");
+        std::fs::remove_file(fixture.path().join("deleted.txt")).unwrap();
+        std::fs::set_permissions(
+            fixture.path().join("run.sh"),
+            std::fs::Permissions::from_mode(0o755),
+        )
+        .unwrap();
+        let commit = fixture.commit("everything");
+        let tree = fixture.git(&["rev-parse", "HEAD^{tree}"]);
+
+        let Ok(response) = handle_plain(
+            Extension(RepositoryPath(fixture.path())),
+            Extension(test_util::git()),
+            Query(PlainUriQuery {
+                id: Some(commit),
+                branch: None,
+                from: None,
+                context: None,
+                algo: None,
+            }),
+        )
+        .await
+        else {
+            panic!("patch wasn't built");
+        };
+        let patch = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        fixture.git(&["reset", "-q", "--hard", "HEAD~"]);
+        fixture.git_with_stdin(&["apply", "--index", "-"], &patch);
+
+        assert_eq!(fixture.git(&["write-tree"]), tree);
+    }
 }
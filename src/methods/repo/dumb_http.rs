@@ -0,0 +1,178 @@
+//! Serves the files needed by clients using git's "dumb" HTTP protocol straight from the bare
+//! repository, for clients & mirrors that can't speak the smart protocol.
+//!
+//! The repository must have been prepared with `git update-server-info` (eg. from a
+//! `post-update` hook) for `info/refs` and `objects/info/packs` to exist.
+
+use std::{
+    ffi::OsStr,
+    path::{Component, Path},
+};
+
+use axum::{
+    body::Body,
+    http::{header, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use tokio_util::io::ReaderStream;
+use tracing::warn;
+
+use crate::methods::repo::{ChildPath, RepositoryPath};
+
+/// Whether dumb HTTP clients should be served, set via `--enable-dumb-http`.
+#[derive(Clone, Copy)]
+pub struct DumbHttp(pub bool);
+
+/// Whether a request routed to the smart protocol handler is actually from a dumb client, which
+/// fetch `info/refs` without asking for a `service`.
+pub fn is_dumb_refs_request(uri: &Uri) -> bool {
+    uri.path().trim_end_matches('/').ends_with("/info/refs")
+        && !uri
+            .query()
+            .is_some_and(|q| q.split('&').any(|v| v.starts_with("service=")))
+}
+
+pub async fn handle(
+    Extension(RepositoryPath(repository_path)): Extension<RepositoryPath>,
+    Extension(ChildPath(child_path)): Extension<ChildPath>,
+    Extension(DumbHttp(enabled)): Extension<DumbHttp>,
+) -> Response {
+    if !enabled {
+        return (StatusCode::NOT_FOUND, "Dumb HTTP is disabled").into_response();
+    }
+
+    if !repository_path.join("git-daemon-export-ok").exists() {
+        return (StatusCode::FORBIDDEN, "Repository not exported").into_response();
+    }
+
+    let Some(child_path) = child_path.filter(|v| is_servable(v)) else {
+        return (StatusCode::NOT_FOUND, "File not found").into_response();
+    };
+
+    // the path itself is already confined to the repository, but a symlink within it could
+    // still point elsewhere
+    let (Ok(repository_path), Ok(path)) = (
+        repository_path.canonicalize(),
+        repository_path.join(&child_path).canonicalize(),
+    ) else {
+        return (StatusCode::NOT_FOUND, "File not found").into_response();
+    };
+
+    if !path.starts_with(&repository_path) {
+        warn!(
+            "Refusing to serve {} as it's outside of the repository",
+            path.display()
+        );
+        return (StatusCode::NOT_FOUND, "File not found").into_response();
+    }
+
+    if !path.is_file() {
+        return (StatusCode::NOT_FOUND, "File not found").into_response();
+    }
+
+    let Ok(file) = tokio::fs::File::open(&path).await else {
+        return (StatusCode::NOT_FOUND, "File not found").into_response();
+    };
+
+    let (content_type, cache_control) = file_headers(&child_path);
+
+    (
+        [
+            (header::CONTENT_TYPE, content_type),
+            (header::CACHE_CONTROL, cache_control),
+        ],
+        Body::from_stream(ReaderStream::new(file)),
+    )
+        .into_response()
+}
+
+/// Only the files a dumb client needs are served, so things like `config`, hooks and the paths
+/// of any alternate object stores stay private.
+fn is_servable(path: &Path) -> bool {
+    let components: Option<Vec<&str>> = path
+        .components()
+        .map(|c| match c {
+            Component::Normal(v) => v.to_str(),
+            _ => None,
+        })
+        .collect();
+
+    match components.as_deref() {
+        Some(
+            ["HEAD"] | ["info", "refs"] | ["objects", "info", "packs"] | ["objects", "pack", _],
+        ) => true,
+        // loose objects, stored by their hex id split after the first byte
+        Some(["objects", dir, file]) => is_lower_hex(dir, 2) && is_lower_hex(file, 38),
+        _ => false,
+    }
+}
+
+fn is_lower_hex(v: &str, len: usize) -> bool {
+    v.len() == len && v.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+fn file_headers(path: &Path) -> (&'static str, &'static str) {
+    // objects are addressed by their content so can never change, whereas everything else is
+    // updated on push
+    const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+    const NO_CACHE: &str = "no-cache";
+
+    if path.starts_with("objects/info") {
+        ("text/plain; charset=utf-8", NO_CACHE)
+    } else if path.starts_with("objects/pack") {
+        match path.extension().and_then(OsStr::to_str) {
+            Some("pack") => ("application/x-git-packed-objects", IMMUTABLE),
+            Some("idx") => ("application/x-git-packed-objects-toc", IMMUTABLE),
+            _ => ("application/octet-stream", IMMUTABLE),
+        }
+    } else if path.starts_with("objects") {
+        ("application/x-git-loose-object", IMMUTABLE)
+    } else {
+        ("text/plain; charset=utf-8", NO_CACHE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use axum::{http::StatusCode, Extension};
+
+    use super::{handle, DumbHttp};
+    use crate::methods::repo::{ChildPath, RepositoryPath};
+
+    #[tokio::test]
+    async fn only_files_dumb_clients_need_are_served() {
+        let repository = tempfile::tempdir().unwrap();
+        let loose = format!("objects/ab/{}", "c".repeat(38));
+
+        let files = [
+            ("HEAD", StatusCode::OK),
+            ("info/refs", StatusCode::OK),
+            ("objects/info/packs", StatusCode::OK),
+            ("objects/pack/pack-abc.pack", StatusCode::OK),
+            (loose.as_str(), StatusCode::OK),
+            ("config", StatusCode::NOT_FOUND),
+            ("git-daemon-export-ok", StatusCode::NOT_FOUND),
+            ("objects/info/alternates", StatusCode::NOT_FOUND),
+            ("objects/info/http-alternates", StatusCode::NOT_FOUND),
+            ("objects/AB/CDEF", StatusCode::NOT_FOUND),
+        ];
+        for (file, _) in &files {
+            let path = repository.path().join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+
+        for (file, expected) in files {
+            let response = handle(
+                Extension(RepositoryPath(repository.path().to_path_buf())),
+                Extension(ChildPath(Some(PathBuf::from(file)))),
+                Extension(DumbHttp(true)),
+            )
+            .await;
+            assert_eq!(response.status(), expected, "{file}");
+        }
+    }
+}
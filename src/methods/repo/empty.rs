@@ -0,0 +1,37 @@
+use std::sync::Arc;
+
+use askama::Template;
+use axum::{response::IntoResponse, Extension};
+use axum_extra::extract::Host;
+
+use crate::{
+    into_response,
+    methods::{
+        filters,
+        repo::{Repository, RepositoryPath},
+    },
+};
+
+#[derive(Template)]
+#[template(path = "repo/empty.html")]
+pub struct View {
+    repo: Repository,
+    branch: Option<Arc<str>>,
+    exported: bool,
+    host: String,
+}
+
+pub async fn handle(
+    Extension(repo): Extension<Repository>,
+    Extension(RepositoryPath(repository_path)): Extension<RepositoryPath>,
+    Host(host): Host,
+) -> impl IntoResponse {
+    let exported = repository_path.join("git-daemon-export-ok").exists();
+
+    into_response(View {
+        repo,
+        branch: None,
+        exported,
+        host,
+    })
+}
@@ -1,25 +1,47 @@
-use std::sync::Arc;
+use std::{fmt::Write, sync::Arc};
 
 use anyhow::Context;
 use askama::Template;
 use axum::{extract::Query, response::IntoResponse, Extension};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
 use serde::Deserialize;
 
 use crate::{
-    database::schema::{commit::YokedCommit, repository::YokedRepository},
+    database::schema::{
+        commit::{CommitTree, YokedCommit},
+        repository::YokedRepository,
+        Snapshot,
+    },
     into_response,
     methods::{
         filters,
-        repo::{Repository, Result, DEFAULT_BRANCHES},
+        repo::{default_branches, Repository, Result},
     },
 };
 
+/// Characters that can't appear as-is in a query string value.
+const QUERY_VALUE: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'&')
+    .add(b'+')
+    .add(b'<')
+    .add(b'=')
+    .add(b'>')
+    .add(b'`');
+
 #[derive(Deserialize)]
 pub struct UriQuery {
     #[serde(rename = "ofs")]
     offset: Option<u64>,
     #[serde(rename = "h")]
     branch: Option<String>,
+    /// Only show commits committed at or after this unix timestamp
+    since: Option<i64>,
+    /// Only show commits committed at or before this unix timestamp
+    until: Option<i64>,
 }
 
 #[derive(Template)]
@@ -27,8 +49,69 @@ pub struct UriQuery {
 pub struct View {
     repo: Repository,
     commits: Vec<YokedCommit>,
-    next_offset: Option<u64>,
+    pagination: Pagination,
     branch: Option<String>,
+    since: Option<i64>,
+    until: Option<i64>,
+}
+
+impl View {
+    /// Link to the page of the log starting at `offset`, keeping the rest of the query.
+    fn page_href(&self, offset: u64) -> String {
+        let mut href = format!("?ofs={offset}");
+
+        if let Some(branch) = &self.branch {
+            write!(href, "&h={}", utf8_percent_encode(branch, QUERY_VALUE)).unwrap();
+        }
+        if let Some(since) = self.since {
+            write!(href, "&since={since}").unwrap();
+        }
+        if let Some(until) = self.until {
+            write!(href, "&until={until}").unwrap();
+        }
+
+        href
+    }
+}
+
+/// Where a page of the log sits within the branch's history.
+pub struct Pagination {
+    /// 1-based number of the current page
+    page: u64,
+    /// Number of pages in total, unknown when the log is filtered by time since the matching
+    /// commits aren't counted
+    pages: Option<u64>,
+    previous_offset: Option<u64>,
+    next_offset: Option<u64>,
+    /// Offset of the final page, if it isn't the current one
+    last_offset: Option<u64>,
+}
+
+impl Pagination {
+    /// Clamps the requested `offset` so it starts at most on the last page of `total` commits.
+    fn clamp_offset(offset: u64, page_size: u64, total: u64) -> u64 {
+        offset.min(Self::final_offset(page_size, total))
+    }
+
+    fn final_offset(page_size: u64, total: u64) -> u64 {
+        total.saturating_sub(1) / page_size * page_size
+    }
+
+    fn new(offset: u64, page_size: u64, total: Option<u64>, has_next: bool) -> Self {
+        // offsets that aren't a multiple of the page size still have a partial page before them
+        let page = offset.div_ceil(page_size) + 1;
+        let pages = total.map(|total| total.div_ceil(page_size).max(page));
+
+        Self {
+            page,
+            pages,
+            previous_offset: (offset > 0).then(|| offset.saturating_sub(page_size)),
+            next_offset: has_next.then_some(offset + page_size),
+            last_offset: total
+                .map(|total| Self::final_offset(page_size, total))
+                .filter(|last| *last > offset),
+        }
+    }
 }
 
 pub async fn handle(
@@ -37,70 +120,109 @@ pub async fn handle(
     Query(query): Query<UriQuery>,
 ) -> Result<impl IntoResponse> {
     tokio::task::spawn_blocking(move || {
-        let offset = query.offset.unwrap_or(0);
+        let page_size = crate::site().log_page_size;
+        let filtered = query.since.is_some() || query.until.is_some();
 
-        let repository = crate::database::schema::repository::Repository::open(&db, &*repo)?
-            .context("Repository does not exist")?;
-        let mut commits =
-            get_branch_commits(&repository, &db, query.branch.as_deref(), 101, offset)?;
+        let snapshot = db.snapshot();
+        let repository =
+            crate::database::schema::repository::Repository::open(&db, Some(&snapshot), &*repo)?
+                .context("Repository does not exist")?;
+        let commit_tree = find_commit_tree(&repository, &db, &snapshot, query.branch.as_deref())?;
 
-        let next_offset = if commits.len() == 101 {
-            commits.pop();
-            Some(offset + 100)
-        } else {
-            None
+        // the number of commits matching a time filter isn't known upfront, so only the
+        // unfiltered log can be clamped to its last page
+        let total = match &commit_tree {
+            Some(tree) if !filtered => Some(tree.len()?),
+            _ => None,
+        };
+        let offset = match total {
+            Some(total) => Pagination::clamp_offset(query.offset.unwrap_or(0), page_size, total),
+            None => query.offset.unwrap_or(0),
         };
 
+        // fetch one extra commit to find out whether there's another page
+        let mut commits = match &commit_tree {
+            Some(tree) if filtered => {
+                tree.fetch_between(query.since, query.until, page_size + 1, offset)?
+            }
+            Some(tree) => tree.fetch_latest(page_size + 1, offset)?,
+            None => Vec::new(),
+        };
+
+        let has_next = commits.len() as u64 > page_size;
+        if has_next {
+            commits.pop();
+        }
+
         Ok(into_response(View {
             repo,
             commits,
-            next_offset,
+            pagination: Pagination::new(offset, page_size, total, has_next),
             branch: query.branch,
+            since: query.since,
+            until: query.until,
         }))
     })
     .await
     .context("Failed to attach to tokio task")?
 }
 
-pub fn get_branch_commits(
+/// Finds the tree of the requested branch, falling back to a tag of the same name, or the
+/// default branch if none was requested.
+pub fn find_commit_tree<'a>(
     repository: &YokedRepository,
     database: &Arc<rocksdb::DB>,
+    snapshot: &'a Snapshot<'a>,
     branch: Option<&str>,
-    amount: u64,
-    offset: u64,
-) -> Result<Vec<YokedCommit>> {
-    if let Some(reference) = branch {
-        let commit_tree = repository
+) -> Result<Option<CommitTree<'a>>> {
+    let candidates: Vec<String> = if let Some(reference) = branch {
+        vec![
+            format!("refs/heads/{reference}"),
+            format!("refs/tags/{reference}"),
+        ]
+    } else {
+        repository
             .get()
-            .commit_tree(database.clone(), &format!("refs/heads/{reference}"));
-        let commit_tree = commit_tree.fetch_latest(amount, offset)?;
+            .default_branch
+            .as_deref()
+            .into_iter()
+            .chain(default_branches())
+            .map(ToString::to_string)
+            .collect()
+    };
 
-        if !commit_tree.is_empty() {
-            return Ok(commit_tree);
-        }
-
-        let tag_tree = repository
+    for reference in candidates {
+        let tree = repository
             .get()
-            .commit_tree(database.clone(), &format!("refs/tags/{reference}"));
-        let tag_tree = tag_tree.fetch_latest(amount, offset)?;
+            .commit_tree(database.clone(), Some(snapshot), &reference)?;
 
-        return Ok(tag_tree);
-    }
-
-    for branch in repository
-        .get()
-        .default_branch
-        .as_deref()
-        .into_iter()
-        .chain(DEFAULT_BRANCHES.into_iter())
-    {
-        let commit_tree = repository.get().commit_tree(database.clone(), branch);
-        let commits = commit_tree.fetch_latest(amount, offset)?;
-
-        if !commits.is_empty() {
-            return Ok(commits);
+        if tree.len()? > 0 {
+            return Ok(Some(tree));
         }
     }
 
-    Ok(vec![])
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Pagination, View};
+    use crate::methods::repo::Repository;
+
+    #[test]
+    fn page_links_encode_the_branch() {
+        let view = View {
+            repo: Repository("repo.git".into()),
+            commits: Vec::new(),
+            pagination: Pagination::new(0, 100, None, true),
+            branch: Some("fix/a&b#c d".to_string()),
+            since: None,
+            until: Some(1_700_000_000),
+        };
+
+        assert_eq!(
+            view.page_href(100),
+            "?ofs=100&h=fix/a%26b%23c%20d&until=1700000000"
+        );
+    }
 }
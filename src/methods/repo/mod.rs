@@ -1,10 +1,17 @@
 mod about;
+mod blob;
+pub mod cgit;
 mod commit;
 mod diff;
+pub mod dumb_http;
+mod empty;
 mod log;
+mod object;
 mod refs;
+pub mod reindex;
 mod smart_git;
 mod snapshot;
+mod stats;
 mod summary;
 mod tag;
 mod tree;
@@ -12,34 +19,50 @@ mod tree;
 use std::{
     collections::BTreeMap,
     ops::Deref,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
     sync::{Arc, LazyLock},
+    time::Duration,
 };
 
 use axum::{
     body::Body,
     handler::Handler,
-    http::{Request, StatusCode},
+    http::{header, Method, Request, StatusCode},
     response::{IntoResponse, Response},
 };
 use path_clean::PathClean;
+use percent_encoding::percent_decode_str;
+use serde::Deserialize;
+use tracing::error;
 
 use self::{
     about::handle as handle_about,
+    blob::handle as handle_blob,
     commit::handle as handle_commit,
     diff::{handle as handle_diff, handle_plain as handle_patch},
+    dumb_http::handle as handle_dumb_http,
+    empty::handle as handle_empty,
     log::handle as handle_log,
+    object::handle as handle_object,
     refs::handle as handle_refs,
+    reindex::handle as handle_reindex,
     smart_git::handle as handle_smart_git,
     snapshot::handle as handle_snapshot,
+    stats::handle as handle_stats,
     summary::handle as handle_summary,
     tag::handle as handle_tag,
     tree::handle as handle_tree,
 };
+use crate::database::indexer::IndexerConfig;
 use crate::database::schema::tag::YokedString;
 use crate::database::schema::{commit::YokedCommit, tag::YokedTag};
+use crate::git::{BadRequest, NotFound};
+use crate::methods::error::render as render_error;
 
-pub const DEFAULT_BRANCHES: [&str; 2] = ["refs/heads/master", "refs/heads/main"];
+/// Branches to fall back to, in order, when a repository doesn't have a default branch set.
+pub fn default_branches() -> impl Iterator<Item = &'static str> {
+    crate::site().default_branches.iter().map(AsRef::as_ref)
+}
 
 // this is some wicked, wicked abuse of axum right here...
 #[allow(clippy::trait_duplication_in_bounds)] // clippy seems a bit.. lost
@@ -49,11 +72,44 @@ pub async fn service(mut request: Request<Body>) -> Response {
         .get::<Arc<PathBuf>>()
         .expect("scan_path missing");
 
+    if request
+        .extensions()
+        .get::<cgit::CgitUrls>()
+        .is_some_and(|v| v.0)
+    {
+        if let Some(response) = cgit::redirect(request.uri()) {
+            return response;
+        }
+    }
+
+    let dumb_http = request
+        .extensions()
+        .get::<dumb_http::DumbHttp>()
+        .is_some_and(|v| v.0);
+
+    // repositories and paths within them can contain anything, so arrive percent-encoded
+    let Ok(request_path) = percent_decode_str(request.uri().path()).decode_utf8() else {
+        return RepositoryNotFound.into_response();
+    };
+    let request_path = request_path.into_owned();
+
     let ParsedUri {
         uri,
-        child_path,
-        action,
-    } = parse_uri(request.uri().path().trim_matches('/'));
+        mut child_path,
+        mut action,
+    } = parse_uri(request_path.trim_matches('/'), dumb_http);
+
+    if dumb_http
+        && action == HandlerAction::SmartGit
+        && dumb_http::is_dumb_refs_request(request.uri())
+    {
+        action = HandlerAction::DumbHttp;
+        child_path = Some(PathBuf::from("info/refs"));
+    }
+
+    if let Some(response) = canonicalise_trailing_slash(&request, action) {
+        return response;
+    }
 
     let uri = Path::new(uri).clean();
     let path = scan_path.join(&uri);
@@ -62,31 +118,120 @@ pub async fn service(mut request: Request<Body>) -> Response {
         .extensions()
         .get::<Arc<rocksdb::DB>>()
         .expect("db extension missing");
-    if path.as_os_str().is_empty()
-        || !crate::database::schema::repository::Repository::exists(db, &uri).unwrap_or_default()
-    {
+    // reindexing is allowed for repositories that haven't been picked up by the indexer yet, so
+    // newly pushed repositories can be indexed on demand. the handler checks the repository is
+    // there itself once the caller has authenticated
+    let (exists, is_empty) = if action == HandlerAction::Reindex {
+        (
+            uri.components().all(|c| matches!(c, Component::Normal(_))),
+            false,
+        )
+    } else {
+        match crate::database::schema::repository::Repository::open(db, None, &uri) {
+            Ok(Some(repository)) => (true, repository.get().empty),
+            _ => (false, false),
+        }
+    };
+
+    let excluded = request
+        .extensions()
+        .get::<Arc<IndexerConfig>>()
+        .is_some_and(|config| config.is_excluded(&uri));
+
+    if path.as_os_str().is_empty() || !exists || excluded {
         return RepositoryNotFound.into_response();
     }
 
-    request.extensions_mut().insert(ChildPath(child_path));
-    request.extensions_mut().insert(Repository(uri));
-    request.extensions_mut().insert(RepositoryPath(path));
+    let RequestTimeouts { browse, streaming } = *request
+        .extensions()
+        .get::<RequestTimeouts>()
+        .expect("request timeouts missing");
+    let timeout = if action.is_streaming() {
+        streaming
+    } else {
+        Some(browse)
+    };
 
-    match action {
-        HandlerAction::About => handle_about.call(request, None::<()>).await,
-        HandlerAction::SmartGit => handle_smart_git.call(request, None::<()>).await,
-        HandlerAction::Refs => handle_refs.call(request, None::<()>).await,
-        HandlerAction::Log => handle_log.call(request, None::<()>).await,
-        HandlerAction::Tree => handle_tree.call(request, None::<()>).await,
-        HandlerAction::Commit => handle_commit.call(request, None::<()>).await,
-        HandlerAction::Diff => handle_diff.call(request, None::<()>).await,
-        HandlerAction::Patch => handle_patch.call(request, None::<()>).await,
-        HandlerAction::Tag => handle_tag.call(request, None::<()>).await,
-        HandlerAction::Snapshot => handle_snapshot.call(request, None::<()>).await,
-        HandlerAction::Summary => handle_summary.call(request, None::<()>).await,
+    let handle = async move {
+        request.extensions_mut().insert(ChildPath(child_path));
+        request.extensions_mut().insert(Repository(uri));
+        request.extensions_mut().insert(RepositoryPath(path));
+
+        // there's nothing to browse in a repository without any commits, so rather than having
+        // every handler deal with an unborn HEAD we show the user how to push to it instead
+        if is_empty && action.requires_commits() {
+            return handle_empty.call(request, None::<()>).await;
+        }
+
+        match action {
+            HandlerAction::About => handle_about.call(request, None::<()>).await,
+            HandlerAction::SmartGit => handle_smart_git.call(request, None::<()>).await,
+            HandlerAction::DumbHttp => handle_dumb_http.call(request, None::<()>).await,
+            HandlerAction::Refs => handle_refs.call(request, None::<()>).await,
+            HandlerAction::Log => handle_log.call(request, None::<()>).await,
+            HandlerAction::Tree => handle_tree.call(request, None::<()>).await,
+            HandlerAction::Blob => handle_blob.call(request, None::<()>).await,
+            HandlerAction::Commit => handle_commit.call(request, None::<()>).await,
+            HandlerAction::Diff => handle_diff.call(request, None::<()>).await,
+            HandlerAction::Patch => handle_patch.call(request, None::<()>).await,
+            HandlerAction::Tag => handle_tag.call(request, None::<()>).await,
+            HandlerAction::Snapshot => handle_snapshot.call(request, None::<()>).await,
+            HandlerAction::Stats => handle_stats.call(request, None::<()>).await,
+            HandlerAction::Object => handle_object.call(request, None::<()>).await,
+            HandlerAction::Summary => handle_summary.call(request, None::<()>).await,
+            HandlerAction::Reindex => handle_reindex.call(request, None::<()>).await,
+        }
+    };
+
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, handle)
+            .await
+            .unwrap_or_else(|_| StatusCode::REQUEST_TIMEOUT.into_response()),
+        None => handle.await,
     }
 }
 
+/// Redirects browsers from a URL with a trailing slash to the one without, if enabled. Requests
+/// from git clients are left alone since they build their own URLs and may not follow
+/// redirects.
+fn canonicalise_trailing_slash(request: &Request<Body>, action: HandlerAction) -> Option<Response> {
+    let trailing_slash = request
+        .extensions()
+        .get::<TrailingSlash>()
+        .copied()
+        .unwrap_or_default();
+
+    let path = request.uri().path();
+
+    if trailing_slash != TrailingSlash::Strip
+        || action.is_git_protocol()
+        || !matches!(*request.method(), Method::GET | Method::HEAD)
+        || !path.ends_with('/')
+    {
+        return None;
+    }
+
+    // the path has already had the base path stripped off by the router
+    let base_path = &crate::site().base_path;
+    let path = path.trim_end_matches('/');
+    if path.is_empty() && base_path.is_empty() {
+        return None;
+    }
+
+    let location = match request.uri().query() {
+        Some(query) => format!("{base_path}{path}?{query}"),
+        None => format!("{base_path}{path}"),
+    };
+
+    Some(
+        (
+            StatusCode::MOVED_PERMANENTLY,
+            [(header::LOCATION, location)],
+        )
+            .into_response(),
+    )
+}
+
 #[derive(Debug, PartialEq, Eq)]
 struct ParsedUri<'a> {
     action: HandlerAction,
@@ -94,7 +239,10 @@ struct ParsedUri<'a> {
     child_path: Option<PathBuf>,
 }
 
-fn parse_uri(uri: &str) -> ParsedUri<'_> {
+/// Works out which handler a request is for. Paths that would otherwise be dumb HTTP files are
+/// only treated as such when `dumb_http` is enabled, so they don't shadow repositories with
+/// names like `HEAD` or `objects`.
+fn parse_uri(uri: &str, dumb_http: bool) -> ParsedUri<'_> {
     let mut uri_parts = memchr::memchr_iter(b'/', uri.as_bytes());
 
     let original_uri = uri;
@@ -173,9 +321,28 @@ fn parse_uri(uri: &str) -> ParsedUri<'_> {
             uri,
             child_path: None,
         },
-        Some(_) => {
+        Some("stats") => ParsedUri {
+            action: HandlerAction::Stats,
+            uri,
+            child_path: None,
+        },
+        Some("object") => ParsedUri {
+            action: HandlerAction::Object,
+            uri,
+            child_path: None,
+        },
+        Some("reindex") => ParsedUri {
+            action: HandlerAction::Reindex,
+            uri,
+            child_path: None,
+        },
+        Some(action) => {
             static TREE_FINDER: LazyLock<memchr::memmem::Finder> =
                 LazyLock::new(|| memchr::memmem::Finder::new(b"/tree/"));
+            static OBJECTS_FINDER: LazyLock<memchr::memmem::Finder> =
+                LazyLock::new(|| memchr::memmem::Finder::new(b"/objects/"));
+            static BLOB_FINDER: LazyLock<memchr::memmem::Finder> =
+                LazyLock::new(|| memchr::memmem::Finder::new(b"/blob/"));
 
             uri = original_uri;
 
@@ -187,6 +354,25 @@ fn parse_uri(uri: &str) -> ParsedUri<'_> {
                     // 6 is the length of /tree/
                     child_path: Some(Path::new(&uri[idx + 6..]).clean()),
                 }
+            } else if let Some(idx) = BLOB_FINDER.find(uri.as_bytes()) {
+                ParsedUri {
+                    action: HandlerAction::Blob,
+                    uri: &uri[..idx],
+                    // 6 is the length of /blob/
+                    child_path: Some(PathBuf::from(&uri[idx + 6..])),
+                }
+            } else if dumb_http && action == "HEAD" {
+                ParsedUri {
+                    action: HandlerAction::DumbHttp,
+                    uri: &uri[..uri.len() - "/HEAD".len()],
+                    child_path: Some(PathBuf::from("HEAD")),
+                }
+            } else if let Some(idx) = OBJECTS_FINDER.find(uri.as_bytes()).filter(|_| dumb_http) {
+                ParsedUri {
+                    action: HandlerAction::DumbHttp,
+                    uri: &uri[..idx],
+                    child_path: Some(PathBuf::from(&uri[idx + 1..])),
+                }
             } else {
                 ParsedUri {
                     action: HandlerAction::Summary,
@@ -207,15 +393,70 @@ fn parse_uri(uri: &str) -> ParsedUri<'_> {
 enum HandlerAction {
     About,
     SmartGit,
+    DumbHttp,
     Refs,
     Log,
     Tree,
+    Blob,
     Commit,
     Diff,
     Patch,
     Tag,
     Snapshot,
+    Stats,
+    Object,
     Summary,
+    Reindex,
+}
+
+impl HandlerAction {
+    /// Whether the handler streams potentially large responses (clones & archives), which
+    /// legitimately take longer than browsing.
+    fn is_streaming(self) -> bool {
+        matches!(self, Self::SmartGit | Self::DumbHttp | Self::Snapshot)
+    }
+
+    /// Whether the request comes from a git client rather than a browser.
+    fn is_git_protocol(self) -> bool {
+        matches!(self, Self::SmartGit | Self::DumbHttp | Self::Reindex)
+    }
+
+    /// Whether the handler needs the repository to have at least one commit to render anything
+    /// meaningful.
+    fn requires_commits(self) -> bool {
+        !matches!(
+            self,
+            Self::SmartGit | Self::DumbHttp | Self::Reindex | Self::Tag
+        )
+    }
+}
+
+/// How a trailing slash on a repository URL is treated, set via `--trailing-slash`.
+#[derive(clap::ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum TrailingSlash {
+    /// Permanently redirect to the URL without the trailing slash, so every page has a single
+    /// canonical URL
+    #[default]
+    Strip,
+    /// Serve the same page with or without the trailing slash
+    Ignore,
+}
+
+/// Timeouts applied to repository requests, set by `--request-timeout` for browsing and
+/// `--streaming-request-timeout` for clones & archives (which have no timeout if it's unset).
+#[derive(Clone, Copy)]
+pub struct RequestTimeouts {
+    pub browse: Duration,
+    pub streaming: Option<Duration>,
+}
+
+/// The representation a page is served in, requested via `?format=`.
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Html,
+    Json,
 }
 
 #[derive(Clone)]
@@ -249,7 +490,7 @@ pub struct InvalidRequest;
 
 impl IntoResponse for InvalidRequest {
     fn into_response(self) -> Response {
-        (StatusCode::NOT_FOUND, "Invalid request").into_response()
+        render_error(StatusCode::NOT_FOUND, "Invalid request", None)
     }
 }
 
@@ -257,7 +498,7 @@ pub struct RepositoryNotFound;
 
 impl IntoResponse for RepositoryNotFound {
     fn into_response(self) -> Response {
-        (StatusCode::NOT_FOUND, "Repository not found").into_response()
+        render_error(StatusCode::NOT_FOUND, "Repository not found", None)
     }
 }
 
@@ -265,6 +506,16 @@ pub struct Error(anyhow::Error);
 
 impl From<Arc<anyhow::Error>> for Error {
     fn from(e: Arc<anyhow::Error>) -> Self {
+        // errors out of the caches are shared so can't be moved out, keep hold of whether it
+        // was a lookup failure or a bad request so it's still surfaced as a 404 or 400
+        if let Some(not_found) = e.downcast_ref::<NotFound>() {
+            return Self(anyhow::Error::new(*not_found));
+        }
+
+        if let Some(bad_request) = e.downcast_ref::<BadRequest>() {
+            return Self(anyhow::Error::new(*bad_request));
+        }
+
         Self(anyhow::Error::msg(format!("{e:?}")))
     }
 }
@@ -277,11 +528,112 @@ impl From<anyhow::Error> for Error {
 
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
-        (StatusCode::INTERNAL_SERVER_ERROR, format!("{:?}", self.0)).into_response()
+        if let Some(NotFound(message)) = self.0.downcast_ref::<NotFound>() {
+            return render_error(StatusCode::NOT_FOUND, message, None);
+        }
+
+        if let Some(BadRequest(message)) = self.0.downcast_ref::<BadRequest>() {
+            return render_error(StatusCode::BAD_REQUEST, message, None);
+        }
+
+        error!(error = ?self.0, "Failed to handle request");
+
+        render_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Something went wrong whilst handling your request",
+            Some(format!("{:?}", self.0)),
+        )
     }
 }
 
 pub struct Refs {
-    heads: BTreeMap<String, YokedCommit>,
+    heads: BTreeMap<String, Head>,
     tags: Vec<(YokedString, YokedTag)>,
 }
+
+pub struct Head {
+    commit: YokedCommit,
+    /// Number of commits indexed for the branch, zero if the branch hasn't been indexed yet
+    commit_count: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::Arc, time::Duration};
+
+    use axum::{
+        body::Body,
+        http::{header, Request, StatusCode},
+        Extension, Router,
+    };
+    use tower::ServiceExt;
+
+    use super::{parse_uri, service, HandlerAction, ParsedUri, RequestTimeouts};
+    use crate::test_util::{self, init_site, Fixture};
+
+    #[test]
+    fn dumb_http_paths_are_only_routed_when_enabled() {
+        assert_eq!(
+            parse_uri("repo.git/objects/ab/cdef", true),
+            ParsedUri {
+                action: HandlerAction::DumbHttp,
+                uri: "repo.git",
+                child_path: Some(PathBuf::from("objects/ab/cdef")),
+            }
+        );
+        assert_eq!(
+            parse_uri("repo.git/HEAD", true),
+            ParsedUri {
+                action: HandlerAction::DumbHttp,
+                uri: "repo.git",
+                child_path: Some(PathBuf::from("HEAD")),
+            }
+        );
+
+        for uri in ["repo.git/objects/ab/cdef", "group/HEAD"] {
+            assert_eq!(
+                parse_uri(uri, false),
+                ParsedUri {
+                    action: HandlerAction::Summary,
+                    uri,
+                    child_path: None,
+                }
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn empty_repositories_are_detected_from_the_index() {
+        init_site();
+
+        let fixture = Fixture::new();
+        let (_db_dir, db) = test_util::open_db(fixture.scan_path());
+        test_util::index(fixture.scan_path(), &db);
+
+        // there's no git extension, so this would panic if the repository were opened to check
+        let app = Router::new()
+            .fallback(service)
+            .layer(Extension(db))
+            .layer(Extension(Arc::new(PathBuf::from(fixture.scan_path()))))
+            .layer(Extension(RequestTimeouts {
+                browse: Duration::from_secs(10),
+                streaming: None,
+            }));
+
+        let response = app
+            .oneshot(
+                Request::get("/repo.git")
+                    .header(header::HOST, "git.example.com")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("This repository is empty"));
+    }
+}
@@ -0,0 +1,50 @@
+use std::sync::Arc;
+
+use askama::Template;
+use axum::{extract::Query, response::IntoResponse, Extension, Json};
+use serde::Deserialize;
+
+use crate::{
+    git::ObjectInfo,
+    into_response,
+    methods::{
+        filters,
+        repo::{Format, Repository, RepositoryPath, Result},
+    },
+    Git, ResponseEither,
+};
+
+#[derive(Deserialize)]
+pub struct UriQuery {
+    /// The full or abbreviated id of the object to describe
+    id: String,
+    #[serde(default)]
+    format: Format,
+}
+
+#[derive(Template)]
+#[template(path = "repo/object.html")]
+pub struct View {
+    repo: Repository,
+    object: ObjectInfo,
+    branch: Option<Arc<str>>,
+}
+
+pub async fn handle(
+    Extension(repo): Extension<Repository>,
+    Extension(RepositoryPath(repository_path)): Extension<RepositoryPath>,
+    Extension(git): Extension<Arc<Git>>,
+    Query(query): Query<UriQuery>,
+) -> Result<impl IntoResponse> {
+    let open_repo = git.repo(repository_path, None).await?;
+    let object = open_repo.object_info(&query.id).await?;
+
+    Ok(match query.format {
+        Format::Json => ResponseEither::Left(Json(object)),
+        Format::Html => ResponseEither::Right(into_response(View {
+            repo,
+            object,
+            branch: None,
+        })),
+    })
+}
@@ -4,33 +4,52 @@ use crate::{
     into_response,
     methods::{
         filters,
-        repo::{Refs, Repository, Result},
+        repo::{Head, Refs, Repository, Result},
     },
 };
 use anyhow::Context;
 use askama::Template;
-use axum::{response::IntoResponse, Extension};
+use axum::{extract::Query, response::IntoResponse, Extension};
 use rkyv::string::ArchivedString;
+use serde::Deserialize;
 use yoke::Yoke;
 
+#[derive(Deserialize)]
+pub struct UriQuery {
+    /// Only show refs whose name contains this string, case-insensitively
+    q: Option<String>,
+}
+
 #[derive(Template)]
 #[template(path = "repo/refs.html")]
 pub struct View {
     repo: Repository,
     refs: Refs,
     branch: Option<Arc<str>>,
+    query: Option<String>,
 }
 
 pub async fn handle(
     Extension(repo): Extension<Repository>,
     Extension(db): Extension<Arc<rocksdb::DB>>,
+    Query(query): Query<UriQuery>,
 ) -> Result<impl IntoResponse> {
     tokio::task::spawn_blocking(move || {
-        let repository = crate::database::schema::repository::Repository::open(&db, &*repo)?
-            .context("Repository does not exist")?;
+        let query = query.q.filter(|v| !v.is_empty());
+        let needle = query.as_deref().map(str::to_lowercase);
+        let matches = |name: &str| {
+            needle
+                .as_deref()
+                .is_none_or(|needle| name.to_lowercase().contains(needle))
+        };
+
+        let snapshot = db.snapshot();
+        let repository =
+            crate::database::schema::repository::Repository::open(&db, Some(&snapshot), &*repo)?
+                .context("Repository does not exist")?;
         let repository = repository.get();
 
-        let heads_db = repository.heads(&db)?;
+        let heads_db = repository.heads(&db, Some(&snapshot))?;
         let heads_db = heads_db.as_ref().map(Yoke::get);
 
         let mut heads = BTreeMap::new();
@@ -41,21 +60,34 @@ pub async fn handle(
                 .iter()
                 .map(ArchivedString::as_str)
             {
-                let commit_tree = repository.commit_tree(db.clone(), head);
-                let name = head.strip_prefix("refs/heads/");
+                let commit_tree = repository.commit_tree(db.clone(), Some(&snapshot), head)?;
+                let name = head
+                    .strip_prefix("refs/heads/")
+                    .filter(|name| matches(name));
 
                 if let (Some(name), Some(commit)) = (name, commit_tree.fetch_latest_one()?) {
-                    heads.insert(name.to_string(), commit);
+                    let commit_count = commit_tree.len()?;
+                    heads.insert(
+                        name.to_string(),
+                        Head {
+                            commit,
+                            commit_count,
+                        },
+                    );
                 }
             }
         }
 
-        let tags = repository.tag_tree(db).fetch_all()?;
+        let mut tags = repository
+            .tag_tree(db.clone(), Some(&snapshot))
+            .fetch_all()?;
+        tags.retain(|(name, _)| matches(name.get()));
 
         Ok(into_response(View {
             repo,
             refs: Refs { heads, tags },
             branch: None,
+            query,
         }))
     })
     .await
@@ -0,0 +1,174 @@
+use std::{path::PathBuf, sync::Arc};
+
+use axum::{
+    http::{header, HeaderMap, Method, StatusCode},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use tracing::info;
+
+use crate::{
+    database::indexer::{IndexerConfig, ReindexQueue},
+    methods::repo::{Repository, RepositoryNotFound},
+};
+
+/// Token required in the `Authorization` header to trigger a reindex, the endpoint is disabled
+/// entirely if this isn't set.
+#[derive(Clone)]
+pub struct ReindexToken(pub Option<Arc<str>>);
+
+pub async fn handle(
+    Extension(Repository(repository)): Extension<Repository>,
+    Extension(ReindexToken(token)): Extension<ReindexToken>,
+    Extension(scan_path): Extension<Arc<PathBuf>>,
+    Extension(queue): Extension<ReindexQueue>,
+    Extension(config): Extension<Arc<IndexerConfig>>,
+    method: Method,
+    headers: HeaderMap,
+) -> Response {
+    let Some(token) = token else {
+        return (StatusCode::NOT_FOUND, "Reindexing is disabled").into_response();
+    };
+
+    if method != Method::POST {
+        return (
+            StatusCode::METHOD_NOT_ALLOWED,
+            "Reindex must be requested via POST",
+        )
+            .into_response();
+    }
+
+    let given_token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+
+    if !given_token.is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes())) {
+        return (StatusCode::UNAUTHORIZED, "Invalid reindex token").into_response();
+    }
+
+    // only checked once authenticated, so the endpoint can't be used to probe the filesystem
+    if !config.is_discoverable(&scan_path, &repository) {
+        return RepositoryNotFound.into_response();
+    }
+
+    info!(repository = %repository.display(), "Reindex requested");
+
+    // the reindex happens in the background so a large push doesn't run up against the request
+    // timeout, any errors end up in the server log
+    if !queue.push(repository) {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Too many reindexes already queued",
+        )
+            .into_response();
+    }
+
+    (StatusCode::ACCEPTED, "Reindex queued").into_response()
+}
+
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::PathBuf,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use axum::{
+        body::Body,
+        http::{header, Request, StatusCode},
+        Extension, Router,
+    };
+    use tower::ServiceExt;
+
+    use super::ReindexToken;
+    use crate::{
+        database::indexer::{IndexStatus, IndexerConfig, ReindexQueue},
+        methods::repo::{service, RequestTimeouts},
+        test_util::{init_site, open_db, Fixture},
+    };
+
+    async fn reindex(fixture: &Fixture, path: &str, token: Option<&str>) -> StatusCode {
+        init_site();
+
+        let (_db_dir, db) = open_db(fixture.scan_path());
+        let scan_path = Arc::new(PathBuf::from(fixture.scan_path()));
+        let config = Arc::new(IndexerConfig::default());
+        let queue = ReindexQueue::spawn(
+            PathBuf::from(fixture.scan_path()),
+            db.clone(),
+            Arc::new(Mutex::new(IndexStatus::default())),
+            config.clone(),
+        );
+
+        let app = Router::new()
+            .fallback(service)
+            .layer(Extension(ReindexToken(Some(Arc::from("secret")))))
+            .layer(Extension(queue))
+            .layer(Extension(config))
+            .layer(Extension(db))
+            .layer(Extension(scan_path))
+            .layer(Extension(RequestTimeouts {
+                browse: Duration::from_secs(10),
+                streaming: None,
+            }));
+
+        let mut request = Request::post(format!("/{path}/reindex"));
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+        }
+
+        app.oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn unauthenticated_requests_cant_probe_for_paths() {
+        let fixture = Fixture::new();
+
+        for path in ["repo.git", "missing.git"] {
+            assert_eq!(
+                reindex(&fixture, path, None).await,
+                StatusCode::UNAUTHORIZED,
+                "{path}"
+            );
+            assert_eq!(
+                reindex(&fixture, path, Some("wrong")).await,
+                StatusCode::UNAUTHORIZED,
+                "{path}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn only_discoverable_repositories_are_reindexed() {
+        let fixture = Fixture::new();
+        fixture.write("nested/file", "");
+
+        assert_eq!(
+            reindex(&fixture, "repo.git", Some("secret")).await,
+            StatusCode::ACCEPTED
+        );
+        assert_eq!(
+            reindex(&fixture, "missing.git", Some("secret")).await,
+            StatusCode::NOT_FOUND
+        );
+        let spaced = Fixture::named("my repo.git");
+        assert_eq!(
+            reindex(&spaced, "my%20repo.git", Some("secret")).await,
+            StatusCode::ACCEPTED
+        );
+        // a scan never looks inside another repository
+        assert_eq!(
+            reindex(&fixture, "repo.git/nested", Some("secret")).await,
+            StatusCode::NOT_FOUND
+        );
+    }
+}
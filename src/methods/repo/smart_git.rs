@@ -1,4 +1,11 @@
-use std::{io, io::ErrorKind, path::Path, process::Stdio, str::FromStr};
+use std::{
+    fmt::Write,
+    io,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process::Stdio,
+    str::FromStr,
+};
 
 use anyhow::{anyhow, Context};
 use axum::{
@@ -27,7 +34,6 @@ use crate::{
     StatusCode,
 };
 
-#[allow(clippy::unused_async)]
 pub async fn handle(
     Extension(RepositoryPath(repository_path)): Extension<RepositoryPath>,
     Extension(Repository(repository)): Extension<Repository>,
@@ -35,8 +41,38 @@ pub async fn handle(
     uri: Uri,
     headers: HeaderMap,
     body: Body,
-) -> Result<impl IntoResponse> {
-    let path = extract_path(&uri, &repository)?;
+) -> Response {
+    let service = GitService::from_uri(&uri);
+
+    match handle_inner(repository_path, repository, method, &uri, headers, body).await {
+        Ok(response) => response,
+        Err(error) => {
+            if let SmartGitError::Backend(error) = &error {
+                error!(?error, "Failed to serve git http-backend request");
+            }
+
+            error.into_git_response(service)
+        }
+    }
+}
+
+async fn handle_inner(
+    repository_path: PathBuf,
+    repository: PathBuf,
+    method: Method,
+    uri: &Uri,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response, SmartGitError> {
+    if !repository_path.is_dir() {
+        return Err(SmartGitError::RepositoryGone);
+    }
+
+    if !repository_path.join("git-daemon-export-ok").exists() {
+        return Err(SmartGitError::NotExported);
+    }
+
+    let path = extract_path(uri, &repository)?;
 
     let mut command = Command::new("git");
 
@@ -100,6 +136,13 @@ pub async fn handle(
         return Err(anyhow!("Received incomplete response from git http-backend").into());
     };
 
+    // `git http-backend` doesn't always give us a `Status` header when it fails, so anything
+    // that isn't a success is logged here with a generic error going back to the client
+    if headers.status().is_server_error() {
+        print_status(&mut child, &mut stderr).await;
+        return Err(anyhow!("git http-backend returned status code {}", headers.status()).into());
+    }
+
     // stream the response back to the client
     let (body_send, body_recv) = mpsc::channel(8);
     tokio::spawn(
@@ -107,7 +150,115 @@ pub async fn handle(
             .instrument(info_span!("git http-backend reader")),
     );
 
-    Ok((headers, Body::from_stream(ReceiverStream::new(body_recv))))
+    Ok((headers, Body::from_stream(ReceiverStream::new(body_recv))).into_response())
+}
+
+/// The git service the client is interacting with, used to determine how we should report
+/// errors back to the client.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum GitService {
+    /// Initial reference advertisement (`GET info/refs?service=git-upload-pack`)
+    UploadPackAdvertisement,
+    /// Negotiation and pack transfer (`POST git-upload-pack`)
+    UploadPack,
+    /// Anything else that's been routed to us, we'll just respond with plain HTTP errors
+    Other,
+}
+
+impl GitService {
+    fn from_uri(uri: &Uri) -> Self {
+        if uri.path().ends_with("/git-upload-pack") {
+            Self::UploadPack
+        } else if uri.path().ends_with("/info/refs")
+            && uri
+                .query()
+                .is_some_and(|q| q.split('&').any(|v| v == "service=git-upload-pack"))
+        {
+            Self::UploadPackAdvertisement
+        } else {
+            Self::Other
+        }
+    }
+
+    fn content_type(self) -> Option<&'static str> {
+        match self {
+            Self::UploadPackAdvertisement => Some("application/x-git-upload-pack-advertisement"),
+            Self::UploadPack => Some("application/x-git-upload-pack-result"),
+            Self::Other => None,
+        }
+    }
+}
+
+/// Errors that can occur whilst serving a smart git request.
+pub enum SmartGitError {
+    /// The repository exists in the index but has since disappeared from disk.
+    RepositoryGone,
+    /// The repository doesn't have a `git-daemon-export-ok` file, and hence shouldn't be
+    /// cloneable over HTTP.
+    NotExported,
+    /// `git http-backend` failed to serve our request, the details of this are only logged
+    /// server side to avoid leaking anything sensitive to the client.
+    Backend(anyhow::Error),
+}
+
+impl From<anyhow::Error> for SmartGitError {
+    fn from(e: anyhow::Error) -> Self {
+        Self::Backend(e)
+    }
+}
+
+impl From<crate::methods::repo::Error> for SmartGitError {
+    fn from(e: crate::methods::repo::Error) -> Self {
+        Self::Backend(e.0)
+    }
+}
+
+impl SmartGitError {
+    fn status(&self) -> StatusCode {
+        match self {
+            Self::RepositoryGone => StatusCode::NOT_FOUND,
+            Self::NotExported => StatusCode::FORBIDDEN,
+            Self::Backend(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn message(&self) -> &'static str {
+        match self {
+            Self::RepositoryGone => "Repository not found",
+            Self::NotExported => "Repository not exported",
+            Self::Backend(_) => "Internal error whilst serving repository, please try again later",
+        }
+    }
+
+    /// Builds a response the git client is able to understand.
+    ///
+    /// Git clients will only show the response body from a successful response, so internal
+    /// errors for upload-pack are sent as an `ERR` pkt-line with a `200` status, which makes
+    /// `git clone` print `remote error: ...` rather than a bare HTTP status code. Other failures
+    /// are mapped to their HTTP equivalents.
+    fn into_git_response(self, service: GitService) -> Response {
+        match (&self, service.content_type()) {
+            (Self::Backend(_), Some(content_type)) => {
+                let headers = [(
+                    axum::http::header::CONTENT_TYPE,
+                    HeaderValue::from_static(content_type),
+                )];
+
+                (headers, encode_pkt_line_error(self.message())).into_response()
+            }
+            _ => (self.status(), self.message()).into_response(),
+        }
+    }
+}
+
+/// Encodes `message` as a pkt-line `ERR` packet.
+fn encode_pkt_line_error(message: &str) -> String {
+    let payload = format!("ERR {message}\n");
+
+    let mut out = String::with_capacity(payload.len() + 4);
+    write!(out, "{:04x}", payload.len() + 4).unwrap();
+    out.push_str(&payload);
+    out
 }
 
 /// Forwards the entirety of `stdout` to `body_send`, printing subprocess stderr and status on
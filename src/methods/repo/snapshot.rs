@@ -1,7 +1,13 @@
-use std::sync::Arc;
+use std::{ffi::OsStr, path::Path, sync::Arc};
 
 use anyhow::{anyhow, Context};
-use axum::{body::Body, extract::Query, http::Response, Extension};
+use axum::{
+    body::Body,
+    extract::Query,
+    http::{Response, StatusCode},
+    response::IntoResponse,
+    Extension,
+};
 use serde::Deserialize;
 use tokio_stream::wrappers::ReceiverStream;
 use tracing::{error, info_span, Instrument};
@@ -14,6 +20,9 @@ pub struct UriQuery {
     #[serde(rename = "h")]
     branch: Option<Arc<str>>,
     id: Option<Arc<str>>,
+    /// Directory every file in the snapshot is nested under, defaults to `<repo>-<ref>/` like
+    /// release tarballs conventionally are
+    prefix: Option<String>,
 }
 
 pub async fn handle(
@@ -21,6 +30,25 @@ pub async fn handle(
     Extension(git): Extension<Arc<Git>>,
     Query(query): Query<UriQuery>,
 ) -> Result<Response<Body>> {
+    let reference = query
+        .id
+        .as_deref()
+        .or(query.branch.as_deref())
+        .unwrap_or("main");
+
+    let prefix = match query.prefix.as_deref() {
+        Some(prefix) => sanitise_prefix(prefix),
+        None => sanitise_prefix(&format!(
+            "{}-{}",
+            repository_name(&repository_path),
+            reference.replace('/', "-")
+        )),
+    };
+
+    let Some(prefix) = prefix else {
+        return Ok((StatusCode::BAD_REQUEST, "Invalid snapshot prefix").into_response());
+    };
+
     let open_repo = git.repo(repository_path, query.branch.clone()).await?;
 
     // byte stream back to the client
@@ -35,7 +63,7 @@ pub async fn handle(
     let res = tokio::spawn(
         async move {
             if let Err(error) = open_repo
-                .archive(send.clone(), send_cont, id.as_deref())
+                .archive(send.clone(), send_cont, id.as_deref(), prefix)
                 .await
             {
                 error!(%error, "Failed to build archive for client");
@@ -64,18 +92,44 @@ pub async fn handle(
         return Err(anyhow!("Ran into inconsistent error state whilst building archive, please file an issue at https://github.com/w4/rgit/issues").into());
     }
 
-    let file_name = query
-        .id
-        .as_deref()
-        .or(query.branch.as_deref())
-        .unwrap_or("main");
-
     Ok(Response::builder()
         .header("Content-Type", "application/gzip")
         .header(
             "Content-Disposition",
-            format!("attachment; filename=\"{file_name}.tar.gz\""),
+            format!("attachment; filename=\"{reference}.tar.gz\""),
         )
         .body(Body::from_stream(ReceiverStream::new(recv)))
         .context("failed to build response")?)
 }
+
+/// The name of the repository as `git archive` users would name it, without the `.git` suffix
+/// of a bare repository.
+fn repository_name(repository_path: &Path) -> &str {
+    let name = repository_path
+        .file_name()
+        .and_then(OsStr::to_str)
+        .unwrap_or("snapshot");
+    name.strip_suffix(".git").unwrap_or(name)
+}
+
+/// Normalises a user-provided prefix to either an empty string or a relative path ending in a
+/// `/`, refusing any prefix that could place files outside of the directory the snapshot is
+/// extracted to.
+fn sanitise_prefix(prefix: &str) -> Option<String> {
+    if prefix.starts_with('/') || prefix.contains('\\') {
+        return None;
+    }
+
+    let mut out = String::with_capacity(prefix.len() + 1);
+
+    for component in prefix.split('/').filter(|v| !v.is_empty()) {
+        if component == "." || component == ".." || component.contains('\0') {
+            return None;
+        }
+
+        out.push_str(component);
+        out.push('/');
+    }
+
+    Some(out)
+}
@@ -0,0 +1,122 @@
+use std::{fmt::Write, sync::Arc};
+
+use anyhow::Context;
+use askama::Template;
+use axum::{extract::Query, response::IntoResponse, Extension};
+use serde::Deserialize;
+
+use crate::{
+    database::schema::stats::Contributor,
+    into_response,
+    methods::{
+        filters,
+        repo::{log::find_commit_tree, Repository, Result},
+    },
+};
+
+/// Contributors beyond this are summarised rather than listed individually.
+const MAX_CONTRIBUTORS: usize = 50;
+
+#[derive(Deserialize)]
+pub struct UriQuery {
+    #[serde(rename = "h")]
+    branch: Option<Arc<str>>,
+}
+
+#[derive(Template)]
+#[template(path = "repo/stats.html")]
+pub struct View {
+    repo: Repository,
+    branch: Option<Arc<str>>,
+    contributors: Vec<Contributor>,
+    other_contributors: usize,
+    total_commits: u64,
+    recent_commits: u64,
+    first_week: Option<(i64, i32)>,
+    weeks: usize,
+    sparkline: String,
+}
+
+impl View {
+    /// The percentage of all commits made by a contributor.
+    #[allow(clippy::cast_precision_loss)]
+    fn share(&self, contributor: &Contributor) -> String {
+        format!(
+            "{:.1}",
+            contributor.commits as f64 / self.total_commits.max(1) as f64 * 100.0
+        )
+    }
+
+    /// Width of the sparkline's viewbox, which needs to be non-zero even with a single week.
+    fn sparkline_width(&self) -> usize {
+        self.weeks.saturating_sub(1).max(1)
+    }
+}
+
+pub async fn handle(
+    Extension(repo): Extension<Repository>,
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+    Query(query): Query<UriQuery>,
+) -> Result<impl IntoResponse> {
+    tokio::task::spawn_blocking(move || {
+        let snapshot = db.snapshot();
+        let repository =
+            crate::database::schema::repository::Repository::open(&db, Some(&snapshot), &*repo)?
+                .context("Repository does not exist")?;
+
+        let commit_tree = find_commit_tree(&repository, &db, &snapshot, query.branch.as_deref())?;
+
+        let (mut contributors, (first_week, activity)) = match commit_tree {
+            Some(tree) => (
+                tree.stats().contributors()?,
+                tree.stats().weekly_activity()?,
+            ),
+            None => (Vec::new(), (0, Vec::new())),
+        };
+
+        let total_commits = contributors.iter().map(|v| v.commits).sum();
+        let other_contributors = contributors.len().saturating_sub(MAX_CONTRIBUTORS);
+        contributors.truncate(MAX_CONTRIBUTORS);
+
+        Ok(into_response(View {
+            repo,
+            branch: query.branch,
+            contributors,
+            other_contributors,
+            total_commits,
+            recent_commits: activity.iter().sum(),
+            first_week: (!activity.is_empty()).then_some((first_week, 0)),
+            weeks: activity.len(),
+            sparkline: sparkline_points(&activity),
+        }))
+    })
+    .await
+    .context("Failed to attach to tokio task")?
+}
+
+/// Builds the points of an SVG polyline plotting the weekly commit counts, in a viewbox one unit
+/// wide per week and 100 units tall.
+#[allow(clippy::cast_precision_loss)]
+fn sparkline_points(activity: &[u64]) -> String {
+    let max = activity.iter().copied().max().unwrap_or_default().max(1) as f64;
+
+    let mut out = String::with_capacity(activity.len() * 8);
+    for (week, commits) in activity.iter().enumerate() {
+        let height = 100.0 - (*commits as f64 / max * 100.0);
+        write!(out, "{week},{height:.1} ").unwrap();
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sparkline_points;
+
+    #[test]
+    fn sparkline_is_scaled_to_the_busiest_week() {
+        assert_eq!(sparkline_points(&[0, 5, 10]), "0,100.0 1,50.0 2,0.0 ");
+        assert_eq!(sparkline_points(&[0, 0]), "0,100.0 1,100.0 ");
+        assert_eq!(sparkline_points(&[]), "");
+    }
+}
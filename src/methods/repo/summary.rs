@@ -7,11 +7,15 @@ use axum_extra::extract::Host;
 use rkyv::string::ArchivedString;
 
 use crate::{
-    database::schema::{commit::YokedCommit, repository::YokedRepository},
+    database::schema::{
+        commit::YokedCommit,
+        repository::{ArchivedLanguageBreakdown, YokedRepository},
+        Snapshot,
+    },
     into_response,
     methods::{
         filters,
-        repo::{Refs, Repository, Result, DEFAULT_BRANCHES},
+        repo::{default_branches, Head, Refs, Repository, Result},
     },
 };
 
@@ -24,6 +28,16 @@ pub struct View {
     branch: Option<Arc<str>>,
     exported: bool,
     host: String,
+    description: Option<String>,
+    summary_commits: usize,
+    languages: Vec<LanguageShare>,
+}
+
+/// A segment of the language bar.
+pub struct LanguageShare {
+    name: String,
+    percentage: String,
+    colour: String,
 }
 
 pub async fn handle(
@@ -32,12 +46,18 @@ pub async fn handle(
     Host(host): Host,
 ) -> Result<impl IntoResponse> {
     tokio::task::spawn_blocking(move || {
-        let repository = crate::database::schema::repository::Repository::open(&db, &*repo)?
-            .context("Repository does not exist")?;
-        let commits = get_default_branch_commits(&repository, &db)?;
+        // everything on the page is read from one snapshot, so the heads listed can't refer to
+        // commits the indexer has dropped since they were read
+        let snapshot = db.snapshot();
+        let repository =
+            crate::database::schema::repository::Repository::open(&db, Some(&snapshot), &*repo)?
+                .context("Repository does not exist")?;
+        let summary_commits = crate::site().summary_commits;
+        // fetch one extra commit to find out whether to link to the rest of the log
+        let commits = get_default_branch_commits(&repository, &db, &snapshot, summary_commits + 1)?;
 
         let mut heads = BTreeMap::new();
-        if let Some(heads_db) = repository.get().heads(&db)? {
+        if let Some(heads_db) = repository.get().heads(&db, Some(&snapshot))? {
             for head in heads_db
                 .get()
                 .0
@@ -45,16 +65,29 @@ pub async fn handle(
                 .iter()
                 .map(ArchivedString::as_str)
             {
-                let commit_tree = repository.get().commit_tree(db.clone(), head);
+                let commit_tree =
+                    repository
+                        .get()
+                        .commit_tree(db.clone(), Some(&snapshot), head)?;
                 let name = head.strip_prefix("refs/heads/");
 
                 if let (Some(name), Some(commit)) = (name, commit_tree.fetch_latest_one()?) {
-                    heads.insert(name.to_string(), commit);
+                    let commit_count = commit_tree.len()?;
+                    heads.insert(
+                        name.to_string(),
+                        Head {
+                            commit,
+                            commit_count,
+                        },
+                    );
                 }
             }
         }
 
-        let tags = repository.get().tag_tree(db).fetch_all()?;
+        let tags = repository
+            .get()
+            .tag_tree(db.clone(), Some(&snapshot))
+            .fetch_all()?;
 
         Ok(into_response(View {
             repo,
@@ -63,25 +96,106 @@ pub async fn handle(
             branch: None,
             exported: repository.get().exported,
             host,
+            description: repository
+                .get()
+                .description
+                .as_deref()
+                .map(ToString::to_string),
+            summary_commits: usize::try_from(summary_commits).unwrap_or(usize::MAX),
+            languages: repository
+                .get()
+                .languages
+                .as_ref()
+                .map(language_shares)
+                .unwrap_or_default(),
         }))
     })
     .await
     .context("Failed to attach to tokio task")?
 }
 
+#[allow(clippy::cast_precision_loss)]
+fn language_shares(breakdown: &ArchivedLanguageBreakdown) -> Vec<LanguageShare> {
+    let other_bytes = breakdown.other_bytes.to_native();
+    let total = breakdown
+        .languages
+        .iter()
+        .map(|v| v.bytes.to_native())
+        .sum::<u64>()
+        + other_bytes;
+
+    if total == 0 {
+        return Vec::new();
+    }
+
+    let percentage = |bytes: u64| format!("{:.1}", bytes as f64 / total as f64 * 100.0);
+
+    breakdown
+        .languages
+        .iter()
+        .map(|v| LanguageShare {
+            name: v.name.to_string(),
+            percentage: percentage(v.bytes.to_native()),
+            colour: language_colour(&v.name),
+        })
+        .chain((other_bytes > 0).then(|| LanguageShare {
+            name: "other".to_string(),
+            percentage: percentage(other_bytes),
+            colour: "#bbb".to_string(),
+        }))
+        .collect()
+}
+
+/// Uses the colours GitHub uses for the most common languages so the bar is familiar, and
+/// derives a stable colour from the name for the rest.
+fn language_colour(name: &str) -> String {
+    let colour = match name {
+        "bash" => "#89e051",
+        "c" => "#555555",
+        "c-sharp" => "#178600",
+        "cpp" => "#f34b7d",
+        "css" => "#563d7c",
+        "go" => "#00add8",
+        "haskell" => "#5e5086",
+        "html" => "#e34c26",
+        "java" => "#b07219",
+        "javascript" => "#f1e05a",
+        "kotlin" => "#a97bff",
+        "lua" => "#000080",
+        "nix" => "#7e7eff",
+        "php" => "#4f5d95",
+        "python" => "#3572a5",
+        "ruby" => "#701516",
+        "rust" => "#dea584",
+        "scss" => "#c6538c",
+        "swift" => "#f05138",
+        "typescript" | "tsx" => "#3178c6",
+        _ => {
+            let hue = xxhash_rust::const_xxh3::xxh3_64(name.as_bytes()) % 360;
+            return format!("hsl({hue}, 55%, 55%)");
+        }
+    };
+
+    colour.to_string()
+}
+
 pub fn get_default_branch_commits(
     repository: &YokedRepository,
     database: &Arc<rocksdb::DB>,
+    snapshot: &Snapshot<'_>,
+    amount: u64,
 ) -> Result<Vec<YokedCommit>> {
     for branch in repository
         .get()
         .default_branch
         .as_deref()
         .into_iter()
-        .chain(DEFAULT_BRANCHES.into_iter())
+        .chain(default_branches())
     {
-        let commit_tree = repository.get().commit_tree(database.clone(), branch);
-        let commits = commit_tree.fetch_latest(11, 0)?;
+        let commit_tree = repository
+            .get()
+            .commit_tree(database.clone(), Some(snapshot), branch)?;
+        let commits = commit_tree.fetch_latest(amount, 0)?;
 
         if !commits.is_empty() {
             return Ok(commits);
@@ -90,3 +204,48 @@ pub fn get_default_branch_commits(
 
     Ok(vec![])
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::{response::IntoResponse, Extension};
+    use axum_extra::extract::Host;
+
+    use super::handle;
+    use crate::{
+        methods::repo::Repository,
+        test_util::{self, init_site, Fixture},
+    };
+
+    #[tokio::test]
+    async fn configured_fallback_branches_are_summarised() {
+        // the test site falls back to master, main and then develop
+        init_site();
+
+        let mut fixture = Fixture::new();
+        fixture.git(&["checkout", "-q", "-b", "develop"]);
+        fixture.commit("work in progress");
+        // leave HEAD pointing at an unborn main, so only the fallback list can find develop
+        fixture.git(&["symbolic-ref", "HEAD", "refs/heads/main"]);
+
+        let (_db_dir, db) = test_util::open_db(fixture.scan_path());
+        test_util::index(fixture.scan_path(), &db);
+
+        let Ok(response) = handle(
+            Extension(Repository(fixture.relative_path().to_path_buf())),
+            Extension(db),
+            Host("git.example.com".to_string()),
+        )
+        .await
+        else {
+            panic!("summary wasn't rendered");
+        };
+
+        let body = axum::body::to_bytes(response.into_response().into_body(), usize::MAX)
+            .await
+            .unwrap();
+        // once in the branch list and once in the recent commits, which is only filled in when
+        // develop is found as a fallback
+        let body = String::from_utf8_lossy(&body);
+        assert_eq!(body.matches("work in progress").count(), 2, "{body}");
+    }
+}
@@ -6,7 +6,7 @@ use serde::Deserialize;
 use yoke::Yoke;
 
 use crate::{
-    git::DetailedTag,
+    git::{DetailedTag, SignatureStatus, TagKind},
     into_response,
     methods::{
         filters,
@@ -27,6 +27,7 @@ pub struct View {
     repo: Repository,
     tag: Yoke<DetailedTag<'static>, Vec<u8>>,
     branch: Option<Arc<str>>,
+    signature: Option<SignatureStatus>,
 }
 
 pub async fn handle(
@@ -36,11 +37,19 @@ pub async fn handle(
     Query(query): Query<UriQuery>,
 ) -> Result<impl IntoResponse> {
     let open_repo = git.repo(repository_path, Some(query.name.clone())).await?;
-    let tag = open_repo.tag_info().await?;
+    let tag = open_repo.clone().tag_info().await?;
+
+    // lightweight tags have no signature of their own
+    let signature = if tag.get().kind == TagKind::Annotated {
+        open_repo.signature_status(tag.get().id).await?
+    } else {
+        None
+    };
 
     Ok(into_response(View {
         repo,
         tag,
         branch: Some(query.name),
+        signature,
     }))
 }
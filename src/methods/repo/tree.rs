@@ -1,19 +1,26 @@
 use askama::Template;
-use axum::{extract::Query, response::IntoResponse, Extension};
+use axum::{
+    extract::Query,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use gix::ObjectId;
 use itertools::Itertools;
-use serde::Deserialize;
-use std::path::PathBuf;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
 use std::{
     fmt::{Display, Formatter},
     sync::Arc,
 };
 
 use crate::{
-    git::{FileWithContent, PathDestination, TreeItem},
+    git::{Content, FileWithContent, PathDestination, TreeItem},
     into_response,
     methods::{
         filters,
-        repo::{ChildPath, Repository, RepositoryPath, Result},
+        repo::{ChildPath, Format, Repository, RepositoryPath, Result},
     },
     Git, ResponseEither,
 };
@@ -23,8 +30,14 @@ pub struct UriQuery {
     id: Option<String>,
     #[serde(default)]
     raw: bool,
+    /// Serve the file as an attachment rather than inline
+    #[serde(default)]
+    download: bool,
     #[serde(rename = "h")]
     branch: Option<Arc<str>>,
+    /// Serve directory listings as JSON rather than HTML
+    #[serde(default)]
+    format: Format,
 }
 
 impl Display for UriQuery {
@@ -53,6 +66,105 @@ pub struct TreeView {
     pub query: UriQuery,
     pub repo_path: PathBuf,
     pub branch: Option<Arc<str>>,
+    /// Commit (or tree) the view was resolved from, for linking to it immutably
+    pub permalink_id: ObjectId,
+}
+
+/// Characters that can't appear as-is in a URL path. `/` is left alone, since the repository
+/// and file paths substituted into editor links are nested in the template's own path.
+const EDITOR_LINK_PATH: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+impl FileView {
+    /// Link to open the file in a local editor, built from the `--editor-link` template.
+    fn editor_link(&self) -> Option<String> {
+        let template = crate::site().editor_link.as_deref()?;
+        Some(editor_link(template, &self.repo, &self.repo_path))
+    }
+}
+
+/// Fills in an `--editor-link` template for the file at `path` within `repo`. Files aren't linked
+/// to individual lines, so `{line}` is always the first.
+fn editor_link(template: &str, repo: &Path, path: &Path) -> String {
+    let repo = repo.to_string_lossy();
+    let repo = repo.strip_suffix(".git").unwrap_or(&repo);
+
+    template
+        .replace(
+            "{repo}",
+            &utf8_percent_encode(repo, EDITOR_LINK_PATH).to_string(),
+        )
+        .replace(
+            "{path}",
+            &utf8_percent_encode(&path.to_string_lossy(), EDITOR_LINK_PATH).to_string(),
+        )
+        .replace("{line}", "1")
+}
+
+/// A child of a directory listing served with `?format=json`.
+#[derive(Serialize)]
+pub struct TreeEntry {
+    name: String,
+    path: PathBuf,
+    mode: String,
+    kind: TreeEntryKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    oid: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TreeEntryKind {
+    File,
+    Dir,
+    Submodule,
+}
+
+impl From<TreeItem> for TreeEntry {
+    fn from(item: TreeItem) -> Self {
+        match item {
+            TreeItem::Tree(tree) => Self {
+                name: tree.name,
+                path: tree.path,
+                mode: format!("{:o}", tree.mode),
+                kind: TreeEntryKind::Dir,
+                size: None,
+                url: None,
+                oid: None,
+            },
+            TreeItem::File(file) => Self {
+                name: file.name,
+                path: file.path,
+                mode: format!("{:o}", file.mode),
+                kind: TreeEntryKind::File,
+                size: Some(file.size),
+                url: None,
+                oid: None,
+            },
+            TreeItem::Submodule(submodule) => Self {
+                name: submodule.name,
+                path: submodule.path,
+                mode: format!("{:o}", submodule.mode),
+                kind: TreeEntryKind::Submodule,
+                size: None,
+                url: Some(submodule.url.to_string()),
+                oid: Some(submodule.oid.to_string()),
+            },
+        }
+    }
 }
 
 #[derive(Template)]
@@ -62,6 +174,8 @@ pub struct FileView {
     pub repo_path: PathBuf,
     pub file: FileWithContent,
     pub branch: Option<Arc<str>>,
+    /// Commit (or tree) the view was resolved from, for linking to it immutably
+    pub permalink_id: ObjectId,
 }
 
 pub async fn handle(
@@ -70,32 +184,99 @@ pub async fn handle(
     Extension(ChildPath(child_path)): Extension<ChildPath>,
     Extension(git): Extension<Arc<Git>>,
     Query(query): Query<UriQuery>,
+    headers: HeaderMap,
 ) -> Result<impl IntoResponse> {
     let open_repo = git.repo(repository_path, query.branch.clone()).await?;
+    let raw = query.raw || query.download;
+
+    let (destination, permalink_id) = open_repo
+        .path(child_path.clone(), query.id.as_deref(), !raw)
+        .await?;
+
+    Ok(match destination {
+        PathDestination::Tree(items) if query.format == Format::Json => ResponseEither::Right(
+            Json(items.into_iter().map(TreeEntry::from).collect::<Vec<_>>()).into_response(),
+        ),
+        PathDestination::Tree(items) => {
+            ResponseEither::Left(ResponseEither::Left(into_response(TreeView {
+                repo,
+                items,
+                branch: query.branch.clone(),
+                query,
+                repo_path: child_path.unwrap_or_default(),
+                permalink_id,
+            })))
+        }
+        PathDestination::File(file) if raw => {
+            ResponseEither::Right(raw_file(file, query.download, &headers))
+        }
+        PathDestination::File(file) => {
+            ResponseEither::Left(ResponseEither::Right(into_response(FileView {
+                repo,
+                file,
+                branch: query.branch,
+                repo_path: child_path.unwrap_or_default(),
+                permalink_id,
+            })))
+        }
+    })
+}
+
+/// Serves the bytes of a file as-is, tagged with the blob id so clients can revalidate cheaply.
+fn raw_file(file: FileWithContent, download: bool, headers: &HeaderMap) -> Response {
+    let mut response = raw_blob(file.id, file.content, headers);
 
-    Ok(
-        match open_repo
-            .path(child_path.clone(), query.id.as_deref(), !query.raw)
-            .await?
+    if download {
+        let name = file.metadata.name.replace(['"', '\\', '\r', '\n'], "_");
+
+        if let Ok(disposition) = HeaderValue::from_str(&format!("attachment; filename=\"{name}\""))
         {
-            PathDestination::Tree(items) => {
-                ResponseEither::Left(ResponseEither::Left(into_response(TreeView {
-                    repo,
-                    items,
-                    branch: query.branch.clone(),
-                    query,
-                    repo_path: child_path.unwrap_or_default(),
-                })))
-            }
-            PathDestination::File(file) if query.raw => ResponseEither::Right(file.content),
-            PathDestination::File(file) => {
-                ResponseEither::Left(ResponseEither::Right(into_response(FileView {
-                    repo,
-                    file,
-                    branch: query.branch,
-                    repo_path: child_path.unwrap_or_default(),
-                })))
-            }
-        },
-    )
+            response
+                .headers_mut()
+                .insert(header::CONTENT_DISPOSITION, disposition);
+        }
+    }
+
+    response
+}
+
+/// Serves the content of a blob with its id as the `ETag`, answering conditional requests for
+/// the same blob without sending the content again.
+pub(super) fn raw_blob(id: ObjectId, content: Content, headers: &HeaderMap) -> Response {
+    let etag = format!("\"{id}\"");
+
+    let mut response = if headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.split(',').any(|v| v.trim() == etag))
+    {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        content.into_response()
+    };
+
+    if let Ok(etag) = HeaderValue::from_str(&etag) {
+        response.headers_mut().insert(header::ETAG, etag);
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::editor_link;
+
+    #[test]
+    fn editor_links_are_encoded() {
+        assert_eq!(
+            editor_link(
+                "vscode://file/src/{repo}/{path}:{line}",
+                Path::new("group/my repo.git"),
+                Path::new("docs/what?#100%.md"),
+            ),
+            "vscode://file/src/group/my%20repo/docs/what%3F%23100%25.md:1"
+        );
+    }
 }
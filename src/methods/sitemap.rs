@@ -0,0 +1,80 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use askama::Template;
+use axum::{
+    extract::Query,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use axum_extra::extract::Host;
+use serde::Deserialize;
+
+use super::filters;
+use crate::{
+    database::schema::repository::{ArchivedRepository, Repository},
+    into_response,
+};
+
+/// Number of repositories listed in each sitemap, each one has a few URLs listed so this keeps
+/// well under the 50,000 URL limit imposed on a single sitemap.
+const REPOSITORIES_PER_SITEMAP: usize = 10_000;
+
+#[derive(Deserialize)]
+pub struct UriQuery {
+    page: Option<usize>,
+}
+
+#[derive(Template)]
+#[template(path = "sitemap.xml")]
+pub struct View<'a> {
+    host: String,
+    repositories: &'a [(&'a String, &'a ArchivedRepository)],
+}
+
+#[derive(Template)]
+#[template(path = "sitemap_index.xml")]
+pub struct IndexView {
+    host: String,
+    pages: usize,
+}
+
+pub async fn handle(
+    Extension(db): Extension<Arc<rocksdb::DB>>,
+    Host(host): Host,
+    Query(query): Query<UriQuery>,
+) -> Result<Response, super::repo::Error> {
+    let fetched = tokio::task::spawn_blocking(move || Repository::fetch_all(&db))
+        .await
+        .context("Failed to join Tokio task")??;
+
+    // repositories that can't be cloned aren't meant to be public
+    let repositories = fetched
+        .iter()
+        .map(|(path, repository)| (path, repository.get()))
+        .filter(|(_, repository)| repository.exported)
+        .collect::<Vec<_>>();
+
+    // large instances get split across multiple sitemaps, with `/sitemap.xml` listing them all
+    let chunks = repositories
+        .chunks(REPOSITORIES_PER_SITEMAP)
+        .collect::<Vec<_>>();
+
+    let repositories = match query.page {
+        None if chunks.len() > 1 => {
+            return Ok(into_response(IndexView {
+                host,
+                pages: chunks.len(),
+            })
+            .into_response());
+        }
+        None => chunks.first().copied().unwrap_or_default(),
+        Some(page) => match page.checked_sub(1).and_then(|i| chunks.get(i).copied()) {
+            Some(chunk) => chunk,
+            None => return Ok((StatusCode::NOT_FOUND, "Sitemap not found").into_response()),
+        },
+    };
+
+    Ok(into_response(View { host, repositories }).into_response())
+}
@@ -0,0 +1,154 @@
+//! Client side of `POST /<repo>/reindex`, used by the `notify-reindex` subcommand so operators
+//! don't need to hand-write requests in their `post-receive` hooks.
+
+use std::{net::SocketAddr, path::PathBuf};
+
+use anyhow::{bail, Context};
+use httparse::Status;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct NotifyReindexArgs {
+    /// The socket address the running rgit instance is bound to (eg. 127.0.0.1:3333)
+    #[clap(long)]
+    address: SocketAddr,
+    /// The token the running rgit instance was given via `--reindex-token`. Prefer passing this
+    /// via the environment or `--token-file`, arguments are visible to every user on the host
+    #[clap(long, env = "RGIT_REINDEX_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+    /// Path to a file containing the token, used if `--token` isn't given
+    #[clap(long)]
+    token_file: Option<PathBuf>,
+    /// The path the running rgit instance is scanning for repositories
+    #[clap(long)]
+    scan_path: PathBuf,
+    /// The path the running rgit instance was given via `--base-path`, if any
+    #[clap(long, default_value = "")]
+    base_path: String,
+    /// Path to the repository to reindex, defaults to `$GIT_DIR` or the current directory, which
+    /// is what git sets when running hooks
+    #[clap(long)]
+    repository: Option<PathBuf>,
+}
+
+/// Characters that can't appear as-is in a path segment of the request line.
+const PATH_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+pub async fn run(args: NotifyReindexArgs) -> anyhow::Result<()> {
+    let token = match (args.token, args.token_file) {
+        (Some(token), _) => token,
+        (None, Some(path)) => std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read token from {}", path.display()))?
+            .trim()
+            .to_string(),
+        (None, None) => bail!("A token must be given via --token-file or RGIT_REINDEX_TOKEN"),
+    };
+
+    let repository = match args.repository {
+        Some(v) => v,
+        None => std::env::var_os("GIT_DIR")
+            .map(PathBuf::from)
+            .map_or_else(std::env::current_dir, Ok)
+            .context("Failed to determine repository path")?,
+    };
+
+    let repository = repository
+        .canonicalize()
+        .with_context(|| format!("Failed to resolve repository {}", repository.display()))?;
+    let scan_path = args
+        .scan_path
+        .canonicalize()
+        .with_context(|| format!("Failed to resolve scan path {}", args.scan_path.display()))?;
+
+    let relative = repository
+        .strip_prefix(&scan_path)
+        .context("Repository is not within the scan path")?
+        .to_str()
+        .context("Repository path contains invalid bytes")?;
+    let request_path = request_path(&args.base_path, relative);
+
+    let mut stream = TcpStream::connect(args.address)
+        .await
+        .with_context(|| format!("Failed to connect to rgit at {}", args.address))?;
+
+    stream
+        .write_all(
+            format!(
+                "POST {request_path} HTTP/1.1\r\n\
+                 Host: {address}\r\n\
+                 Authorization: Bearer {token}\r\n\
+                 Content-Length: 0\r\n\
+                 Connection: close\r\n\r\n",
+                address = args.address,
+            )
+            .as_bytes(),
+        )
+        .await
+        .context("Failed to send reindex request")?;
+
+    let mut response = Vec::new();
+    stream
+        .read_to_end(&mut response)
+        .await
+        .context("Failed to read reindex response")?;
+
+    let mut headers = [httparse::EMPTY_HEADER; 16];
+    let mut parsed = httparse::Response::new(&mut headers);
+    let Status::Complete(body_offset) = parsed
+        .parse(&response)
+        .context("Received invalid response from rgit")?
+    else {
+        bail!("Received incomplete response from rgit");
+    };
+
+    let status = parsed.code.unwrap_or_default();
+    let body = String::from_utf8_lossy(&response[body_offset..]);
+
+    if !(200..300).contains(&status) {
+        bail!("rgit refused to reindex {relative} ({status}): {body}");
+    }
+
+    println!("rgit: {body}");
+
+    Ok(())
+}
+
+/// Builds the path of the reindex endpoint for the repository at `relative` to the scan path.
+fn request_path(base_path: &str, relative: &str) -> String {
+    let mut out = base_path.trim_end_matches('/').to_string();
+    for segment in relative.split('/') {
+        out.push('/');
+        out.extend(utf8_percent_encode(segment, PATH_SEGMENT));
+    }
+    out.push_str("/reindex");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::request_path;
+
+    #[test]
+    fn repository_path_is_encoded() {
+        assert_eq!(request_path("", "repo.git"), "/repo.git/reindex");
+        assert_eq!(
+            request_path("/git/", "group/my repo#1?.git"),
+            "/git/group/my%20repo%231%3F.git/reindex"
+        );
+    }
+}
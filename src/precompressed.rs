@@ -0,0 +1,84 @@
+//! Static assets held alongside gzip & brotli compressed copies of themselves, so they can be
+//! served to clients that accept them without compressing the same bytes on every request.
+
+use std::io::Write;
+
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, HeaderValue},
+    response::Response,
+};
+
+#[derive(Copy, Clone)]
+pub struct PrecompressedAsset {
+    pub raw: &'static [u8],
+    pub gzip: &'static [u8],
+    pub brotli: &'static [u8],
+}
+
+impl PrecompressedAsset {
+    /// Compresses an asset that's only known at runtime, leaking the variants as they're
+    /// expected to live for the rest of the program.
+    #[must_use]
+    pub fn compress(raw: &'static [u8]) -> Self {
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        gzip.write_all(raw).expect("write to vec can't fail");
+        let gzip = gzip.finish().expect("write to vec can't fail");
+
+        let mut brotli = brotli::CompressorWriter::new(Vec::new(), 4096, 11, 22);
+        brotli.write_all(raw).expect("write to vec can't fail");
+        let brotli = brotli.into_inner();
+
+        Self {
+            raw,
+            gzip: Box::leak(gzip.into_boxed_slice()),
+            brotli: Box::leak(brotli.into_boxed_slice()),
+        }
+    }
+
+    /// Responds with the smallest variant the client accepts, falling back to the uncompressed
+    /// asset.
+    #[must_use]
+    pub fn respond(&self, request_headers: &HeaderMap, content_type: &'static str) -> Response {
+        let (content, encoding) = if accepts_encoding(request_headers, "br") {
+            (self.brotli, Some("br"))
+        } else if accepts_encoding(request_headers, "gzip") {
+            (self.gzip, Some("gzip"))
+        } else {
+            (self.raw, None)
+        };
+
+        let mut resp = Response::new(Body::from(content));
+        let headers = resp.headers_mut();
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+        headers.insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+
+        if let Some(encoding) = encoding {
+            headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding));
+        }
+
+        resp
+    }
+}
+
+/// Whether the client's `Accept-Encoding` lists `encoding` without disabling it via `q=0`.
+fn accepts_encoding(headers: &HeaderMap, encoding: &str) -> bool {
+    headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|v| {
+            let mut parts = v.split(';').map(str::trim);
+
+            parts
+                .next()
+                .is_some_and(|v| v.eq_ignore_ascii_case(encoding))
+                && !parts.any(|param| {
+                    param
+                        .strip_prefix("q=")
+                        .and_then(|q| q.parse::<f32>().ok())
+                        .is_some_and(|q| q <= 0.0)
+                })
+        })
+}
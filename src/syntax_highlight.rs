@@ -1,14 +1,18 @@
 use std::{
-    cell::RefCell,
+    cell::{Cell, RefCell},
     collections::HashMap,
     fmt::Write as FmtWrite,
     io::{ErrorKind, Write as IoWrite},
     path::Path,
-    sync::LazyLock,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        OnceLock,
+    },
 };
 
 use comrak::adapters::SyntaxHighlighterAdapter;
 use tracing::{debug, error};
+use tree_sitter::QueryError;
 use tree_sitter_grammar_repository::{Grammar, Language};
 use tree_sitter_highlight::{HighlightConfiguration, HighlightEvent, Highlighter};
 
@@ -24,11 +28,15 @@ macro_rules! count {
 
 macro_rules! define_classes {
     ($($name:literal => $class:literal),*,) => {
-        static HIGHLIGHT_NAMES: [&str; count!($($name),*)] = [
+        /// Every highlight name tree-sitter is configured to recognise, as used by themes (eg.
+        /// `markup.heading`).
+        pub const HIGHLIGHT_NAMES: [&str; count!($($name),*)] = [
             $($name),*
         ];
 
-        static HIGHLIGHT_CLASSES: [&str; count!($($name),*)] = [
+        /// The CSS classes emitted for each entry of [`HIGHLIGHT_NAMES`], at the same index (eg.
+        /// `markup heading`).
+        pub const HIGHLIGHT_CLASSES: [&str; count!($($name),*)] = [
             $($class),*
         ];
     };
@@ -88,42 +96,143 @@ define_classes! {
 "variable.member" => "variable member",
 "variable.parameter" => "variable parameter",}
 
+/// Builds the highlighter configuration for every grammar up front, rather than on first use, for
+/// deployments that would rather pay the memory cost than the latency of the first request in
+/// each language.
 pub fn prime_highlighters() {
-    let _res = HIGHLIGHTER_CONFIGS.len();
+    for grammar in Grammar::VARIANTS {
+        highlighter_config(*grammar);
+    }
+}
+
+/// Highlighter configuration for each grammar, indexed by [`Grammar::idx`] and built on first use
+/// as most deployments will only ever see a handful of languages. Grammars with broken queries
+/// are left as `None` so files in that language are rendered as plaintext rather than taking down
+/// the whole server.
+static HIGHLIGHTER_CONFIGS: [OnceLock<Option<HighlightConfiguration>>; Grammar::VARIANTS.len()] =
+    [const { OnceLock::new() }; Grammar::VARIANTS.len()];
+
+fn highlighter_config(grammar: Grammar) -> Option<&'static HighlightConfiguration> {
+    init_highlighter_config(&HIGHLIGHTER_CONFIGS[grammar.idx()], grammar, || {
+        build_highlighter_config(grammar)
+    })
+}
+
+/// Fills in the configuration slot for `grammar` using `build` if it hasn't been already, logging
+/// and leaving it empty if the queries don't compile.
+fn init_highlighter_config(
+    slot: &OnceLock<Option<HighlightConfiguration>>,
+    grammar: Grammar,
+    build: impl FnOnce() -> Result<HighlightConfiguration, QueryError>,
+) -> Option<&HighlightConfiguration> {
+    slot.get_or_init(|| {
+        build()
+            .inspect_err(|error| {
+                error!(
+                    ?grammar,
+                    %error,
+                    "Bad highlighter query, falling back to plaintext"
+                );
+            })
+            .ok()
+    })
+    .as_ref()
 }
 
-static HIGHLIGHTER_CONFIGS: LazyLock<Vec<HighlightConfiguration>> = LazyLock::new(|| {
+fn build_highlighter_config(grammar: Grammar) -> Result<HighlightConfiguration, QueryError> {
+    let params = grammar.highlight_configuration_params();
+
+    let mut configuration = HighlightConfiguration::new(
+        params.language.into(),
+        params.name,
+        params.highlights_query,
+        params.injection_query,
+        params.locals_query,
+    )?;
+    configuration.configure(&HIGHLIGHT_NAMES);
+
+    Ok(configuration)
+}
+
+/// Compiles the queries for every grammar, returning the ones that fail so broken queries can be
+/// caught before deploying (via `rgit check-highlighters`) rather than on startup.
+pub fn check_highlighters() -> Vec<(Grammar, QueryError)> {
     Grammar::VARIANTS
         .iter()
         .copied()
-        .map(Grammar::highlight_configuration_params)
-        .map(|v| {
-            let mut configuration = HighlightConfiguration::new(
-                v.language.into(),
-                v.name,
-                v.highlights_query,
-                v.injection_query,
-                v.locals_query,
-            )
-            .unwrap_or_else(|e| panic!("bad query for {}: {e}", v.name));
-            configuration.configure(&HIGHLIGHT_NAMES);
-            configuration
+        .filter_map(|grammar| {
+            build_highlighter_config(grammar)
+                .err()
+                .map(|e| (grammar, e))
         })
         .collect()
-});
+}
 
-pub fn fetch_highlighter_config(file: &Path) -> Option<&'static HighlightConfiguration> {
+pub fn fetch_highlighter_config(
+    file: &Path,
+    content: &str,
+) -> Option<&'static HighlightConfiguration> {
     Language::from_file_name(file)
+        .or_else(|| language_from_shebang(content))
         .map(Language::grammar)
-        .map(Grammar::idx)
-        .map(|idx| &HIGHLIGHTER_CONFIGS[idx])
+        .and_then(highlighter_config)
+}
+
+/// Guesses the language of an extensionless script from the interpreter named in its shebang,
+/// such as `#!/bin/bash` or `#!/usr/bin/env python3`.
+fn language_from_shebang(content: &str) -> Option<Language> {
+    let line = content.lines().next()?.strip_prefix("#!")?;
+    let mut args = line.split_whitespace();
+
+    let mut interpreter = args.next()?.rsplit('/').next()?;
+    if interpreter == "env" {
+        // skip over any flags given to env itself, eg. `env -S node --flag`
+        interpreter = args.find(|v| !v.starts_with('-') && !v.contains('='))?;
+    }
+
+    // interpreters are commonly versioned (`python3.12`) whereas the known names usually aren't
+    Language::from_shebang(interpreter).or_else(|| {
+        Language::from_shebang(
+            interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.'),
+        )
+    })
+}
+
+/// Maximum number of injected languages resolved while highlighting a single file, set by
+/// `--max-highlight-injections`.
+pub static MAX_HIGHLIGHT_INJECTIONS: AtomicUsize = AtomicUsize::new(256);
+
+/// Resolves an injected language (eg. a fenced code block within markdown) for the highlighter.
+///
+/// The highlighter doesn't tell us how deeply nested an injection is, so instead the number of
+/// injections resolved for the file so far is tracked in `count`. Any past the limit are left
+/// unhighlighted, which bounds the work done for pathological documents whether they nest
+/// deeply or just contain a huge number of injections.
+fn resolve_injection(
+    injection: &str,
+    count: &Cell<usize>,
+) -> Option<&'static HighlightConfiguration> {
+    if count.get() >= MAX_HIGHLIGHT_INJECTIONS.load(Ordering::Relaxed) {
+        debug!(injection, "Injection limit reached, not highlighting");
+        return None;
+    }
+
+    count.set(count.get() + 1);
+
+    debug!(injection, "Highlighter switch requested");
+    fetch_highlighter_config_by_token(injection)
 }
 
 pub fn fetch_highlighter_config_by_token(token: &str) -> Option<&'static HighlightConfiguration> {
-    Language::from_injection(token)
+    // fenced code blocks are usually tagged with a language name (`rust`) or extension (`rs`)
+    // rather than something matching helix's injection regexes
+    let token = token.to_ascii_lowercase();
+
+    Language::from_name(&token)
+        .or_else(|| Language::from_injection(&token))
+        .or_else(|| Language::from_file_name(format!("file.{token}")))
         .map(Language::grammar)
-        .map(Grammar::idx)
-        .map(|idx| &HIGHLIGHTER_CONFIGS[idx])
+        .and_then(highlighter_config)
 }
 
 pub struct ComrakHighlightAdapter;
@@ -169,6 +278,128 @@ pub fn format_file(content: &str, identifier: FileIdentifier<'_>) -> anyhow::Res
     Ok(out)
 }
 
+/// Formats a file the same way as [`format_file`] but without running it through the
+/// highlighter, for files that are too large to highlight in a reasonable amount of time.
+pub fn format_file_plain(content: &str) -> String {
+    let mut out = String::with_capacity(content.len());
+    write_plaintext(&mut out, content, "<code>", "</code>\n");
+    out
+}
+
+/// Highlights an entire file, returning the HTML for each of its lines individually.
+///
+/// Highlighting the whole file at once rather than line-by-line preserves the context needed for
+/// constructs spanning multiple lines (such as block comments), and any highlights still open at
+/// the end of a line are closed and reopened on the next so every line is balanced on its own.
+pub fn highlight_lines(content: &str, identifier: FileIdentifier<'_>) -> Vec<String> {
+    let plaintext = || {
+        content
+            .lines()
+            .map(|line| {
+                let mut out = String::with_capacity(line.len());
+                v_htmlescape::b_escape(line.as_bytes(), &mut out);
+                out
+            })
+            .collect()
+    };
+
+    let config = match identifier {
+        FileIdentifier::Path(v) => fetch_highlighter_config(v, content),
+        FileIdentifier::Token(v) => fetch_highlighter_config_by_token(v),
+    };
+
+    let Some(config) = config else {
+        return plaintext();
+    };
+
+    HIGHLIGHTER.with_borrow_mut(|highlighter| {
+        highlighter.parser().reset();
+
+        let injections = Cell::new(0);
+        let spans = highlighter.highlight(config, content.as_bytes(), None, |injection| {
+            resolve_injection(injection, &injections)
+        });
+
+        let spans = match spans {
+            Ok(v) => v,
+            Err(error) => {
+                error!(
+                    ?error,
+                    "Failed to run highlighter, falling back to plaintext"
+                );
+                return plaintext();
+            }
+        };
+
+        let mut lines = Vec::new();
+        let mut line = String::new();
+        let mut open = Vec::new();
+
+        for span in spans {
+            let span = match span {
+                Ok(v) => v,
+                Err(error) => {
+                    error!(
+                        ?error,
+                        "Failed to run highlighter, falling back to plaintext"
+                    );
+                    return plaintext();
+                }
+            };
+
+            match span {
+                HighlightEvent::Source { start, end } => {
+                    let mut source = &content[start..end];
+
+                    while let Some(idx) = source.find('\n') {
+                        let text = source[..idx].strip_suffix('\r').unwrap_or(&source[..idx]);
+                        v_htmlescape::b_escape(text.as_bytes(), &mut line);
+
+                        for _ in &open {
+                            line.push_str("</span>");
+                        }
+                        lines.push(std::mem::take(&mut line));
+                        for class in &open {
+                            write!(line, r#"<span class="highlight {class}">"#).unwrap();
+                        }
+
+                        source = &source[idx + 1..];
+                    }
+
+                    v_htmlescape::b_escape(source.as_bytes(), &mut line);
+                }
+                HighlightEvent::HighlightStart(highlight) => {
+                    let class = HIGHLIGHT_CLASSES[highlight.0];
+                    write!(line, r#"<span class="highlight {class}">"#).unwrap();
+                    open.push(class);
+                }
+                HighlightEvent::HighlightEnd => {
+                    line.push_str("</span>");
+                    open.pop();
+                }
+            }
+        }
+
+        // a trailing newline doesn't start another line, matching `str::lines`
+        if !content.is_empty() && !content.ends_with('\n') {
+            for _ in &open {
+                line.push_str("</span>");
+            }
+            lines.push(line);
+        }
+
+        lines
+    })
+}
+
+fn write_plaintext(out: &mut String, content: &str, line_prefix: &str, line_suffix: &str) {
+    for line in content.lines() {
+        out.push_str(line_prefix);
+        v_htmlescape::b_escape(line.as_bytes(), out);
+        out.push_str(line_suffix);
+    }
+}
+
 pub fn format_file_inner(
     out: &mut String,
     content: &str,
@@ -176,7 +407,7 @@ pub fn format_file_inner(
     code_tag: bool,
 ) -> anyhow::Result<()> {
     let config = match identifier {
-        FileIdentifier::Path(v) => fetch_highlighter_config(v),
+        FileIdentifier::Path(v) => fetch_highlighter_config(v, content),
         FileIdentifier::Token(v) => fetch_highlighter_config_by_token(v),
     };
 
@@ -184,21 +415,16 @@ pub fn format_file_inner(
     let line_suffix = if code_tag { "</code>\n" } else { "\n" };
 
     let Some(config) = config else {
-        for line in content.lines() {
-            out.push_str(line_prefix);
-            v_htmlescape::b_escape(line.as_bytes(), out);
-            out.push_str(line_suffix);
-        }
-
+        write_plaintext(out, content, line_prefix, line_suffix);
         return Ok(());
     };
 
     HIGHLIGHTER.with_borrow_mut(|highlighter| {
         highlighter.parser().reset();
 
+        let injections = Cell::new(0);
         let spans = highlighter.highlight(config, content.as_bytes(), None, |injection| {
-            debug!(injection, "Highlighter switch requested");
-            fetch_highlighter_config_by_token(injection)
+            resolve_injection(injection, &injections)
         });
 
         let mut spans = match spans {
@@ -209,12 +435,7 @@ pub fn format_file_inner(
                     "Failed to run highlighter, falling back to plaintext"
                 );
 
-                for line in content.lines() {
-                    out.push_str(line_prefix);
-                    v_htmlescape::b_escape(line.as_bytes(), out);
-                    out.push_str(line_suffix);
-                }
-
+                write_plaintext(out, content, line_prefix, line_suffix);
                 return Ok(());
             }
         };
@@ -268,3 +489,71 @@ pub fn format_file_inner(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, sync::OnceLock};
+
+    use tree_sitter_grammar_repository::Language;
+    use tree_sitter_highlight::HighlightConfiguration;
+
+    use super::{
+        build_highlighter_config, format_file, init_highlighter_config, language_from_shebang,
+        FileIdentifier,
+    };
+
+    #[test]
+    fn scripts_are_recognised_by_their_shebang() {
+        for (script, language) in [
+            ("#!/bin/bash\necho hi\n", "bash"),
+            ("#!/usr/bin/env node\nconsole.log(1);\n", "javascript"),
+            ("#!/usr/bin/env -S python3.12 -u\nprint(1)\n", "python"),
+        ] {
+            assert_eq!(
+                language_from_shebang(script).map(Language::name),
+                Some(language),
+                "{script}"
+            );
+        }
+
+        for script in ["echo hi\n", "#!/opt/bin/not-an-interpreter\n", ""] {
+            assert_eq!(language_from_shebang(script), None, "{script}");
+        }
+    }
+
+    #[test]
+    fn extensionless_scripts_are_highlighted() {
+        for script in [
+            "#!/bin/bash\necho hi\n",
+            "#!/usr/bin/env node\nconst a = 1;\n",
+        ] {
+            let out = format_file(script, FileIdentifier::Path(Path::new("bin/run"))).unwrap();
+            assert!(out.contains(r#"<span class="highlight"#), "{out}");
+        }
+    }
+
+    #[test]
+    fn grammars_with_broken_queries_are_left_unconfigured() {
+        let grammar = Language::from_name("rust").unwrap().grammar();
+
+        let broken = OnceLock::new();
+        let config = init_highlighter_config(&broken, grammar, || {
+            let params = grammar.highlight_configuration_params();
+            HighlightConfiguration::new(
+                params.language.into(),
+                params.name,
+                "(not_a_real_node) @keyword",
+                "",
+                "",
+            )
+        });
+        assert!(config.is_none());
+        // the failure is remembered rather than retried for every file
+        assert!(matches!(broken.get(), Some(None)));
+
+        let working = OnceLock::new();
+        let config =
+            init_highlighter_config(&working, grammar, || build_highlighter_config(grammar));
+        assert!(config.is_some());
+    }
+}
@@ -0,0 +1,239 @@
+//! Helpers shared between tests, for building throwaway repositories and databases.
+//!
+//! Repositories are built by shelling out to the git CLI, so tests exercise rgit against exactly
+//! what git itself writes.
+
+use std::{
+    ffi::OsStr,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::Arc,
+};
+
+use clap::Parser;
+use tempfile::TempDir;
+
+use crate::{
+    database::indexer::IndexerConfig, git::GitConfig, methods::filters::AvatarHash, Args,
+    SiteConfig, SITE,
+};
+
+/// Base path every test is served under, so link building is always tested with a prefix.
+pub const BASE_PATH: &str = "/git";
+
+/// Sets up the instance-wide configuration the same way for every test, since it can only be set
+/// once per process.
+pub fn init_site() -> &'static SiteConfig {
+    crate::HIGHLIGHT_CSS_HASH.get_or_init(|| Box::from("test"));
+    crate::DARK_HIGHLIGHT_CSS_HASH.get_or_init(|| Box::from("test"));
+
+    SITE.get_or_init(|| SiteConfig {
+        name: None,
+        clone_base: None,
+        editor_link: Some(Box::from("vscode://file/src/{repo}/{path}:{line}")),
+        url: Some(Box::from("https://git.example.com/git")),
+        base_path: Box::from(BASE_PATH),
+        logo: None,
+        avatar_base: Box::from("https://avatars.example.com"),
+        avatar_hash: AvatarHash::Md5,
+        no_avatars: true,
+        default_branches: Box::new([
+            Box::from("refs/heads/master"),
+            Box::from("refs/heads/main"),
+            Box::from("refs/heads/develop"),
+        ]),
+        summary_commits: 10,
+        log_page_size: 100,
+    })
+}
+
+/// A git repository within a scan path, which is removed once it and every other repository in
+/// it are dropped.
+pub struct Fixture {
+    scan_path: Arc<TempDir>,
+    name: &'static str,
+    time: i64,
+}
+
+impl Fixture {
+    /// Creates an empty repository named `repo.git` with `main` checked out.
+    pub fn new() -> Self {
+        Self::named("repo.git")
+    }
+
+    pub fn named(name: &'static str) -> Self {
+        Self::init(Arc::new(tempfile::tempdir().unwrap()), name)
+    }
+
+    /// Creates another empty repository alongside this one, in the same scan path.
+    pub fn sibling(&self, name: &'static str) -> Self {
+        Self::init(self.scan_path.clone(), name)
+    }
+
+    fn init(scan_path: Arc<TempDir>, name: &'static str) -> Self {
+        let fixture = Self {
+            scan_path,
+            name,
+            time: 1_700_000_000,
+        };
+
+        std::fs::create_dir_all(fixture.path()).unwrap();
+        fixture.git(&["init", "-q", "-b", "main"]);
+
+        fixture
+    }
+
+    pub fn scan_path(&self) -> &Path {
+        self.scan_path.path()
+    }
+
+    /// The path of the repository relative to the scan path, as it's known to the index.
+    pub fn relative_path(&self) -> &Path {
+        Path::new(self.name)
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.scan_path.path().join(self.name)
+    }
+
+    /// Writes a file into the working tree, creating any parent directories.
+    pub fn write(&self, path: &str, content: impl AsRef<[u8]>) {
+        let path = self.path().join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    /// Runs git within the repository, panicking if it fails, and returns its trimmed stdout.
+    pub fn git(&self, args: &[&str]) -> String {
+        self.git_with_stdin(args, &[])
+    }
+
+    pub fn git_with_stdin(&self, args: &[&str], stdin: &[u8]) -> String {
+        let date = format!("{} +0000", self.time);
+
+        let mut child = Command::new("git")
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(["-c", "commit.gpgsign=false", "-c", "tag.gpgsign=false"])
+            .args(args)
+            .current_dir(self.path())
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("GIT_CONFIG_GLOBAL", "/dev/null")
+            .env("GIT_AUTHOR_DATE", &date)
+            .env("GIT_COMMITTER_DATE", &date)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("failed to run git");
+
+        child.stdin.take().unwrap().write_all(stdin).unwrap();
+        let output = child.wait_with_output().unwrap();
+
+        assert!(
+            output.status.success(),
+            "git {args:?} failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    }
+
+    /// Commits everything in the working tree, an hour after the previous commit, returning the
+    /// new commit's id.
+    pub fn commit(&mut self, message: &str) -> String {
+        self.time += 3600;
+        self.git(&["add", "-A"]);
+        self.git(&["commit", "-q", "--allow-empty", "-m", message]);
+        self.git(&["rev-parse", "HEAD"])
+    }
+
+    /// Imports a linear history of `count` commits onto `branch`, which mustn't exist yet, through
+    /// `git fast-import`. This is far quicker than committing one at a time for building large
+    /// repositories.
+    pub fn import_commits(&mut self, branch: &str, count: usize) {
+        use std::fmt::Write;
+
+        let mut stream = String::new();
+        for i in 0..count {
+            self.time += 60;
+            let message = format!("commit {i}");
+            write!(
+                stream,
+                "commit refs/heads/{branch}\ncommitter Test <test@example.com> {} +0000\n\
+                 data {}\n{message}\nM 644 inline file\ndata {}\n{i}\n\n",
+                self.time,
+                message.len(),
+                i.to_string().len(),
+            )
+            .unwrap();
+        }
+
+        self.git_with_stdin(&["fast-import", "--quiet"], stream.as_bytes());
+    }
+
+    /// Writes a raw object to the repository without any validation, for building objects that
+    /// git itself would refuse to create.
+    pub fn write_raw_object(&self, kind: &str, content: &[u8]) -> String {
+        self.git_with_stdin(
+            &["hash-object", "-w", "--literally", "-t", kind, "--stdin"],
+            content,
+        )
+    }
+
+    /// Writes a Git LFS pointer to `oid`, a sha256 hex digest, into the working tree.
+    pub fn write_lfs_pointer(&self, path: &str, oid: &str, size: u64) {
+        self.write(
+            path,
+            format!("version https://git-lfs.github.com/spec/v1\noid sha256:{oid}\nsize {size}\n"),
+        );
+    }
+
+    /// Opens the repository the same way it's opened to serve requests.
+    pub async fn open(&self, git: &Arc<crate::Git>) -> Arc<crate::git::OpenRepository> {
+        git.clone().repo(self.path(), None).await.unwrap()
+    }
+}
+
+/// A git handle with the default limits and every optional feature turned off.
+pub fn git() -> Arc<crate::Git> {
+    git_with(GitConfig::default())
+}
+
+/// A git handle configured by `config`, for tests that need a feature turned on.
+pub fn git_with(config: GitConfig) -> Arc<crate::Git> {
+    Arc::new(crate::Git::new(config))
+}
+
+/// Creates an LFS object store holding the given objects, keyed by their sha256 hex digest, laid
+/// out like `.git/lfs/objects` for passing as `--lfs-store`.
+pub fn lfs_store(objects: &[(&str, &[u8])]) -> TempDir {
+    let store = tempfile::tempdir().unwrap();
+    for (oid, content) in objects {
+        let object_dir = store.path().join(&oid[..2]).join(&oid[2..4]);
+        std::fs::create_dir_all(&object_dir).unwrap();
+        std::fs::write(object_dir.join(oid), content).unwrap();
+    }
+    store
+}
+
+/// Opens a fresh database the same way the server does, alongside the directory holding it.
+pub fn open_db(scan_path: &Path) -> (TempDir, Arc<rocksdb::DB>) {
+    let dir = tempfile::tempdir().unwrap();
+    let args = Args::parse_from([
+        OsStr::new("rgit"),
+        OsStr::new("--db-store"),
+        dir.path().as_os_str(),
+        OsStr::new("127.0.0.1:0"),
+        scan_path.as_os_str(),
+    ]);
+
+    let db = crate::open_db(&args).unwrap();
+    (dir, db)
+}
+
+/// Runs a full index of the scan path, the same as the periodic indexer does.
+pub fn index(scan_path: &Path, db: &Arc<rocksdb::DB>) {
+    let status = std::sync::Mutex::new(crate::database::indexer::IndexStatus::default());
+    crate::database::indexer::run(scan_path, db, &status, &IndexerConfig::default());
+}
@@ -5,6 +5,8 @@ use serde::{
     Deserialize, Deserializer,
 };
 
+use crate::syntax_highlight::HIGHLIGHT_NAMES;
+
 #[derive(Deserialize)]
 pub struct Theme {
     palette: hashbrown::HashMap<String, String>,
@@ -81,6 +83,39 @@ impl Theme {
         }
     }
 
+    /// Highlight names the theme has no rule for, either directly or through one of its parents
+    /// (ie. `markup` covers `markup.heading`), which will be rendered in the inherited colour.
+    pub fn missing_highlights(&self) -> Vec<&'static str> {
+        HIGHLIGHT_NAMES
+            .iter()
+            .copied()
+            .filter(|name| {
+                !std::iter::successors(Some(*name), |v| v.rsplit_once('.').map(|(v, _)| v))
+                    .any(|v| self.definitions.contains_key(v))
+            })
+            .collect()
+    }
+
+    /// Palette colours referenced by the theme's rules that its palette doesn't define, which
+    /// would otherwise only be noticed by [`Self::build_css`] panicking.
+    pub fn unknown_colors(&self) -> Vec<&str> {
+        let mut unknown = self
+            .definitions
+            .values()
+            .flat_map(|palette_ref| match palette_ref {
+                PaletteReference::Foreground(color) => vec![color.as_str()],
+                PaletteReference::WithModifiers(v) => {
+                    v.bg.iter().chain(&v.fg).map(String::as_str).collect()
+                }
+            })
+            .filter(|color| !color.starts_with('#') && !self.palette.contains_key(*color))
+            .collect::<Vec<_>>();
+
+        unknown.sort_unstable();
+        unknown.dedup();
+        unknown
+    }
+
     pub fn build_css(&self) -> String {
         let mut out = String::new();
 
@@ -125,3 +160,31 @@ impl Theme {
         out
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Theme;
+
+    #[test]
+    fn missing_highlights_are_covered_by_their_parents() {
+        let theme = basic_toml::from_str::<Theme>("\"markup\" = \"#ffffff\"\n[palette]\n").unwrap();
+        let missing = theme.missing_highlights();
+
+        assert!(!missing.contains(&"markup.heading"), "{missing:?}");
+        assert!(missing.contains(&"keyword"), "{missing:?}");
+    }
+
+    #[test]
+    fn unknown_palette_colors_are_found() {
+        let theme = basic_toml::from_str::<Theme>(
+            "\"keyword\" = \"red\"\n\
+             \"comment\" = { fg = \"gray\", bg = \"#000000\" }\n\
+             \"string\" = \"green\"\n\
+             [palette]\n\
+             green = \"#00ff00\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(theme.unknown_colors(), ["gray", "red"]);
+    }
+}
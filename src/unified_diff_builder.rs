@@ -8,23 +8,25 @@ use gix::diff::blob::{
     Sink,
 };
 
+/// Receives each line of the diff, along with its (zero-indexed) line number in the file it came
+/// from. Removed and context lines are numbered by the old file, additions by the new file.
 pub(crate) trait Callback {
-    fn addition(&mut self, data: &str, dst: &mut String);
-    fn remove(&mut self, data: &str, dst: &mut String);
-    fn context(&mut self, data: &str, dst: &mut String);
+    fn addition(&mut self, line: usize, data: &str, dst: &mut String);
+    fn remove(&mut self, line: usize, data: &str, dst: &mut String);
+    fn context(&mut self, line: usize, data: &str, dst: &mut String);
 }
 
 impl<C: Callback> Callback for &mut C {
-    fn addition(&mut self, data: &str, dst: &mut String) {
-        (*self).addition(data, dst);
+    fn addition(&mut self, line: usize, data: &str, dst: &mut String) {
+        (*self).addition(line, data, dst);
     }
 
-    fn remove(&mut self, data: &str, dst: &mut String) {
-        (*self).remove(data, dst);
+    fn remove(&mut self, line: usize, data: &str, dst: &mut String) {
+        (*self).remove(line, data, dst);
     }
 
-    fn context(&mut self, data: &str, dst: &mut String) {
-        (*self).context(data, dst);
+    fn context(&mut self, line: usize, data: &str, dst: &mut String) {
+        (*self).context(line, data, dst);
     }
 }
 
@@ -39,6 +41,9 @@ where
     after: &'a [Token],
     interner: &'a Interner<&'a str>,
 
+    /// Lines of unchanged context kept around each change
+    context_len: u32,
+
     pos: u32,
     before_hunk_start: u32,
     after_hunk_start: u32,
@@ -56,9 +61,16 @@ where
     W: Write,
 {
     /// Create a new `UnifiedDiffBuilder` for the given `input`,
-    /// that will writes it output to the provided implementation of [`Write`].
-    pub fn with_writer(input: &'a InternedInput<&'a str>, writer: W, callback: C) -> Self {
+    /// that will writes it output to the provided implementation of [`Write`],
+    /// keeping `context_len` lines of context around each change.
+    pub fn with_writer(
+        input: &'a InternedInput<&'a str>,
+        writer: W,
+        callback: C,
+        context_len: u32,
+    ) -> Self {
         Self {
+            context_len,
             before_hunk_start: 0,
             after_hunk_start: 0,
             before_hunk_len: 0,
@@ -78,7 +90,8 @@ where
             return;
         }
 
-        let end = (self.pos + 3).min(u32::try_from(self.before.len()).unwrap_or(u32::MAX));
+        let end =
+            (self.pos + self.context_len).min(u32::try_from(self.before.len()).unwrap_or(u32::MAX));
         self.update_pos(end, end);
 
         writeln!(
@@ -97,9 +110,15 @@ where
     }
 
     fn update_pos(&mut self, print_to: u32, move_to: u32) {
-        for token in &self.before[self.pos as usize..print_to as usize] {
-            self.callback
-                .context(self.interner[*token], &mut self.buffer);
+        for (line, token) in self.before[self.pos as usize..print_to as usize]
+            .iter()
+            .enumerate()
+        {
+            self.callback.context(
+                self.pos as usize + line,
+                self.interner[*token],
+                &mut self.buffer,
+            );
         }
         let len = print_to - self.pos;
         self.pos = move_to;
@@ -116,24 +135,38 @@ where
     type Out = W;
 
     fn process_change(&mut self, before: Range<u32>, after: Range<u32>) {
-        if before.start - self.pos > 6 {
+        // changes separated by no more than the context either side of them are merged into a
+        // single hunk, rather than repeating the lines between them
+        if before.start - self.pos > 2 * self.context_len {
             self.flush();
-            self.pos = before.start - 3;
+            self.pos = before.start - self.context_len;
             self.before_hunk_start = self.pos;
-            self.after_hunk_start = after.start - 3;
+            self.after_hunk_start = after.start - self.context_len;
         }
         self.update_pos(before.start, before.end);
         self.before_hunk_len += before.end - before.start;
         self.after_hunk_len += after.end - after.start;
 
-        for token in &self.before[before.start as usize..before.end as usize] {
-            self.callback
-                .remove(self.interner[*token], &mut self.buffer);
+        for (line, token) in self.before[before.start as usize..before.end as usize]
+            .iter()
+            .enumerate()
+        {
+            self.callback.remove(
+                before.start as usize + line,
+                self.interner[*token],
+                &mut self.buffer,
+            );
         }
 
-        for token in &self.after[after.start as usize..after.end as usize] {
-            self.callback
-                .addition(self.interner[*token], &mut self.buffer);
+        for (line, token) in self.after[after.start as usize..after.end as usize]
+            .iter()
+            .enumerate()
+        {
+            self.callback.addition(
+                after.start as usize + line,
+                self.interner[*token],
+                &mut self.buffer,
+            );
         }
     }
 
@@ -142,3 +175,76 @@ where
         self.dst
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use gix::diff::blob::{intern::InternedInput, sources::lines_with_terminator, Algorithm};
+
+    use super::{Callback, UnifiedDiffBuilder};
+
+    struct Plain;
+
+    impl Callback for Plain {
+        fn addition(&mut self, _line: usize, data: &str, dst: &mut String) {
+            dst.push('+');
+            dst.push_str(data);
+        }
+
+        fn remove(&mut self, _line: usize, data: &str, dst: &mut String) {
+            dst.push('-');
+            dst.push_str(data);
+        }
+
+        fn context(&mut self, _line: usize, data: &str, dst: &mut String) {
+            dst.push(' ');
+            dst.push_str(data);
+        }
+    }
+
+    /// Diffs 30 numbered lines against the same lines with the 5th and 20th changed.
+    fn diff(context_len: u32) -> String {
+        let before: String = (1..=30).map(|i| format!("{i}\n")).collect();
+        let after = before
+            .replace("\n5\n", "\nfive\n")
+            .replace("\n20\n", "\ntwenty\n");
+
+        let input = InternedInput::new(
+            lines_with_terminator(&before),
+            lines_with_terminator(&after),
+        );
+        gix::diff::blob::diff(
+            Algorithm::Myers,
+            &input,
+            UnifiedDiffBuilder::with_writer(&input, String::new(), Plain, context_len),
+        )
+    }
+
+    fn hunk_headers(diff: &str) -> Vec<&str> {
+        diff.lines().filter(|line| line.starts_with("@@")).collect()
+    }
+
+    #[test]
+    fn no_context() {
+        assert_eq!(
+            diff(0),
+            "@@ -5,1 +5,1 @@\n-5\n+five\n@@ -20,1 +20,1 @@\n-20\n+twenty\n"
+        );
+    }
+
+    #[test]
+    fn default_context_keeps_distant_changes_apart() {
+        assert_eq!(
+            hunk_headers(&diff(3)),
+            ["@@ -2,7 +2,7 @@", "@@ -17,7 +17,7 @@"]
+        );
+    }
+
+    #[test]
+    fn wide_context_merges_hunks() {
+        let diff = diff(10);
+
+        // the changes are 14 lines apart, within the 10 lines of context either side of them
+        assert_eq!(hunk_headers(&diff), ["@@ -1,30 +1,30 @@"]);
+        assert_eq!(diff.lines().filter(|l| l.starts_with(' ')).count(), 28);
+    }
+}
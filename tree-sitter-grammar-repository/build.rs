@@ -1,11 +1,13 @@
 use std::{
     borrow::Cow,
+    collections::{HashMap, HashSet},
     ffi::OsStr,
     fmt::Write,
     fs,
+    hash::{DefaultHasher, Hash, Hasher},
     path::{Path, PathBuf},
     process::Command,
-    sync::LazyLock,
+    sync::{Arc, LazyLock, Mutex, OnceLock},
 };
 
 use anyhow::{bail, Context};
@@ -44,9 +46,50 @@ static BLACKLISTED_FOR_STATIC_LINKING: &[&str] = &[
     "llvm",
 ];
 
+/// Comma separated list of additional grammars to leave out of the build.
+const GRAMMAR_EXCLUDE_ENV: &str = "TREE_SITTER_GRAMMAR_EXCLUDE";
+/// Comma separated list of grammars to build, if set every other grammar is left out.
+const GRAMMAR_INCLUDE_ENV: &str = "TREE_SITTER_GRAMMAR_INCLUDE";
+/// Path to a lockfile that every fetched source is verified against, see [`Lockfile`].
+const GRAMMAR_LOCKFILE_ENV: &str = "TREE_SITTER_GRAMMAR_LOCKFILE";
+
+/// Directory fetched sources & compiled grammars are kept in between builds, defaults to
+/// `OUT_DIR` which is wiped by `cargo clean`.
+const GRAMMAR_CACHE_DIR_ENV: &str = "TREE_SITTER_GRAMMAR_CACHE_DIR";
+
+/// If set to `1`, grammars that fail to fetch or compile are left out with a warning rather than
+/// failing the build.
+const SKIP_FAILED_ENV: &str = "TREE_SITTER_SKIP_FAILED";
+
+static LOCKFILE: OnceLock<Option<Lockfile>> = OnceLock::new();
+/// Grammars that failed to build and were skipped due to [`SKIP_FAILED_ENV`].
+static SKIPPED: OnceLock<HashSet<String>> = OnceLock::new();
+/// Identifies the target, profile & C compiler grammars are being built with, so a cache
+/// directory shared between builds never links in libraries compiled for another.
+static TOOLCHAIN_KEY: LazyLock<String> = LazyLock::new(toolchain_key);
+
 fn main() -> anyhow::Result<()> {
     let out_dir = PathBuf::from(std::env::var("OUT_DIR").context("OUT_DIR not set by rustc")?);
 
+    println!("cargo::rerun-if-env-changed={GRAMMAR_EXCLUDE_ENV}");
+    println!("cargo::rerun-if-env-changed={GRAMMAR_INCLUDE_ENV}");
+    println!("cargo::rerun-if-env-changed={GRAMMAR_LOCKFILE_ENV}");
+    println!("cargo::rerun-if-env-changed={GRAMMAR_CACHE_DIR_ENV}");
+    println!("cargo::rerun-if-env-changed={SKIP_FAILED_ENV}");
+    // nothing else in the crate affects the generated code, so there's no need to refetch &
+    // rebuild every grammar when any other file in the package is touched
+    println!("cargo::rerun-if-changed=build.rs");
+
+    let lockfile = std::env::var_os(GRAMMAR_LOCKFILE_ENV)
+        .map(|path| {
+            println!("cargo::rerun-if-changed={}", Path::new(&path).display());
+            Lockfile::read(Path::new(&path))
+        })
+        .transpose()?;
+    LOCKFILE
+        .set(lockfile)
+        .map_err(|_| anyhow::anyhow!("lockfile already loaded"))?;
+
     let root = std::env::var("TREE_SITTER_GRAMMAR_LIB_DIR").ok();
     println!("cargo::rerun-if-env-changed=TREE_SITTER_GRAMMAR_LIB_DIR");
 
@@ -71,10 +114,11 @@ fn main() -> anyhow::Result<()> {
         let config: HelixLanguages =
             basic_toml::from_str(config).context("failed to parse helix languages.toml")?;
 
+        println!("cargo::rerun-if-changed={}", root.display());
         println!("cargo::rustc-link-search=native={}", root.display());
 
         for grammar in &config.grammar {
-            if BLACKLISTED_MODULES.contains(&grammar.name.as_str()) {
+            if is_blacklisted(&grammar.name) {
                 continue;
             }
 
@@ -83,13 +127,15 @@ fn main() -> anyhow::Result<()> {
 
         (config, root.join("queries"))
     } else {
-        let sources = out_dir.join("sources");
+        let sources = std::env::var_os(GRAMMAR_CACHE_DIR_ENV)
+            .map_or_else(|| out_dir.join("sources"), PathBuf::from);
         fs::create_dir_all(&sources)?;
 
-        let helix_root = sources.join("helix");
+        let helix_root = sources.join(cache_key("helix", GRAMMAR_REPOSITORY_REF));
 
         fetch_git_repository(GRAMMAR_REPOSITORY_URL, GRAMMAR_REPOSITORY_REF, &helix_root)
             .context(GRAMMAR_REPOSITORY_URL)?;
+        verify_checkout("helix", GRAMMAR_REPOSITORY_REF, &helix_root)?;
 
         let config = fs::read_to_string(helix_root.join(GRAMMAR_REPOSITORY_CONFIG_PATH))
             .context("failed to read helix languages.toml")?;
@@ -105,7 +151,30 @@ fn main() -> anyhow::Result<()> {
         let config: HelixLanguages =
             basic_toml::from_str(config).context("failed to parse helix languages.toml")?;
 
-        fetch_and_build_grammar(config.grammar.clone(), &sources)?;
+        let failed = fetch_and_build_grammar(config.grammar.clone(), &sources)?;
+
+        if !failed.is_empty() {
+            if std::env::var(SKIP_FAILED_ENV).is_ok_and(|v| v == "1") {
+                for (name, error) in &failed {
+                    println!("cargo::warning=skipping grammar {name}: {error:#}");
+                }
+            } else {
+                let errors = failed.iter().fold(String::new(), |mut out, (name, error)| {
+                    // `write!` to a String cannot fail.
+                    write!(out, "\n  {name}: {error:#}").unwrap();
+                    out
+                });
+
+                bail!(
+                    "{} grammar(s) failed to build, set {SKIP_FAILED_ENV}=1 to skip them:{errors}",
+                    failed.len()
+                );
+            }
+        }
+
+        SKIPPED
+            .set(failed.into_iter().map(|(name, _)| name).collect())
+            .map_err(|_| anyhow::anyhow!("skipped grammars already set"))?;
 
         (config, helix_root.join("runtime/queries"))
     };
@@ -143,11 +212,41 @@ fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Whether the grammar should be left out of the build, either because it's known to be broken
+/// or because the consumer has opted out of it via the environment.
+fn is_blacklisted(name: &str) -> bool {
+    static EXCLUDED: LazyLock<Vec<String>> =
+        LazyLock::new(|| grammar_list_from_env(GRAMMAR_EXCLUDE_ENV).unwrap_or_default());
+    static INCLUDED: LazyLock<Option<Vec<String>>> =
+        LazyLock::new(|| grammar_list_from_env(GRAMMAR_INCLUDE_ENV));
+
+    BLACKLISTED_MODULES.contains(&name)
+        || SKIPPED.get().is_some_and(|skipped| skipped.contains(name))
+        || EXCLUDED.iter().any(|v| v == name)
+        || INCLUDED
+            .as_ref()
+            .is_some_and(|included| !included.iter().any(|v| v == name))
+}
+
+fn grammar_list_from_env(key: &str) -> Option<Vec<String>> {
+    let value = std::env::var(key).ok()?;
+
+    Some(
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(ToString::to_string)
+            .collect(),
+    )
+}
+
 fn build_language_registry(
     language_definition: Vec<LanguageDefinition>,
     dylib: bool,
 ) -> anyhow::Result<proc_macro2::TokenStream> {
     let mut camel = Vec::new();
+    let mut names = Vec::new();
     let mut grammars = Vec::new();
 
     let mut globs = Vec::new();
@@ -157,8 +256,19 @@ fn build_language_registry(
     let mut injection_regex_str_len = Vec::new();
     let mut regex_to_camel = Vec::new();
 
+    let mut shebangs = Vec::new();
+    let mut shebang_to_camel = Vec::new();
+
     for language in &language_definition {
+        let grammar = language
+            .grammar
+            .as_deref()
+            .unwrap_or(language.name.as_str());
+
+        // languages are dropped along with their grammar, otherwise they'd refer to a
+        // non-existent `Grammar` variant
         if BLACKLISTED_MODULES.contains(&language.name.as_str())
+            || is_blacklisted(grammar)
             || (!dylib && BLACKLISTED_FOR_STATIC_LINKING.contains(&language.name.as_str()))
         {
             continue;
@@ -166,11 +276,8 @@ fn build_language_registry(
 
         let camel_cased_name = format_ident!("{}", language.name.to_upper_camel_case());
         camel.push(camel_cased_name.clone());
+        names.push(language.name.as_str());
 
-        let grammar = language
-            .grammar
-            .as_deref()
-            .unwrap_or(language.name.as_str());
         grammars.push(format_ident!("{}", grammar.to_upper_camel_case()));
 
         for ty in &language.file_types {
@@ -187,6 +294,14 @@ fn build_language_registry(
             injection_regex_str_len.push(regex.len());
             regex_to_camel.push(camel_cased_name.clone());
         }
+
+        for shebang in &language.shebangs {
+            // a few interpreters are claimed by more than one language, the first one wins
+            if !shebangs.contains(&shebang.as_str()) {
+                shebangs.push(shebang.as_str());
+                shebang_to_camel.push(camel_cased_name.clone());
+            }
+        }
     }
 
     let injection_regex_len = injection_regex.len();
@@ -204,6 +319,29 @@ fn build_language_registry(
                 #(Self::#camel),*
             ];
 
+            pub fn from_name(name: &str) -> Option<Self> {
+                match name {
+                    #(#names => Some(Self::#camel),)*
+                    _ => None,
+                }
+            }
+
+            /// The name the language is known by, the inverse of [`Language::from_name`].
+            pub const fn name(self) -> &'static str {
+                match self {
+                    #(Self::#camel => #names),*
+                }
+            }
+
+            /// Looks up a language by the name of the interpreter given in a script's shebang
+            /// (eg. `python3` or `node`).
+            pub fn from_shebang(interpreter: &str) -> Option<Self> {
+                match interpreter {
+                    #(#shebangs => Some(Self::#shebang_to_camel),)*
+                    _ => None,
+                }
+            }
+
             pub const fn grammar(self) -> Grammar {
                 match self {
                     #(Self::#camel => Grammar::#grammars),*
@@ -279,7 +417,7 @@ fn build_grammar_registry(
 ) -> proc_macro2::TokenStream {
     let (ids, plain, camel, snake) = names
         .filter(|name| {
-            !BLACKLISTED_MODULES.contains(&name.as_str())
+            !is_blacklisted(name)
                 && (dylib || !BLACKLISTED_FOR_STATIC_LINKING.contains(&name.as_str()))
         })
         .enumerate()
@@ -338,9 +476,7 @@ fn build_language_module(
     query_path: &Path,
     dylib: bool,
 ) -> anyhow::Result<Option<proc_macro2::TokenStream>> {
-    if BLACKLISTED_MODULES.contains(&name)
-        || (!dylib && BLACKLISTED_FOR_STATIC_LINKING.contains(&name))
-    {
+    if is_blacklisted(name) || (!dylib && BLACKLISTED_FOR_STATIC_LINKING.contains(&name)) {
         return Ok(None);
     }
 
@@ -401,80 +537,194 @@ fn read_local_query(query_path: &Path, language: &str, filename: &str) -> String
         .to_string()
 }
 
+/// Fetches & compiles every grammar in parallel, returning the ones that failed along with the
+/// reason why.
 fn fetch_and_build_grammar(
     grammars: Vec<GrammarDefinition>,
     source_dir: &Path,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<Vec<(String, anyhow::Error)>> {
     let pool = ThreadPool::new(std::thread::available_parallelism()?.get());
+    let failed = Arc::new(Mutex::new(Vec::new()));
 
     for grammar in grammars {
-        if BLACKLISTED_MODULES.contains(&grammar.name.as_str())
+        if is_blacklisted(&grammar.name)
             || BLACKLISTED_FOR_STATIC_LINKING.contains(&grammar.name.as_str())
         {
             continue;
         }
 
-        let mut grammar_root = source_dir.join(&grammar.name);
+        let source_dir = source_dir.to_path_buf();
+        let failed = failed.clone();
 
         pool.execute(move || {
-            let grammar_root = match grammar.source {
-                GrammarSource::Git {
-                    remote,
-                    revision,
-                    subpath,
-                } => {
-                    fetch_git_repository(&remote, &revision, &grammar_root)
-                        .context(GRAMMAR_REPOSITORY_URL)
-                        .expect("failed to fetch git repository");
-
-                    if let Some(subpath) = subpath {
-                        grammar_root.push(subpath);
-                    }
+            let name = grammar.name.clone();
 
-                    grammar_root
-                }
-                GrammarSource::Local { path } => path,
-            };
+            if let Err(e) = build_grammar(grammar, &source_dir) {
+                failed.lock().unwrap().push((name, e));
+            }
+        });
+    }
+
+    pool.join();
+
+    if pool.panic_count() > 0 {
+        bail!("{} grammar build(s) panicked", pool.panic_count());
+    }
+
+    let mut failed = std::mem::take(&mut *failed.lock().unwrap());
+    failed.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    Ok(failed)
+}
 
-            let grammar_src = grammar_root.join("src");
-
-            let parser_file = Some(grammar_src.join("parser.c"))
-                .filter(|s| s.exists())
-                .or_else(|| Some(grammar_src.join("parser.cc")))
-                .filter(|s| s.exists());
-            let scanner_file = Some(grammar_src.join("scanner.c"))
-                .filter(|s| s.exists())
-                .or_else(|| Some(grammar_src.join("scanner.cc")))
-                .filter(|s| s.exists());
-
-            if let Some(parser_file) = parser_file {
-                cc::Build::new()
-                    .cpp(parser_file.extension() == Some(OsStr::new("cc")))
-                    .file(parser_file)
-                    .flag_if_supported("-w")
-                    .flag_if_supported("-s")
-                    .include(&grammar_src)
-                    .compile(&format!("{}-parser", grammar.name));
+/// Fetches & compiles a single grammar, reusing the libraries from a previous build if the same
+/// revision has already been compiled in `source_dir`.
+fn build_grammar(grammar: GrammarDefinition, source_dir: &Path) -> anyhow::Result<()> {
+    let (grammar_root, build_dir) = match grammar.source {
+        GrammarSource::Git {
+            remote,
+            revision,
+            subpath,
+        } => {
+            let key = cache_key(&grammar.name, &revision);
+            let mut grammar_root = source_dir.join(&key);
+
+            let up_to_date = fetch_git_repository(&remote, &revision, &grammar_root)
+                .with_context(|| remote.clone())?;
+            verify_checkout(&grammar.name, &revision, &grammar_root)?;
+
+            if let Some(subpath) = subpath {
+                grammar_root.push(subpath);
             }
 
-            if let Some(scanner_file) = scanner_file {
-                cc::Build::new()
-                    .cpp(scanner_file.extension() == Some(OsStr::new("cc")))
-                    .file(scanner_file)
-                    .flag_if_supported("-w")
-                    .flag_if_supported("-s")
-                    .include(&grammar_src)
-                    .compile(&format!("{}-scanner", grammar.name));
+            // only pinned revisions can be cached, since a branch could move on without us
+            let build_dir = source_dir.join(format!("{key}-{}.build", *TOOLCHAIN_KEY));
+            if up_to_date && link_cached_grammar(&grammar.name, &build_dir) {
+                return Ok(());
             }
-        });
+
+            (grammar_root, Some(build_dir))
+        }
+        GrammarSource::Local { path } => {
+            // local grammars are always rebuilt, but only when their sources change
+            println!("cargo::rerun-if-changed={}", path.display());
+            (path, None)
+        }
+    };
+
+    let grammar_src = grammar_root.join("src");
+
+    let parser_file = Some(grammar_src.join("parser.c"))
+        .filter(|s| s.exists())
+        .or_else(|| Some(grammar_src.join("parser.cc")))
+        .filter(|s| s.exists());
+    let scanner_file = Some(grammar_src.join("scanner.c"))
+        .filter(|s| s.exists())
+        .or_else(|| Some(grammar_src.join("scanner.cc")))
+        .filter(|s| s.exists());
+
+    // C++ grammars also need the C++ standard library linking in, which `cc` works out for us,
+    // so they're always compiled rather than being picked up from the cache
+    let build_dir = build_dir.filter(|_| {
+        [&parser_file, &scanner_file]
+            .into_iter()
+            .flatten()
+            .all(|v| v.extension() == Some(OsStr::new("c")))
+    });
+
+    if let Some(build_dir) = &build_dir {
+        fs::create_dir_all(build_dir)?;
     }
 
-    pool.join();
+    for (file, kind) in [(parser_file, "parser"), (scanner_file, "scanner")] {
+        let Some(file) = file else {
+            continue;
+        };
+
+        let mut build = cc::Build::new();
+        build
+            .cpp(file.extension() == Some(OsStr::new("cc")))
+            .file(file)
+            .flag_if_supported("-w")
+            .flag_if_supported("-s")
+            .include(&grammar_src);
+
+        if let Some(build_dir) = &build_dir {
+            build.out_dir(build_dir);
+        }
+
+        build.try_compile(&format!("{}-{kind}", grammar.name))?;
+    }
+
+    if let Some(build_dir) = &build_dir {
+        // marks the build as complete, so an interrupted build is never picked up from the cache
+        fs::write(build_dir.join(".complete"), "")?;
+    }
 
     Ok(())
 }
 
-fn fetch_git_repository(url: &str, ref_: &str, destination: &Path) -> anyhow::Result<()> {
+/// Directory name for a grammar checkout. Revisions are commit hashes in practice, which
+/// identify the content regardless of which remote it was fetched from.
+fn cache_key(name: &str, revision: &str) -> String {
+    format!("{name}-{revision}")
+}
+
+/// Hashes everything that changes the libraries compiled from a grammar's sources. The hash is
+/// only stable for the same Rust toolchain, which at worst means rebuilding after an upgrade.
+fn toolchain_key() -> String {
+    let mut hasher = DefaultHasher::new();
+
+    for var in ["TARGET", "PROFILE", "OPT_LEVEL", "DEBUG"] {
+        std::env::var(var).ok().hash(&mut hasher);
+    }
+
+    // picks up `CC`, `CFLAGS` and friends the same way the actual builds do
+    if let Ok(compiler) = cc::Build::new().try_get_compiler() {
+        compiler.path().hash(&mut hasher);
+        compiler.args().hash(&mut hasher);
+
+        // the path alone doesn't change when the compiler is upgraded in place
+        Command::new(compiler.path())
+            .arg("--version")
+            .output()
+            .ok()
+            .map(|v| v.stdout)
+            .hash(&mut hasher);
+    }
+
+    format!("{:016x}", hasher.finish())
+}
+
+/// Links the libraries for a grammar compiled by a previous build, returning `false` if there
+/// isn't a complete build to reuse.
+fn link_cached_grammar(name: &str, build_dir: &Path) -> bool {
+    if !build_dir.join(".complete").exists() {
+        return false;
+    }
+
+    let msvc = std::env::var("CARGO_CFG_TARGET_ENV").is_ok_and(|v| v == "msvc");
+
+    println!("cargo::rustc-link-search=native={}", build_dir.display());
+
+    for kind in ["parser", "scanner"] {
+        let lib = format!("{name}-{kind}");
+        let file = if msvc {
+            format!("{lib}.lib")
+        } else {
+            format!("lib{lib}.a")
+        };
+
+        if build_dir.join(file).exists() {
+            println!("cargo::rustc-link-lib=static={lib}");
+        }
+    }
+
+    true
+}
+
+/// Fetches `ref_` into `destination`, returning `true` if it was already checked out.
+fn fetch_git_repository(url: &str, ref_: &str, destination: &Path) -> anyhow::Result<bool> {
     if !destination.exists() {
         let res = Command::new("git").arg("init").arg(destination).status()?;
         if !res.success() {
@@ -495,8 +745,8 @@ fn fetch_git_repository(url: &str, ref_: &str, destination: &Path) -> anyhow::Re
         .current_dir(destination)
         .output()?
         .stdout;
-    if res == ref_.as_bytes() {
-        return Ok(());
+    if res.trim_ascii() == ref_.as_bytes() {
+        return Ok(true);
     }
 
     let res = Command::new("git")
@@ -515,6 +765,76 @@ fn fetch_git_repository(url: &str, ref_: &str, destination: &Path) -> anyhow::Re
         bail!("git fetch failed with exit code {res}");
     }
 
+    Ok(false)
+}
+
+/// Pins the exact sources each grammar is built from, one `<name> <revision> <tree hash>` entry
+/// per line. Blank lines and lines starting with `#` are ignored.
+///
+/// The tree hash is that of the whole checkout (`git rev-parse HEAD^{tree}`), so a remote
+/// serving different content for a pinned revision fails the build.
+struct Lockfile(HashMap<String, (String, String)>);
+
+impl Lockfile {
+    fn read(path: &Path) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("failed to read grammar lockfile {}", path.display()))?;
+
+        let mut entries = HashMap::new();
+
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.split_whitespace();
+            let (Some(name), Some(revision), Some(tree), None) =
+                (parts.next(), parts.next(), parts.next(), parts.next())
+            else {
+                bail!(
+                    "{}:{}: expected `<name> <revision> <tree hash>`",
+                    path.display(),
+                    i + 1
+                );
+            };
+
+            entries.insert(name.to_string(), (revision.to_string(), tree.to_string()));
+        }
+
+        Ok(Self(entries))
+    }
+}
+
+/// Checks the checkout at `destination` against the lockfile, if one was given.
+fn verify_checkout(name: &str, revision: &str, destination: &Path) -> anyhow::Result<()> {
+    let Some(Lockfile(lockfile)) = LOCKFILE.get().and_then(Option::as_ref) else {
+        return Ok(());
+    };
+
+    let output = Command::new("git")
+        .args(["rev-parse", "HEAD^{tree}"])
+        .current_dir(destination)
+        .output()?;
+    if !output.status.success() {
+        bail!("git rev-parse failed with exit code {}", output.status);
+    }
+
+    let tree = String::from_utf8(output.stdout).context("git returned invalid tree hash")?;
+    let tree = tree.trim();
+
+    let Some((locked_revision, locked_tree)) = lockfile.get(name) else {
+        bail!("{name} is missing from the grammar lockfile, expected: {name} {revision} {tree}");
+    };
+
+    if locked_revision != revision {
+        bail!("{name} is locked to revision {locked_revision} but {revision} was requested");
+    }
+
+    if locked_tree != tree {
+        bail!("{name} at {revision} has tree {tree}, but the lockfile expects {locked_tree}");
+    }
+
     Ok(())
 }
 
@@ -524,6 +844,8 @@ struct LanguageDefinition {
     name: String,
     injection_regex: Option<String>,
     file_types: Vec<FileType>,
+    #[serde(default)]
+    shebangs: Vec<String>,
     grammar: Option<String>,
 }
 
@@ -27,6 +27,40 @@
 //!   - ...
 //! ```
 //!
+//! By default every grammar known to work is built. The set can be trimmed
+//! to produce a smaller binary using two comma separated environment
+//! variables, which take grammar names as they appear in `languages.toml`:
+//!
+//! - `TREE_SITTER_GRAMMAR_EXCLUDE` leaves the listed grammars out
+//!   (eg. `TREE_SITTER_GRAMMAR_EXCLUDE=gemini,latex`)
+//! - `TREE_SITTER_GRAMMAR_INCLUDE` builds only the listed grammars
+//!   (eg. `TREE_SITTER_GRAMMAR_INCLUDE=rust,toml,markdown,markdown_inline`)
+//!
+//! Grammars that are known to be broken are always left out regardless of
+//! these settings.
+//!
+//! Grammar sources are fetched into `OUT_DIR` by default, so are fetched &
+//! compiled again after a `cargo clean`. Setting `TREE_SITTER_GRAMMAR_CACHE_DIR`
+//! keeps them in a shared directory instead, where each grammar is keyed by
+//! its name & revision and is only fetched & compiled once (eg. for caching
+//! between CI runs).
+//!
+//! When building grammars from source, `TREE_SITTER_GRAMMAR_LOCKFILE` can
+//! point to a lockfile pinning each fetched repository (including `helix`
+//! itself) to a revision and tree hash, one entry per line:
+//!
+//! ```text
+//! # <name> <revision> <tree hash>
+//! rust 1f63b33efee17e833e0ea29266dd3d713e27e321 3b18e512dba79e4c8300dd08aeb37f8e728b8dad
+//! ```
+//!
+//! The build fails if a fetched checkout doesn't match its entry, or if a
+//! grammar has no entry, in which case the expected line is printed.
+//!
+//! A grammar failing to fetch or compile fails the build by default. With
+//! `TREE_SITTER_SKIP_FAILED=1` it's instead left out of the generated
+//! `Grammar` & `Language` enums with a warning.
+//!
 //! Usage:
 //!
 //! ```ignore